@@ -0,0 +1,19 @@
+CREATE TABLE api_keys (
+    id SERIAL PRIMARY KEY,
+    merchant TEXT NOT NULL,
+    key_prefix TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    usage_count BIGINT NOT NULL DEFAULT 0,
+    last_used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    revoked_at TIMESTAMPTZ
+);
+
+CREATE TABLE api_key_campaigns (
+    api_key_id INT NOT NULL,
+    campaign_id INT NOT NULL,
+
+    PRIMARY KEY (api_key_id, campaign_id),
+    FOREIGN KEY (api_key_id) REFERENCES api_keys (id) ON DELETE CASCADE,
+    FOREIGN KEY (campaign_id) REFERENCES campaigns (id) ON DELETE RESTRICT
+);
@@ -0,0 +1,14 @@
+-- Users are soft-deleted (and optionally anonymized) so that their draws and coupons are retained
+ALTER TABLE users
+    ADD COLUMN deleted_at TIMESTAMPTZ,
+    ADD COLUMN anonymized_at TIMESTAMPTZ,
+    ALTER COLUMN phone DROP NOT NULL,
+    ADD CHECK (anonymized_at is null or (phone is null and deleted_at is not null));
+
+-- A phone number only has to be unique among active users, so that a deleted user can sign up again
+ALTER TABLE users DROP CONSTRAINT users_phone_key;
+CREATE UNIQUE INDEX users_phone_key ON users (phone) WHERE deleted_at is null;
+
+-- `ON DELETE SET NULL` contradicts `user_id NOT NULL`; users with draws must never be hard-deleted
+ALTER TABLE draws DROP CONSTRAINT draws_user_id_fkey;
+ALTER TABLE draws ADD FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE RESTRICT;
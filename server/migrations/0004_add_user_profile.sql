@@ -0,0 +1,7 @@
+ALTER TABLE users
+    ADD COLUMN name TEXT,
+    ADD COLUMN email TEXT,
+    ADD COLUMN locale TEXT,
+    ADD COLUMN marketing_opt_in BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN marketing_opt_in_at TIMESTAMPTZ,
+    ADD CHECK (marketing_opt_in = (marketing_opt_in_at is not null));
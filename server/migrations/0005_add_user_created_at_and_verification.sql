@@ -0,0 +1,20 @@
+-- Users who already exist are backfilled with the time of their first draw, or the time of this
+-- migration if they never drew. The column starts out null, which marks exactly the rows to
+-- backfill, and only gets its default and NOT NULL once they are
+ALTER TABLE users
+    ADD COLUMN created_at TIMESTAMPTZ,
+    ADD COLUMN phone_verified_at TIMESTAMPTZ;
+
+UPDATE users
+SET created_at = coalesce(
+    (SELECT min(date)::timestamp AT TIME ZONE 'UTC' FROM draws WHERE draws.user_id = users.id),
+    now()
+)
+WHERE created_at is null;
+
+ALTER TABLE users
+    ALTER COLUMN created_at SET DEFAULT NOW(),
+    ALTER COLUMN created_at SET NOT NULL;
+
+CREATE INDEX users_created_at_idx ON users (created_at);
+CREATE INDEX draws_campaign_id_user_id_idx ON draws (campaign_id, user_id);
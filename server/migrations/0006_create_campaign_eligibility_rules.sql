@@ -0,0 +1,17 @@
+ALTER TABLE users ADD COLUMN membership_tier TEXT;
+
+CREATE TABLE campaign_eligibility_rules (
+    campaign_id INT PRIMARY KEY,
+    min_account_age_days INT,
+    max_account_age_days INT,
+    phone_prefixes TEXT[],
+    membership_tiers TEXT[],
+    require_verified_phone BOOLEAN NOT NULL DEFAULT FALSE,
+    allowed_user_ids INT[],
+    denied_user_ids INT[],
+
+    FOREIGN KEY (campaign_id) REFERENCES campaigns (id) ON DELETE CASCADE,
+    CHECK (min_account_age_days is null or min_account_age_days >= 0),
+    CHECK (max_account_age_days is null or max_account_age_days >= 0),
+    CHECK (min_account_age_days is null or max_account_age_days is null or min_account_age_days <= max_account_age_days)
+);
@@ -0,0 +1,20 @@
+-- A user can only draw once a day per campaign. The cache only short-circuits repeated draws, this
+-- is what actually enforces it, e.g. when the cache is unavailable or two draws race
+
+-- Repeated draws made before then are removed first, so that the index can be created. The draw
+-- kept is the first that won a coupon, or the first if none did. Coupons of the draws removed stay
+-- valid and redeemable, only the draw that issued them is gone
+DELETE FROM draws
+WHERE id IN (
+    SELECT id
+    FROM (
+        SELECT id, row_number() OVER (
+            PARTITION BY user_id, campaign_id, date
+            ORDER BY campaign_coupon_id is null, id
+        ) AS rank
+        FROM draws
+    ) AS ranked
+    WHERE rank > 1
+);
+
+CREATE UNIQUE INDEX draws_user_id_campaign_id_date_key ON draws (user_id, campaign_id, date);
@@ -0,0 +1,19 @@
+-- Responses of requests made with an Idempotency-Key header, replayed when the request is retried.
+-- A key without a response is claimed by a request still in progress
+CREATE TABLE idempotency_keys (
+    -- Keys are only unique per caller, so that callers who happen to pick the same key don't
+    -- collide: the API key of the caller, or the route and the user the request is made for
+    scope TEXT NOT NULL,
+    key TEXT NOT NULL,
+    -- Hash of the method, path, caller and body of the request, so that a key can't be reused for a
+    -- different request
+    fingerprint TEXT NOT NULL,
+    response_status SMALLINT,
+    response_content_type TEXT,
+    response_body BYTEA,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (scope, key)
+);
+
+CREATE INDEX idempotency_keys_expires_at_idx ON idempotency_keys (expires_at);
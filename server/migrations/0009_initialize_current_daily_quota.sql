@@ -0,0 +1,5 @@
+-- The daily quota left of coupon types that were never drawn from was never initialized. It is
+-- reset on the first draw of every day anyway, this only makes it read right until then
+UPDATE campaign_coupon_types
+SET current_daily_quota = daily_quota
+WHERE last_drawn_date IS NULL;
@@ -0,0 +1,12 @@
+CREATE TABLE campaign_prize_rules (
+    campaign_id INT PRIMARY KEY,
+    when_exhausted TEXT NOT NULL DEFAULT 'no_prize',
+
+    FOREIGN KEY (campaign_id) REFERENCES campaigns (id) ON DELETE CASCADE,
+    CHECK (when_exhausted in ('no_prize', 'renormalize', 'fallback'))
+);
+
+-- Issued instead of an exhausted coupon type, with `when_exhausted = 'fallback'`
+ALTER TABLE campaign_coupon_types ADD COLUMN fallback BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE UNIQUE INDEX campaign_coupon_types_fallback_key ON campaign_coupon_types (campaign_id) WHERE fallback;
@@ -0,0 +1,7 @@
+-- A user is guaranteed a coupon after this many draws in a row without one
+ALTER TABLE campaign_prize_rules ADD COLUMN guaranteed_win_after INT CHECK (guaranteed_win_after >= 1);
+
+-- Guaranteed to be issued on a user's first draw from the campaign, as long as it has quota left
+ALTER TABLE campaign_coupon_types ADD COLUMN first_draw BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE UNIQUE INDEX campaign_coupon_types_first_draw_key ON campaign_coupon_types (campaign_id) WHERE first_draw;
@@ -0,0 +1,3 @@
+-- Spread the daily quotas of coupon types over the day, rather than handing them out first come
+-- first served
+ALTER TABLE campaign_prize_rules ADD COLUMN pacing BOOLEAN NOT NULL DEFAULT FALSE;
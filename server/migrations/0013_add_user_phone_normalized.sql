@@ -0,0 +1,6 @@
+-- Phone lookups ignore spaces, dashes and brackets. Normalizing on write lets them use an index
+-- instead of normalizing every row on every lookup
+ALTER TABLE users
+    ADD COLUMN phone_normalized TEXT GENERATED ALWAYS AS (regexp_replace(phone, '[\s()-]', '', 'g')) STORED;
+
+CREATE INDEX users_phone_normalized_idx ON users (phone_normalized) WHERE deleted_at is null;
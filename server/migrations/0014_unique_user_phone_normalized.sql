@@ -0,0 +1,21 @@
+-- Lookups and dedupe go by the normalized phone number, so that is what has to be unique among
+-- active users. Otherwise '+852 1234 5678' and '+85212345678' could be registered as two users.
+-- This replaces the index on the raw phone number, which the normalized one implies
+DO $$
+BEGIN
+    IF EXISTS (
+        SELECT phone_normalized
+        FROM users
+        WHERE deleted_at IS NULL AND phone_normalized IS NOT NULL
+        GROUP BY phone_normalized
+        HAVING count(*) > 1
+    ) THEN
+        -- Which of the accounts to keep is up to the operator, so they aren't merged here
+        RAISE EXCEPTION 'Active users share a phone number once normalized. Delete or merge them first';
+    END IF;
+END
+$$;
+
+DROP INDEX users_phone_key;
+DROP INDEX users_phone_normalized_idx;
+CREATE UNIQUE INDEX users_phone_normalized_key ON users (phone_normalized) WHERE deleted_at is null;
@@ -0,0 +1,3 @@
+-- Phone numbers are normalized by the server from now on, the same way eligibility rules compare
+-- them, instead of by a second implementation in SQL
+ALTER TABLE users ALTER COLUMN phone_normalized DROP EXPRESSION;
@@ -0,0 +1,54 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, Request},
+    middleware::Next,
+    response::Response,
+};
+use sha2::{Digest, Sha256};
+
+use crate::error::{AppError, ErrorCode};
+use crate::store::Store;
+
+pub const ADMIN_TOKEN_HEADER: &str = "x-admin-token";
+
+/// Rejects requests without the configured admin token, and every request if none is configured
+pub(super) async fn require_admin<B>(
+    State(store): State<Store>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, AppError> {
+    authorize(&store, request.headers())?;
+
+    Ok(next.run(request).await)
+}
+
+/// The check of [`require_admin`], for handlers that only need the admin token for some requests
+pub(super) fn authorize(store: &Store, headers: &HeaderMap) -> Result<(), AppError> {
+    let Some(token) = headers
+        .get(ADMIN_TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return Err(AppError::new(
+            ErrorCode::AdminTokenMissing,
+            format!("Missing {ADMIN_TOKEN_HEADER} header"),
+        ));
+    };
+
+    // Comparing digests rather than the tokens themselves doesn't leak how much of the token
+    // matched through the time taken
+    let authorized = store
+        .config
+        .admin
+        .token
+        .as_ref()
+        .is_some_and(|expected| Sha256::digest(expected) == Sha256::digest(token));
+
+    if authorized {
+        Ok(())
+    } else {
+        Err(AppError::new(
+            ErrorCode::AdminTokenInvalid,
+            "Admin token is invalid",
+        ))
+    }
+}
@@ -0,0 +1,204 @@
+use axum::{
+    extract::State,
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use hyper::StatusCode;
+use rand::distributions::{Alphanumeric, DistString};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::error::{AppError, ErrorCode};
+use crate::extract::{Json, Path, Query};
+use crate::repository::{ApiKeyRepository, NewApiKey, PgRepository};
+use crate::store::Store;
+use crate::types::ApiKey;
+
+mod test;
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+const API_KEY_PREFIX: &str = "ldk_";
+
+/// The API key that authenticated the current request, inserted into the
+/// request extensions by [`require_api_key`]
+#[derive(Clone)]
+pub struct AuthorizedApiKey {
+    pub id: i32,
+}
+
+fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+pub(super) async fn require_api_key<B>(
+    State(store): State<Store>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, AppError> {
+    let key = match request
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())
+    {
+        Some(key) => key.to_string(),
+        None => {
+            return Err(AppError::new(
+                ErrorCode::ApiKeyMissing,
+                format!("Missing {API_KEY_HEADER} header"),
+            ))
+        }
+    };
+
+    let repository = PgRepository::new(store.db_pool.clone(), store.metrics.clone());
+
+    let authorized = repository
+        .authorize(&hash_key(&key))
+        .await?
+        .map(|id| AuthorizedApiKey { id });
+
+    match authorized {
+        Some(authorized) => {
+            request.extensions_mut().insert(authorized);
+            Ok(next.run(request).await)
+        }
+        None => Err(AppError::new(
+            ErrorCode::ApiKeyInvalid,
+            "API key is invalid or has been revoked",
+        )),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+pub(super) struct ListApiKeysQuery {
+    /// Only list the API keys of this merchant
+    pub merchant: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api-key",
+    params(ListApiKeysQuery),
+    responses(
+        (status = 200, description = "List API keys successfully", body = [ApiKey]),
+        (status = 401, description = "Admin token is missing or invalid", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+        (status = 503, description = "Database temporarily unavailable", body = ErrorBody)
+    ),
+    security(
+        ("admin_token" = [])
+    )
+)]
+pub(super) async fn list_api_keys(
+    State(store): State<Store>,
+    Query(query): Query<ListApiKeysQuery>,
+) -> Result<Json<Vec<ApiKey>>, AppError> {
+    let repository = PgRepository::new(store.db_pool.clone(), store.metrics.clone());
+
+    let api_keys = repository.list(query.merchant.as_deref()).await?;
+
+    Ok(Json(api_keys))
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub(super) struct CreateApiKeyPayload {
+    #[schema(example = "merchant-1")]
+    pub merchant: String,
+    pub campaign_ids: Vec<i32>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub(super) struct CreateApiKeyResult {
+    /// The plaintext key. Only the hash is stored so this is the only time it is shown
+    #[schema(example = "ldk_Xq3fTz9Lr2mB0pWc7sKd1vYh8nGa4eJu")]
+    pub key: String,
+    pub api_key: ApiKey,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api-key",
+    request_body = CreateApiKeyPayload,
+    responses(
+        (status = 201, description = "API key created successfully", body = CreateApiKeyResult),
+        (status = 401, description = "Admin token is missing or invalid", body = ErrorBody),
+        (status = 404, description = "One or more campaigns don't exist", body = ErrorBody),
+        (status = 409, description = "API key isn't scoped to any campaign", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+        (status = 503, description = "Database temporarily unavailable", body = ErrorBody)
+    ),
+    security(
+        ("admin_token" = [])
+    )
+)]
+pub(super) async fn create_api_key(
+    State(store): State<Store>,
+    Json(mut payload): Json<CreateApiKeyPayload>,
+) -> Result<Response, AppError> {
+    let repository = PgRepository::new(store.db_pool.clone(), store.metrics.clone());
+
+    payload.campaign_ids.sort_unstable();
+    payload.campaign_ids.dedup();
+
+    if payload.campaign_ids.is_empty() {
+        return Err(AppError::new(
+            ErrorCode::ApiKeyUnscoped,
+            "API key must be scoped to at least one campaign",
+        ));
+    }
+
+    let key = format!(
+        "{API_KEY_PREFIX}{}",
+        Alphanumeric.sample_string(&mut rand::thread_rng(), 32)
+    );
+
+    let api_key = repository
+        .create(&NewApiKey {
+            merchant: payload.merchant,
+            key_prefix: key[..API_KEY_PREFIX.len() + 4].to_string(),
+            key_hash: hash_key(&key),
+            campaign_ids: payload.campaign_ids,
+        })
+        .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreateApiKeyResult { key, api_key }),
+    )
+        .into_response())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api-key/{id}",
+    responses(
+        (status = 200, description = "Revoke API key successfully"),
+        (status = 401, description = "Admin token is missing or invalid", body = ErrorBody),
+        (status = 404, description = "API key not found or already revoked", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+        (status = 503, description = "Database temporarily unavailable", body = ErrorBody)
+    ),
+    params(
+        ("id" = i32, Path, description = "API key id")
+    ),
+    security(
+        ("admin_token" = [])
+    )
+)]
+pub(super) async fn revoke_api_key(
+    Path(id): Path<i32>,
+    State(store): State<Store>,
+) -> Result<Response, AppError> {
+    let repository = PgRepository::new(store.db_pool.clone(), store.metrics.clone());
+
+    if repository.revoke(id).await? {
+        Ok(StatusCode::OK.into_response())
+    } else {
+        Err(AppError::new(
+            ErrorCode::ApiKeyNotFound,
+            format!("API key with ID {id} doesn't exist or has already been revoked"),
+        ))
+    }
+}
@@ -0,0 +1,321 @@
+#[cfg(test)]
+mod tests {
+    use crate::{
+        admin::ADMIN_TOKEN_HEADER,
+        api_key::API_KEY_HEADER,
+        cache::CacheBackend,
+        rate_limit::RateLimitBackend,
+        testing::{post_json, TestContext, ADMIN_TOKEN},
+    };
+
+    use axum::{
+        body::Body,
+        http::{Method, Request, StatusCode},
+    };
+    use serde_json::json;
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    const ADMIN: (&str, &str) = (ADMIN_TOKEN_HEADER, ADMIN_TOKEN);
+
+    #[tokio::test]
+    async fn create_api_key_fail_if_scope_invalid() {
+        let ctx = TestContext::new().await;
+        let app = ctx.app.clone();
+
+        let (status, _, body) = post_json(
+            &app,
+            "/api-key",
+            &[ADMIN],
+            json!({ "merchant": "merchant-1", "campaign_ids": [] }),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["code"], "api_key_unscoped");
+        assert_eq!(
+            body["message"],
+            "API key must be scoped to at least one campaign"
+        );
+
+        let (status, _, _) = post_json(
+            &app,
+            "/api-key",
+            &[ADMIN],
+            json!({ "merchant": "merchant-1", "campaign_ids": [999999] }),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn managing_api_keys_requires_the_admin_token() {
+        let ctx = TestContext::new().await;
+        let app = ctx.app.clone();
+
+        let (status, _, campaign) = post_json(
+            &app,
+            "/campaign",
+            &[],
+            json!({ "coupon_types": [{ "description": "100%", "probability": 1.0, "total_quota": null, "daily_quota": null }] }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let payload = json!({ "merchant": "merchant-1", "campaign_ids": [campaign["id"]] });
+
+        let (status, _, body) = post_json(&app, "/api-key", &[], payload.clone()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["code"], "admin_token_missing");
+
+        let (status, _, body) = post_json(
+            &app,
+            "/api-key",
+            &[(ADMIN_TOKEN_HEADER, "not-the-admin-token")],
+            payload.clone(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["code"], "admin_token_invalid");
+
+        // A merchant's own key doesn't grant admin access either
+
+        let (status, _, created) = post_json(&app, "/api-key", &[ADMIN], payload.clone()).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let api_key = created["key"].as_str().unwrap();
+
+        let (status, _, _) = post_json(
+            &app,
+            "/api-key",
+            &[(API_KEY_HEADER, api_key), (ADMIN_TOKEN_HEADER, api_key)],
+            payload,
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        for (method, uri) in [(Method::GET, "/api-key"), (Method::DELETE, "/api-key/1")] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .uri(uri)
+                        .method(method)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[tokio::test]
+    async fn admin_endpoints_are_closed_without_an_admin_token() {
+        let ctx = TestContext::with_config(|config| {
+            config.cache.backend = CacheBackend::Memory;
+            config.rate_limit.backend = RateLimitBackend::Memory;
+            config.admin.token = None;
+        })
+        .await;
+
+        let (status, _, body) = post_json(
+            &ctx.app,
+            "/api-key",
+            &[(ADMIN_TOKEN_HEADER, "")],
+            json!({ "merchant": "merchant-1", "campaign_ids": [1] }),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["code"], "admin_token_invalid");
+    }
+
+    #[tokio::test]
+    async fn redeem_with_scoped_api_key() {
+        let ctx = TestContext::new().await;
+        let app = ctx.app.clone();
+
+        let coupon_type = json!({
+            "description": "100%",
+            "probability": 1.0,
+            "total_quota": null,
+            "daily_quota": null
+        });
+
+        let (status, _, campaign) = post_json(
+            &app,
+            "/campaign",
+            &[],
+            json!({ "coupon_types": [coupon_type] }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let campaign_id = campaign["id"].as_i64().unwrap();
+
+        let (status, _, other_campaign) = post_json(
+            &app,
+            "/campaign",
+            &[],
+            json!({ "coupon_types": [coupon_type] }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let other_campaign_id = other_campaign["id"].as_i64().unwrap();
+
+        let (status, _, user) = post_json(
+            &app,
+            "/user",
+            &[],
+            json!({ "phone": &Uuid::new_v4().to_string()[..20] }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let user_id = user["id"].as_i64().unwrap();
+
+        let (status, _, draw) = post_json(
+            &app,
+            "/draw",
+            &[],
+            json!({ "campaign_id": campaign_id, "user_id": user_id }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let coupon_id = draw["maybe_coupon"]["id"].as_i64().unwrap();
+
+        // Create one key for the campaign and another one for an unrelated campaign
+
+        let merchant = Uuid::new_v4().to_string();
+
+        let (status, _, created) = post_json(
+            &app,
+            "/api-key",
+            &[ADMIN],
+            json!({ "merchant": merchant, "campaign_ids": [campaign_id] }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let api_key = created["key"].as_str().unwrap().to_string();
+        let api_key_id = created["api_key"]["id"].as_i64().unwrap();
+        assert!(api_key.starts_with(created["api_key"]["key_prefix"].as_str().unwrap()));
+
+        let (status, _, created) = post_json(
+            &app,
+            "/api-key",
+            &[ADMIN],
+            json!({ "merchant": merchant, "campaign_ids": [other_campaign_id] }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let other_api_key = created["key"].as_str().unwrap().to_string();
+
+        // Redeeming without a valid key is rejected by the middleware
+
+        let redeem_payload = json!({ "coupon_id": coupon_id, "user_id": user_id });
+
+        let (status, _, _) = post_json(&app, "/redeem", &[], redeem_payload.clone()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, _, _) = post_json(
+            &app,
+            "/redeem",
+            &[(API_KEY_HEADER, "ldk_not-a-real-key")],
+            redeem_payload.clone(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // A key scoped to another campaign can't redeem the coupon
+
+        let (status, _, _) = post_json(
+            &app,
+            "/redeem",
+            &[(API_KEY_HEADER, &other_api_key)],
+            redeem_payload.clone(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let (status, _, _) = post_json(
+            &app,
+            "/redeem",
+            &[(API_KEY_HEADER, &api_key)],
+            redeem_payload.clone(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Check if usage counters are tracked per key
+
+        let list_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/api-key?merchant={}", merchant))
+                    .header(ADMIN_TOKEN_HEADER, ADMIN_TOKEN)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(list_response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(list_response.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        let api_keys = body.as_array().unwrap();
+        assert_eq!(api_keys.len(), 2);
+        assert_eq!(api_keys[0]["campaign_ids"], json!([campaign_id]));
+        assert_eq!(api_keys[0]["usage_count"], 1);
+        assert!(api_keys[0]["last_used_at"].is_string());
+        assert_eq!(api_keys[1]["usage_count"], 1);
+        assert!(api_keys
+            .iter()
+            .all(|k| k.get("key_hash").is_none() && k.get("key").is_none()));
+
+        // Revoked keys are rejected
+
+        let revoke_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/api-key/{}", api_key_id))
+                    .method(Method::DELETE)
+                    .header(ADMIN_TOKEN_HEADER, ADMIN_TOKEN)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(revoke_response.status(), StatusCode::OK);
+
+        let (status, _, _) = post_json(
+            &app,
+            "/redeem",
+            &[(API_KEY_HEADER, &api_key)],
+            redeem_payload,
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let revoke_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/api-key/{}", api_key_id))
+                    .method(Method::DELETE)
+                    .header(ADMIN_TOKEN_HEADER, ADMIN_TOKEN)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(revoke_response.status(), StatusCode::NOT_FOUND);
+    }
+}
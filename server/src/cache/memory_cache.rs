@@ -0,0 +1,68 @@
+use async_trait::async_trait;
+
+use super::{Cache, CacheHealthStats};
+use crate::config::CacheConfig;
+
+/// Keeps the caches in the memory of the process, so it needs no Redis but isn't shared between
+/// nodes. Once `max_entries` is reached, moka's TinyLFU policy decides which entries are admitted
+/// and which evicted, keeping those used most often
+pub struct MemoryCache {
+    enrolled_campaigns: moka::future::Cache<(i32, chrono::NaiveDate), Vec<i32>>,
+    probability_distributions: moka::future::Cache<i32, Vec<(i32, f32)>>,
+}
+
+impl MemoryCache {
+    pub fn new(config: &CacheConfig) -> Self {
+        Self {
+            enrolled_campaigns: moka::future::Cache::builder()
+                .max_capacity(config.max_entries)
+                .time_to_live(config.enrolled_campaigns_ttl())
+                .build(),
+            probability_distributions: moka::future::Cache::builder()
+                .max_capacity(config.max_entries)
+                .time_to_live(config.probability_distribution_ttl())
+                .build(),
+        }
+    }
+}
+
+impl Default for MemoryCache {
+    fn default() -> Self {
+        Self::new(&CacheConfig::default())
+    }
+}
+
+#[async_trait]
+impl Cache for MemoryCache {
+    async fn enrolled_campaigns(&self, user_id: i32, date: chrono::NaiveDate) -> Vec<i32> {
+        self.enrolled_campaigns
+            .get(&(user_id, date))
+            .await
+            .unwrap_or_default()
+    }
+
+    async fn add_enrolled_campaign(&self, user_id: i32, date: chrono::NaiveDate, campaign_id: i32) {
+        self.enrolled_campaigns
+            .entry((user_id, date))
+            .and_upsert_with(|entry| async move {
+                let mut campaign_ids = entry.map(|e| e.into_value()).unwrap_or_default();
+                campaign_ids.push(campaign_id);
+                campaign_ids
+            })
+            .await;
+    }
+
+    async fn probability_distribution(&self, campaign_id: i32) -> Option<Vec<(i32, f32)>> {
+        self.probability_distributions.get(&campaign_id).await
+    }
+
+    async fn set_probability_distribution(&self, campaign_id: i32, distribution: &[(i32, f32)]) {
+        self.probability_distributions
+            .insert(campaign_id, distribution.to_vec())
+            .await;
+    }
+
+    fn health(&self) -> CacheHealthStats {
+        CacheHealthStats::default()
+    }
+}
@@ -0,0 +1,61 @@
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+
+mod memory_cache;
+mod redis_cache;
+mod test;
+
+pub use memory_cache::MemoryCache;
+pub use redis_cache::RedisCache;
+
+/// Which [`Cache`] implementation the service runs with
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheBackend {
+    /// Shared by every node, see [`RedisCache`]
+    Redis,
+    /// Local to the process, for single-node deployments and tests, see [`MemoryCache`]
+    Memory,
+}
+
+impl FromStr for CacheBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "redis" => Ok(Self::Redis),
+            "memory" => Ok(Self::Memory),
+            _ => Err("expected redis or memory".to_string()),
+        }
+    }
+}
+
+/// Counters of how often the cache has degraded to the DB
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CacheHealthStats {
+    pub degraded: bool,
+    /// Number of operations that failed
+    pub errors: u64,
+    /// Number of operations skipped because the cache failed recently
+    pub bypassed: u64,
+}
+
+/// The caches of the draw hot path. The DB is the source of truth, so a cache only saves DB
+/// round trips and never fails a request: a read that can't be served is a miss, and a write that
+/// can't be made is dropped
+#[async_trait]
+pub trait Cache: Send + Sync {
+    /// The campaigns the user is known to have drawn from on the date. Empty on a cache miss
+    async fn enrolled_campaigns(&self, user_id: i32, date: chrono::NaiveDate) -> Vec<i32>;
+
+    async fn add_enrolled_campaign(&self, user_id: i32, date: chrono::NaiveDate, campaign_id: i32);
+
+    /// The `(coupon type id, probability)` pairs of the campaign. `None` on a cache miss
+    async fn probability_distribution(&self, campaign_id: i32) -> Option<Vec<(i32, f32)>>;
+
+    async fn set_probability_distribution(&self, campaign_id: i32, distribution: &[(i32, f32)]);
+
+    fn health(&self) -> CacheHealthStats;
+}
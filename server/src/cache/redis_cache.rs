@@ -0,0 +1,226 @@
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use redis::AsyncCommands;
+
+use super::{Cache, CacheHealthStats};
+use crate::config::CacheConfig;
+
+/// How long Redis is bypassed after an error before it is tried again
+const RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// Shares the caches between every node of the service.
+///
+/// Redis is only an optimization: the DB is the source of truth. When Redis is unavailable every
+/// read degrades to a cache miss and every write to a no-op, so that callers fall back to the DB.
+/// Redis is bypassed for [`RETRY_AFTER`] after an error and then tried again, so the cache
+/// recovers by itself once Redis is back
+pub struct RedisCache {
+    redis_pool: deadpool_redis::Pool,
+    key_prefix: String,
+    enrolled_campaigns_ttl: Duration,
+    probability_distribution_ttl: Duration,
+    pub(super) health: CacheHealth,
+}
+
+pub(super) struct CacheHealth {
+    created_at: Instant,
+    /// Milliseconds since `created_at` until which Redis is bypassed
+    pub(super) bypass_until: AtomicU64,
+    degraded: AtomicBool,
+    errors: AtomicU64,
+    bypassed: AtomicU64,
+}
+
+/// Parses a `<coupon type id>:<probability>` entry of the probability distribution cache
+fn parse_probability_distribution_entry(entry: &str) -> Option<(i32, f32)> {
+    let (id, probability) = entry.split_once(':')?;
+
+    Some((id.parse().ok()?, probability.parse().ok()?))
+}
+
+impl RedisCache {
+    pub fn new(
+        redis_pool: deadpool_redis::Pool,
+        key_prefix: impl Into<String>,
+        config: &CacheConfig,
+    ) -> Self {
+        Self {
+            redis_pool,
+            key_prefix: key_prefix.into(),
+            enrolled_campaigns_ttl: config.enrolled_campaigns_ttl(),
+            probability_distribution_ttl: config.probability_distribution_ttl(),
+            health: CacheHealth {
+                created_at: Instant::now(),
+                bypass_until: AtomicU64::new(0),
+                degraded: AtomicBool::new(false),
+                errors: AtomicU64::new(0),
+                bypassed: AtomicU64::new(0),
+            },
+        }
+    }
+
+    fn enrolled_campaigns_key(&self, user_id: i32, date: chrono::NaiveDate) -> String {
+        format!(
+            "{}user-{user_id}:enrolled-campaigns:{date}",
+            self.key_prefix
+        )
+    }
+
+    fn probability_distribution_key(&self, campaign_id: i32) -> String {
+        format!("{}campaign-{campaign_id}:prob-dist", self.key_prefix)
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        self.health.created_at.elapsed().as_millis() as u64
+    }
+
+    /// Takes a connection unless Redis failed too recently
+    async fn connection(&self) -> Option<deadpool_redis::Connection> {
+        if self.elapsed_ms() < self.health.bypass_until.load(Ordering::Relaxed) {
+            self.health.bypassed.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        match self.redis_pool.get().await {
+            Ok(connection) => Some(connection),
+            Err(e) => {
+                self.degrade(e);
+                None
+            }
+        }
+    }
+
+    pub(super) fn degrade(&self, error: impl Display) {
+        self.health.errors.fetch_add(1, Ordering::Relaxed);
+        self.health.bypass_until.store(
+            self.elapsed_ms() + RETRY_AFTER.as_millis() as u64,
+            Ordering::Relaxed,
+        );
+
+        if !self.health.degraded.swap(true, Ordering::Relaxed) {
+            tracing::warn!(%error, "Redis is unavailable, falling back to the DB");
+        }
+    }
+
+    fn recover(&self) {
+        if self.health.degraded.swap(false, Ordering::Relaxed) {
+            tracing::info!("Redis is available again");
+        }
+    }
+
+    /// Unwraps the result of a Redis command, degrading on errors
+    fn check<T>(&self, result: redis::RedisResult<T>) -> Option<T> {
+        match result {
+            Ok(value) => {
+                self.recover();
+                Some(value)
+            }
+            Err(e) => {
+                self.degrade(e);
+                None
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Cache for RedisCache {
+    async fn enrolled_campaigns(&self, user_id: i32, date: chrono::NaiveDate) -> Vec<i32> {
+        let Some(mut redis) = self.connection().await else {
+            return vec![];
+        };
+
+        let result: redis::RedisResult<Vec<String>> = redis
+            .lrange(self.enrolled_campaigns_key(user_id, date), 0, -1)
+            .await;
+
+        self.check(result)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|campaign_id| campaign_id.parse().ok())
+            .collect()
+    }
+
+    async fn add_enrolled_campaign(&self, user_id: i32, date: chrono::NaiveDate, campaign_id: i32) {
+        let Some(mut redis) = self.connection().await else {
+            return;
+        };
+
+        let key = self.enrolled_campaigns_key(user_id, date);
+
+        let result: redis::RedisResult<()> = redis::pipe()
+            .rpush(&key, campaign_id.to_string())
+            .ignore()
+            .expire(&key, self.enrolled_campaigns_ttl.as_secs() as usize)
+            .ignore()
+            .query_async(&mut redis)
+            .await;
+
+        self.check(result);
+    }
+
+    async fn probability_distribution(&self, campaign_id: i32) -> Option<Vec<(i32, f32)>> {
+        let mut redis = self.connection().await?;
+
+        let result: redis::RedisResult<Vec<String>> = redis
+            .lrange(self.probability_distribution_key(campaign_id), 0, -1)
+            .await;
+
+        let entries = self.check(result)?;
+
+        if entries.is_empty() {
+            return None;
+        }
+
+        // A malformed entry is treated as a miss so that the distribution is read from the DB
+        let distribution: Option<Vec<_>> = entries
+            .iter()
+            .map(|entry| parse_probability_distribution_entry(entry))
+            .collect();
+
+        if distribution.is_none() {
+            tracing::warn!(campaign_id, "Malformed probability distribution cache");
+        }
+
+        distribution
+    }
+
+    async fn set_probability_distribution(&self, campaign_id: i32, distribution: &[(i32, f32)]) {
+        let Some(mut redis) = self.connection().await else {
+            return;
+        };
+
+        let entries: Vec<String> = distribution
+            .iter()
+            .map(|(id, probability)| format!("{id}:{probability}"))
+            .collect();
+
+        let key = self.probability_distribution_key(campaign_id);
+
+        // Replaces the list in one transaction, so that concurrent misses of the cache don't each
+        // append the distribution to it
+        let result: redis::RedisResult<()> = redis::pipe()
+            .atomic()
+            .del(&key)
+            .ignore()
+            .rpush(&key, entries)
+            .ignore()
+            .expire(&key, self.probability_distribution_ttl.as_secs() as usize)
+            .ignore()
+            .query_async(&mut redis)
+            .await;
+
+        self.check(result);
+    }
+
+    fn health(&self) -> CacheHealthStats {
+        CacheHealthStats {
+            degraded: self.health.degraded.load(Ordering::Relaxed),
+            errors: self.health.errors.load(Ordering::Relaxed),
+            bypassed: self.health.bypassed.load(Ordering::Relaxed),
+        }
+    }
+}
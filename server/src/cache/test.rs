@@ -0,0 +1,221 @@
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use crate::{
+        cache::{Cache, CacheBackend, MemoryCache, RedisCache},
+        testing::TestContext,
+    };
+
+    use axum::{
+        body::Body,
+        http::{self, Method, Request, StatusCode},
+    };
+    use serde_json::json;
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    /// Nothing listens on port 1, so every connection is refused
+    const UNREACHABLE_REDIS_URL: &str = "redis://127.0.0.1:1/";
+
+    #[tokio::test]
+    async fn draw_falls_back_to_db_when_redis_is_unreachable() {
+        let ctx = TestContext::with_config(|config| {
+            config.cache.backend = CacheBackend::Redis;
+            config.redis.url = Some(UNREACHABLE_REDIS_URL.to_string());
+            config.features.pool_stats = true;
+        })
+        .await;
+        let store = &ctx.store;
+        let app = ctx.app.clone();
+
+        let create_campaign_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/campaign")
+                    .method(Method::POST)
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(
+                        serde_json::to_string(&json!({
+                            "coupon_types": [{
+                                "description": "50%",
+                                "probability": 0.5,
+                                "total_quota": null,
+                                "daily_quota": null
+                            }]
+                        }))
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let body = hyper::body::to_bytes(create_campaign_response.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        let campaign_id = body["id"].as_i64().unwrap();
+
+        let user_id: i32 = sqlx::query_scalar!(
+            "--sql
+                insert into users (phone)
+                values ($1)
+                returning id;
+            ",
+            Uuid::new_v4().to_string()[..20].to_owned()
+        )
+        .fetch_one(&store.db_pool)
+        .await
+        .unwrap();
+
+        let draw = || {
+            app.clone().oneshot(
+                Request::builder()
+                    .uri("/draw")
+                    .method(Method::POST)
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(
+                        serde_json::to_string(&json!({
+                            "campaign_id": campaign_id,
+                            "user_id": user_id
+                        }))
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+        };
+
+        let draw_response = draw().await.unwrap();
+
+        assert_eq!(draw_response.status(), StatusCode::OK);
+
+        // Without the cache, the DB still stops the user from drawing twice
+
+        let draw_response = draw().await.unwrap();
+
+        assert_eq!(draw_response.status(), StatusCode::CONFLICT);
+
+        let body = hyper::body::to_bytes(draw_response.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(body["code"], "already_drawn");
+
+        let health = store.cache.health();
+
+        assert!(health.degraded);
+        assert!(health.errors >= 1);
+        // Redis isn't retried on every operation while it is down
+        assert!(health.bypassed >= 1);
+
+        let stats_response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/stats/pools")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let body = hyper::body::to_bytes(stats_response.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(body["cache"]["degraded"], true);
+    }
+
+    #[tokio::test]
+    async fn cache_recovers_once_redis_is_back() {
+        let ctx = TestContext::with_redis().await;
+        let cache = RedisCache::new(
+            ctx.store.redis_pool.clone().unwrap(),
+            format!("{}:", ctx.db_name),
+            &ctx.store.config.cache,
+        );
+
+        cache.degrade("Connection refused");
+
+        assert!(cache.health().degraded);
+        assert_eq!(cache.probability_distribution(-1).await, None);
+        assert_eq!(cache.health().bypassed, 1);
+
+        // Pretend the cooldown has passed
+
+        cache.health.bypass_until.store(0, Ordering::Relaxed);
+
+        let user_id = -1;
+        let today_date = chrono::Utc::now().naive_utc().date();
+
+        cache.add_enrolled_campaign(user_id, today_date, 1).await;
+
+        assert!(!cache.health().degraded);
+        assert!(cache
+            .enrolled_campaigns(user_id, today_date)
+            .await
+            .contains(&1));
+    }
+
+    #[tokio::test]
+    async fn probability_distribution_is_replaced_not_appended() {
+        let ctx = TestContext::with_redis().await;
+        let cache = RedisCache::new(
+            ctx.store.redis_pool.clone().unwrap(),
+            format!("{}:", ctx.db_name),
+            &ctx.store.config.cache,
+        );
+
+        // As if several draws missed the cache at once
+        let distribution = [(1, 0.1), (2, 0.2)];
+
+        tokio::join!(
+            cache.set_probability_distribution(1, &distribution),
+            cache.set_probability_distribution(1, &distribution),
+            cache.set_probability_distribution(1, &distribution),
+        );
+
+        assert_eq!(
+            cache.probability_distribution(1).await,
+            Some(vec![(1, 0.1), (2, 0.2)])
+        );
+
+        cache.set_probability_distribution(1, &[(3, 0.5)]).await;
+
+        assert_eq!(
+            cache.probability_distribution(1).await,
+            Some(vec![(3, 0.5)])
+        );
+        assert!(!cache.health().degraded);
+    }
+
+    #[tokio::test]
+    async fn memory_cache() {
+        let cache = MemoryCache::default();
+        let today_date = chrono::Utc::now().naive_utc().date();
+
+        assert!(cache.enrolled_campaigns(1, today_date).await.is_empty());
+
+        cache.add_enrolled_campaign(1, today_date, 1).await;
+        cache.add_enrolled_campaign(1, today_date, 2).await;
+
+        assert_eq!(cache.enrolled_campaigns(1, today_date).await, vec![1, 2]);
+        assert!(cache.enrolled_campaigns(2, today_date).await.is_empty());
+
+        assert_eq!(cache.probability_distribution(1).await, None);
+
+        cache
+            .set_probability_distribution(1, &[(1, 0.1), (2, 0.2)])
+            .await;
+
+        assert_eq!(
+            cache.probability_distribution(1).await,
+            Some(vec![(1, 0.1), (2, 0.2)])
+        );
+        assert!(!cache.health().degraded);
+    }
+}
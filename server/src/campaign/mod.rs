@@ -1,30 +1,25 @@
-use std::sync::Arc;
-
 use axum::{
-    extract::{Path, State},
-    response::IntoResponse,
-    Json,
+    extract::State,
+    response::{IntoResponse, Response},
 };
 use hyper::StatusCode;
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
+use crate::eligibility::EligibilityRules;
+use crate::error::{AppError, ErrorCode};
+use crate::extract::{Json, Path};
+use crate::prize_rules::{PrizeRules, WhenExhausted};
+use crate::repository::{CampaignRepository, CouponTypeRepository, NewCouponType, PgRepository};
 use crate::store::Store;
-use crate::types::Campaign;
 
 mod test;
 
-#[derive(Serialize, Deserialize, ToSchema)]
-pub(super) enum CampaignError {
-    #[schema(example = "Sum of probabilities of coupon types exceed 1")]
-    Conflict(String),
-    #[schema(example = "Campaign ID doesn't exist")]
-    NotFound(String),
-}
-
 #[derive(Serialize, Deserialize, ToSchema)]
 pub(super) struct GetCampaignResult {
     pub coupon_types: Vec<GetCampaignResultCouponType>,
+    pub eligibility: Option<EligibilityRules>,
+    pub prize_rules: Option<PrizeRules>,
 }
 
 #[derive(ToSchema, Clone, Serialize, Deserialize)]
@@ -35,7 +30,10 @@ pub struct GetCampaignResultCouponType {
     pub total_quota: Option<i32>,
     pub daily_quota: Option<i32>,
     pub current_quota: Option<i32>,
+    /// Left today, the full daily quota until the first draw of the day
     pub current_daily_quota: Option<i32>,
+    pub fallback: bool,
+    pub first_draw: bool,
 }
 
 #[utoipa::path(
@@ -43,52 +41,68 @@ pub struct GetCampaignResultCouponType {
     path = "/campaign/{id}",
     responses(
         (status = 200, description = "Get information about the campaign successfully", body = GetCampaignResult),
-        (status = 404, description = "Campaign ID doesn't exist", body = CampaignError)
+        (status = 404, description = "Campaign ID doesn't exist", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+        (status = 503, description = "Database temporarily unavailable", body = ErrorBody)
     )
 )]
 #[axum::debug_handler]
 pub(super) async fn get_campaign(
     Path(id): Path<i32>,
-    State(store): State<Arc<Store>>,
-) -> impl IntoResponse {
-    let db_pool = store.lock().await.db_pool.clone();
-
-    let campaign_coupon_types = sqlx::query_as!(
-        GetCampaignResultCouponType,
-        "--sql
-            select description, probability, total_quota, daily_quota, current_quota, current_daily_quota
-            from campaign_coupon_types
-            where campaign_id = $1;
-        ",
-        id
-    )
-    .fetch_all(&db_pool)
-    .await
-    .unwrap();
-
-    if campaign_coupon_types.len() == 0 {
-        (
-            StatusCode::NOT_FOUND,
-            Json(CampaignError::NotFound(format!(
+    State(store): State<Store>,
+) -> Result<Response, AppError> {
+    let repository = PgRepository::new(store.db_pool.clone(), store.metrics.clone());
+    let today_date = chrono::Utc::now().naive_utc().date();
+
+    let campaign_coupon_types: Vec<_> = repository
+        .list_by_campaign(id)
+        .await?
+        .into_iter()
+        .map(|t| GetCampaignResultCouponType {
+            current_daily_quota: t.daily_quota_left(today_date),
+            description: t.description,
+            probability: t.probability,
+            total_quota: t.total_quota,
+            daily_quota: t.daily_quota,
+            current_quota: t.current_quota,
+            fallback: t.fallback,
+            first_draw: t.first_draw,
+        })
+        .collect();
+
+    if campaign_coupon_types.is_empty() {
+        return Err(AppError::new(
+            ErrorCode::CampaignNotFound,
+            format!(
                 "Campaign ID {} doesn't exist, or campaign doesn't have any coupon types",
                 id
-            ))),
-        )
-            .into_response()
-    } else {
-        (
-            StatusCode::OK,
-            Json(GetCampaignResult {
-                coupon_types: campaign_coupon_types,
-            }),
-        )
-            .into_response()
+            ),
+        ));
     }
+
+    let eligibility = repository.eligibility_rules(id).await?;
+    let prize_rules = repository.prize_rules(id).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(GetCampaignResult {
+            coupon_types: campaign_coupon_types,
+            eligibility,
+            prize_rules,
+        }),
+    )
+        .into_response())
 }
 
 #[derive(Serialize, Deserialize, ToSchema)]
 pub(super) struct CreateCampaignPayload {
     pub coupon_types: Vec<CreateCampaignPayloadCouponType>,
+    /// Who can draw from the campaign. Everyone can if omitted
+    #[serde(default)]
+    pub eligibility: Option<EligibilityRules>,
+    /// How prizes are handed out. A coupon type that has run out yields no prize if omitted
+    #[serde(default)]
+    pub prize_rules: Option<PrizeRules>,
 }
 
 #[derive(Serialize, Deserialize, ToSchema)]
@@ -101,6 +115,13 @@ pub(super) struct CreateCampaignPayloadCouponType {
     pub total_quota: Option<i32>,
     #[schema(example = "30")]
     pub daily_quota: Option<i32>,
+    /// Issued instead of a coupon type that has run out, with `when_exhausted: fallback`. Give
+    /// it a probability of 0 for it to only be won that way
+    #[serde(default)]
+    pub fallback: bool,
+    /// Issued on a user's first draw from the campaign, as long as it has quota left
+    #[serde(default)]
+    pub first_draw: bool,
 }
 
 #[utoipa::path(
@@ -109,75 +130,146 @@ pub(super) struct CreateCampaignPayloadCouponType {
     request_body = CreateCampaignPayload,
     responses(
         (status = 201, description = "Campaign created successfully", body = Campaign),
-        (status = 409, description = "Sum of probabilities of coupon types exceed 1", body = CampaignError)
+        (status = 409, description = "A probability is out of range or their sum exceeds 1, a quota is negative, or eligibility or prize rules are invalid", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+        (status = 503, description = "Database temporarily unavailable", body = ErrorBody)
     )
 )]
 pub(super) async fn create_campaign(
-    State(store): State<Arc<Store>>,
+    State(store): State<Store>,
     Json(payload): Json<CreateCampaignPayload>,
-) -> impl IntoResponse {
-    let db_pool = store.lock().await.db_pool.clone();
+) -> Result<Response, AppError> {
+    // A negative probability would otherwise offset the others in the sum, and one that doesn't
+    // fit in an f32 is deserialized as infinity
+    if let Some(coupon_type) = payload
+        .coupon_types
+        .iter()
+        .find(|t| !(0.0..=1.0).contains(&t.probability))
+    {
+        return Err(AppError::new(
+            ErrorCode::InvalidProbabilities,
+            format!(
+                "Probability of coupon type {:?} must be between 0 and 1: {}",
+                coupon_type.description, coupon_type.probability
+            ),
+        ));
+    }
 
     let total_prob: f32 = payload.coupon_types.iter().map(|t| t.probability).sum();
 
     if total_prob > 1.0 {
-        return (
-            StatusCode::CONFLICT,
-            Json(CampaignError::Conflict(format!(
+        return Err(AppError::new(
+            ErrorCode::InvalidProbabilities,
+            format!(
                 "Sum of probabilities of coupon types in campaign exceed 1: {}",
                 total_prob
-            ))),
-        )
-            .into_response();
+            ),
+        ));
     }
 
-    let mut tx = db_pool.begin().await.unwrap();
+    // Quotas are unlimited if omitted, and 0 is allowed for a coupon type that is out from the start
+    if let Some(coupon_type) = payload
+        .coupon_types
+        .iter()
+        .find(|t| t.total_quota.unwrap_or(0) < 0 || t.daily_quota.unwrap_or(0) < 0)
+    {
+        return Err(AppError::new(
+            ErrorCode::InvalidQuotas,
+            format!(
+                "Quotas of coupon type {:?} must not be negative: {:?} in total, {:?} daily",
+                coupon_type.description, coupon_type.total_quota, coupon_type.daily_quota
+            ),
+        ));
+    }
 
-    let new_compaign = sqlx::query_as!(
-        Campaign,
-        "--sql
-            insert into campaigns (id)
-            values (default)
-            returning *;
-        "
-    )
-    .fetch_one(&mut *tx)
-    .await
-    .unwrap();
+    if let Some(eligibility) = &payload.eligibility {
+        let min_age = eligibility.min_account_age_days;
+        let max_age = eligibility.max_account_age_days;
+
+        if min_age.unwrap_or(0) < 0
+            || max_age.unwrap_or(0) < 0
+            || min_age.zip(max_age).is_some_and(|(min, max)| min > max)
+        {
+            return Err(AppError::new(
+                ErrorCode::InvalidEligibilityRules,
+                format!(
+                    "Account age range of eligibility rules is invalid: {:?} to {:?} days",
+                    min_age, max_age
+                ),
+            ));
+        }
+    }
+
+    // A fallback coupon type is required by, and only makes sense with, `when_exhausted: fallback`
+    let fallbacks = payload.coupon_types.iter().filter(|t| t.fallback).count();
+    let when_exhausted = payload
+        .prize_rules
+        .as_ref()
+        .map(|rules| rules.when_exhausted)
+        .unwrap_or_default();
+
+    if fallbacks > 1 || (fallbacks == 1) != (when_exhausted == WhenExhausted::Fallback) {
+        return Err(AppError::new(
+            ErrorCode::InvalidPrizeRules,
+            format!(
+                "Prize rules falling back to a coupon type need exactly one fallback coupon type, \
+                 {fallbacks} found with when_exhausted {}",
+                when_exhausted.as_str()
+            ),
+        ));
+    }
+
+    let first_draws = payload.coupon_types.iter().filter(|t| t.first_draw).count();
+
+    if first_draws > 1 {
+        return Err(AppError::new(
+            ErrorCode::InvalidPrizeRules,
+            format!("At most one coupon type can be issued on first draws, {first_draws} found"),
+        ));
+    }
+
+    let guaranteed_win_after = payload
+        .prize_rules
+        .as_ref()
+        .and_then(|rules| rules.guaranteed_win_after);
+
+    if guaranteed_win_after.is_some_and(|after| after < 1) {
+        return Err(AppError::new(
+            ErrorCode::InvalidPrizeRules,
+            format!(
+                "Wins can only be guaranteed after at least 1 draw: {:?}",
+                guaranteed_win_after
+            ),
+        ));
+    }
 
-    let campaign_id = new_compaign.id;
+    // The first draw coupon type is looked up along with the prize rules, so it needs some
+    let prize_rules = payload
+        .prize_rules
+        .or_else(|| (first_draws == 1).then(PrizeRules::default));
 
-    let campaign_ids: Vec<_> = vec![campaign_id; payload.coupon_types.len()];
-    let descriptions: Vec<_> = payload
+    let coupon_types: Vec<_> = payload
         .coupon_types
-        .iter()
-        .map(|t| t.description.clone())
+        .into_iter()
+        .map(|t| NewCouponType {
+            description: t.description,
+            probability: t.probability,
+            total_quota: t.total_quota,
+            daily_quota: t.daily_quota,
+            fallback: t.fallback,
+            first_draw: t.first_draw,
+        })
         .collect();
-    let probabilities: Vec<_> = payload.coupon_types.iter().map(|t| t.probability).collect();
-    let total_quotas: Vec<Option<i32>> =
-        payload.coupon_types.iter().map(|t| t.total_quota).collect();
-    let daily_quotas: Vec<Option<i32>> =
-        payload.coupon_types.iter().map(|t| t.daily_quota).collect();
-
-    // https://github.com/launchbadge/sqlx/blob/main/FAQ.md#how-can-i-bind-an-array-to-a-values-clause-how-can-i-do-bulk-inserts
-    // https://github.com/launchbadge/sqlx/issues/1893
-    #[allow(deprecated)]
-    sqlx::query!(
-        "--sql
-            insert into campaign_coupon_types (campaign_id, description, probability, total_quota, daily_quota, current_quota)
-            select * from unnest($1::int[], $2::text[], $3::float4[], $4::int[], $5::int[], $4::int[]);
-        ",
-        &campaign_ids[..],
-        &descriptions[..],
-        &probabilities[..],
-        &total_quotas[..]: Vec<Option<i32>>,
-        &daily_quotas[..]: Vec<Option<i32>>
-    )
-    .execute(&mut *tx)
-    .await
-    .unwrap();
 
-    tx.commit().await.unwrap();
+    let repository = PgRepository::new(store.db_pool.clone(), store.metrics.clone());
+
+    let new_compaign = CampaignRepository::create(
+        &repository,
+        &coupon_types,
+        payload.eligibility.as_ref(),
+        prize_rules.as_ref(),
+    )
+    .await?;
 
-    (StatusCode::CREATED, Json(new_compaign)).into_response()
+    Ok((StatusCode::CREATED, Json(new_compaign)).into_response())
 }
@@ -1,8 +1,10 @@
 #[cfg(test)]
 mod tests {
     use crate::{
+        admin::ADMIN_TOKEN_HEADER,
+        api_key::API_KEY_HEADER,
         campaign::{CreateCampaignPayload, CreateCampaignPayloadCouponType},
-        create_app, create_store,
+        testing::{post_json, TestContext, ADMIN_TOKEN},
     };
 
     use axum::{
@@ -16,7 +18,8 @@ mod tests {
 
     #[tokio::test]
     async fn create_campaign_fail_if_prob_exceed_1() {
-        let app = create_app().await;
+        let ctx = TestContext::new().await;
+        let app = ctx.app.clone();
 
         let create_campaign_response = app
             .clone()
@@ -33,20 +36,28 @@ mod tests {
                                     probability: 0.5,
                                     total_quota: None,
                                     daily_quota: None,
+                                    fallback: false,
+                                    first_draw: false,
                                 },
                                 CreateCampaignPayloadCouponType {
                                     description: "30%".to_string(),
                                     probability: 0.3,
                                     total_quota: None,
                                     daily_quota: None,
+                                    fallback: false,
+                                    first_draw: false,
                                 },
                                 CreateCampaignPayloadCouponType {
                                     description: "30%".to_string(),
                                     probability: 0.3,
                                     total_quota: None,
                                     daily_quota: None,
+                                    fallback: false,
+                                    first_draw: false,
                                 },
                             ],
+                            eligibility: None,
+                            prize_rules: None,
                         })
                         .unwrap(),
                     ))
@@ -62,8 +73,10 @@ mod tests {
             .unwrap();
         let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
 
+        assert_eq!(body["type"], "conflict");
+        assert_eq!(body["code"], "invalid_probabilities");
         assert_eq!(
-            body["Conflict"],
+            body["message"],
             format!(
                 "Sum of probabilities of coupon types in campaign exceed 1: {}",
                 1.1
@@ -71,9 +84,107 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn create_campaign_fail_if_prob_out_of_range() {
+        let ctx = TestContext::new().await;
+        let app = ctx.app.clone();
+
+        // The sum of the first pair is within 1, and the second probability overflows an f32
+        for (probabilities, message) in [
+            (
+                json!([0.9, -0.5]),
+                "Probability of coupon type \"1\" must be between 0 and 1: -0.5",
+            ),
+            (
+                json!([0.5, 1e39]),
+                "Probability of coupon type \"1\" must be between 0 and 1: inf",
+            ),
+        ] {
+            let coupon_types: Vec<_> = probabilities
+                .as_array()
+                .unwrap()
+                .iter()
+                .enumerate()
+                .map(|(i, probability)| {
+                    json!({ "description": i.to_string(), "probability": probability })
+                })
+                .collect();
+
+            let (status, _, body) = post_json(
+                &app,
+                "/campaign",
+                &[],
+                json!({ "coupon_types": coupon_types }),
+            )
+            .await;
+
+            assert_eq!(status, StatusCode::CONFLICT);
+            assert_eq!(body["code"], "invalid_probabilities");
+            assert_eq!(body["message"], message);
+        }
+    }
+
+    #[tokio::test]
+    async fn create_campaign_fail_if_quota_negative() {
+        let ctx = TestContext::new().await;
+        let app = ctx.app.clone();
+
+        let create_campaign_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/campaign")
+                    .method(Method::POST)
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(
+                        serde_json::to_string(&CreateCampaignPayload {
+                            coupon_types: vec![
+                                CreateCampaignPayloadCouponType {
+                                    description: "Out from the start".to_string(),
+                                    probability: 0.5,
+                                    total_quota: Some(0),
+                                    daily_quota: None,
+                                    fallback: false,
+                                    first_draw: false,
+                                },
+                                CreateCampaignPayloadCouponType {
+                                    description: "Negative".to_string(),
+                                    probability: 0.3,
+                                    total_quota: None,
+                                    daily_quota: Some(-1),
+                                    fallback: false,
+                                    first_draw: false,
+                                },
+                            ],
+                            eligibility: None,
+                            prize_rules: None,
+                        })
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(create_campaign_response.status(), StatusCode::CONFLICT);
+
+        let body = hyper::body::to_bytes(create_campaign_response.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(body["type"], "conflict");
+        assert_eq!(body["code"], "invalid_quotas");
+        assert_eq!(
+            body["message"],
+            "Quotas of coupon type \"Negative\" must not be negative: None in total, Some(-1) daily"
+        );
+    }
+
     #[tokio::test]
     async fn create_campaign_and_draw_coupon() {
-        let app = create_app().await;
+        let ctx = TestContext::with_redis().await;
+        let app = ctx.app.clone();
 
         let create_campaign_response = app
             .clone()
@@ -90,20 +201,28 @@ mod tests {
                                     probability: 1.0,
                                     total_quota: Some(50),
                                     daily_quota: Some(10),
+                                    fallback: false,
+                                    first_draw: false,
                                 },
                                 CreateCampaignPayloadCouponType {
                                     description: "0%".to_string(),
                                     probability: 0.0,
                                     total_quota: None,
                                     daily_quota: None,
+                                    fallback: false,
+                                    first_draw: false,
                                 },
                                 CreateCampaignPayloadCouponType {
                                     description: "0%".to_string(),
                                     probability: 0.0,
                                     total_quota: None,
                                     daily_quota: None,
+                                    fallback: false,
+                                    first_draw: false,
                                 },
                             ],
+                            eligibility: None,
+                            prize_rules: None,
                         })
                         .unwrap(),
                     ))
@@ -122,15 +241,9 @@ mod tests {
         let campaign_id = body["id"].as_i64().unwrap();
         let campaign_id: i32 = campaign_id.try_into().unwrap();
 
-        let store = create_store().await;
-        let db_pool = store.lock().await.db_pool.clone();
-        let redis = &mut store
-            .lock()
-            .await
-            .redis
-            .get_async_connection()
-            .await
-            .unwrap();
+        let store = &ctx.store;
+        let db_pool = store.db_pool.clone();
+        let redis = &mut store.redis_pool.as_ref().unwrap().get().await.unwrap();
 
         // Create 2 temp users for testing
 
@@ -153,6 +266,37 @@ mod tests {
         let user_id = users[0].id;
         let user_2_id = users[1].id;
 
+        // Create an API key scoped to the campaign for redeeming
+
+        let create_api_key_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/api-key")
+                    .method(Method::POST)
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .header(ADMIN_TOKEN_HEADER, ADMIN_TOKEN)
+                    .body(Body::from(
+                        serde_json::to_string(&json!({
+                            "merchant": "merchant-1",
+                            "campaign_ids": [campaign_id]
+                        }))
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(create_api_key_response.status(), StatusCode::CREATED);
+
+        let body = hyper::body::to_bytes(create_api_key_response.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        let api_key = body["key"].as_str().unwrap().to_string();
+
         // Check if /draw POST endpoint works
 
         let draw_response = app
@@ -219,6 +363,7 @@ mod tests {
                     .uri("/redeem")
                     .method(Method::POST)
                     .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .header(API_KEY_HEADER, &api_key)
                     .body(Body::from(
                         serde_json::to_string(&json!({
                             "coupon_id": coupon_id,
@@ -242,6 +387,7 @@ mod tests {
                     .uri("/redeem")
                     .method(Method::POST)
                     .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .header(API_KEY_HEADER, &api_key)
                     .body(Body::from(
                         serde_json::to_string(&json!({
                             "coupon_id": coupon_id,
@@ -260,8 +406,10 @@ mod tests {
 
         let today_date = chrono::Utc::now().naive_utc().date();
 
-        let enrolled_campaigns_cache_key =
-            format!("user-{}:enrolled-campaigns:{}", user_id, today_date);
+        let enrolled_campaigns_cache_key = format!(
+            "{}:user-{}:enrolled-campaigns:{}",
+            ctx.db_name, user_id, today_date
+        );
 
         let enrolled_campaigns_cache: Vec<String> = redis
             .lrange(enrolled_campaigns_cache_key.clone(), 0, -1)
@@ -273,7 +421,7 @@ mod tests {
 
         // Check if the campaign coupon types probability distribution is cached
 
-        let coupon_types_cache_key = format!("campaign-{}:prob-dist", campaign_id);
+        let coupon_types_cache_key = format!("{}:campaign-{}:prob-dist", ctx.db_name, campaign_id);
 
         let coupon_types_cache: Vec<String> = redis
             .lrange(coupon_types_cache_key.clone(), 0, -1)
@@ -427,8 +575,9 @@ mod tests {
         // let body = String::from_utf8_lossy(body.as_ref());
         let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
 
+        assert_eq!(body["code"], "already_drawn");
         assert_eq!(
-            body["Conflict"],
+            body["message"],
             "User has already drawn from this campaign. Come again tommorow".to_string()
         );
 
@@ -545,4 +694,142 @@ mod tests {
 
         assert_eq!(body.get("maybe_coupon").unwrap(), &serde_json::Value::Null);
     }
+
+    #[tokio::test]
+    async fn draw_fail_if_not_eligible() {
+        let ctx = TestContext::new().await;
+        let app = ctx.app.clone();
+
+        let create_campaign_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/campaign")
+                    .method(Method::POST)
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(
+                        serde_json::to_string(&json!({
+                            "coupon_types": [{
+                                "description": "100%",
+                                "probability": 1.0,
+                                "total_quota": null,
+                                "daily_quota": null
+                            }],
+                            "eligibility": {
+                                "max_account_age_days": 7,
+                                "phone_prefixes": ["+853"]
+                            }
+                        }))
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(create_campaign_response.status(), StatusCode::CREATED);
+
+        let body = hyper::body::to_bytes(create_campaign_response.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        let campaign_id = body["id"].as_i64().unwrap();
+
+        // Check if the eligibility rules are returned with the campaign
+
+        let get_campaign_details_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/campaign/{}", campaign_id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let body = hyper::body::to_bytes(get_campaign_details_response.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(body["eligibility"]["max_account_age_days"], 7);
+        assert_eq!(body["eligibility"]["phone_prefixes"], json!(["+853"]));
+        assert_eq!(body["eligibility"]["require_verified_phone"], false);
+
+        let store = &ctx.store;
+        let db_pool = store.db_pool.clone();
+
+        let phones = [
+            format!("+852 {}", &Uuid::new_v4().simple().to_string()[..8]),
+            format!("+853 {}", &Uuid::new_v4().simple().to_string()[..8]),
+        ];
+
+        let users = sqlx::query!(
+            "--sql
+                insert into users (phone)
+                select * from unnest($1::text[])
+                returning id;
+            ",
+            &phones[..],
+        )
+        .fetch_all(&db_pool)
+        .await
+        .unwrap();
+
+        // The Hong Kong user fails the phone prefix rule
+
+        let draw_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/draw")
+                    .method(Method::POST)
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(
+                        serde_json::to_string(&json!({
+                            "campaign_id": campaign_id,
+                            "user_id": users[0].id
+                        }))
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(draw_response.status(), StatusCode::FORBIDDEN);
+
+        let body = hyper::body::to_bytes(draw_response.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(body["code"], "not_eligible");
+        assert_eq!(body["details"]["rule"], "phone_prefix");
+
+        // The Macau user is eligible
+
+        let draw_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/draw")
+                    .method(Method::POST)
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(
+                        serde_json::to_string(&json!({
+                            "campaign_id": campaign_id,
+                            "user_id": users[1].id
+                        }))
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(draw_response.status(), StatusCode::OK);
+    }
 }
@@ -0,0 +1,603 @@
+//! Typed configuration of the service. Settings are read from the defaults, then the TOML file of
+//! `CONFIG_FILE` (`config.toml` if it exists), then the environment, each overriding the former.
+//! Everything is validated at startup, so that a misconfigured service fails with every problem
+//! listed instead of panicking on first use
+
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::Deserialize;
+use tracing_subscriber::EnvFilter;
+
+use crate::cache::CacheBackend;
+use crate::rate_limit::RateLimitBackend;
+
+mod test;
+
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+/// So that the token can't be guessed
+const MIN_ADMIN_TOKEN_LEN: usize = 32;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub cache: CacheConfig,
+    pub redis: RedisConfig,
+    pub log: LogConfig,
+    pub rate_limit: RateLimitConfig,
+    pub idempotency: IdempotencyConfig,
+    pub admin: AdminConfig,
+    pub features: FeatureConfig,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// `BIND_ADDRESS`
+    pub bind_address: SocketAddr,
+    /// `SHUTDOWN_TIMEOUT_SECS`, how long in-flight requests are given to finish on SIGTERM or
+    /// SIGINT. Keep it below the orchestrator's grace period
+    pub shutdown_timeout_secs: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: SocketAddr::from((Ipv4Addr::UNSPECIFIED, 8080)),
+            shutdown_timeout_secs: 25,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout_secs)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// `DATABASE_URL`, required
+    pub url: String,
+    /// `DB_MAX_CONNECTIONS`
+    pub max_connections: u32,
+    /// `DB_MIN_CONNECTIONS`
+    pub min_connections: u32,
+    /// `DB_ACQUIRE_TIMEOUT_SECS`, how long a handler waits for a connection before failing
+    pub acquire_timeout_secs: u64,
+    /// `DB_IDLE_TIMEOUT_SECS`, how long an unused connection is kept open
+    pub idle_timeout_secs: u64,
+    /// `DB_MIGRATE_ON_STARTUP`. Turn off to run migrations as a separate deployment step with the
+    /// `migrate` subcommand instead
+    pub migrate_on_startup: bool,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            max_connections: 10,
+            min_connections: 0,
+            acquire_timeout_secs: 30,
+            idle_timeout_secs: 600,
+            migrate_on_startup: true,
+        }
+    }
+}
+
+impl DatabaseConfig {
+    pub fn acquire_timeout(&self) -> Duration {
+        Duration::from_secs(self.acquire_timeout_secs)
+    }
+
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout_secs)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// `CACHE_BACKEND`
+    pub backend: CacheBackend,
+    /// `CACHE_ENROLLED_CAMPAIGNS_TTL_SECS`. A user's enrolled campaigns only matter on the day of
+    /// the draw
+    pub enrolled_campaigns_ttl_secs: u64,
+    /// `CACHE_PROBABILITY_DISTRIBUTION_TTL_SECS`
+    pub probability_distribution_ttl_secs: u64,
+    /// `CACHE_MAX_ENTRIES`, per cache of the in-memory backend
+    pub max_entries: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            backend: CacheBackend::Redis,
+            enrolled_campaigns_ttl_secs: 24 * 60 * 60,
+            probability_distribution_ttl_secs: 60 * 60,
+            max_entries: 100_000,
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn enrolled_campaigns_ttl(&self) -> Duration {
+        Duration::from_secs(self.enrolled_campaigns_ttl_secs)
+    }
+
+    pub fn probability_distribution_ttl(&self) -> Duration {
+        Duration::from_secs(self.probability_distribution_ttl_secs)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedisConfig {
+    /// `REDIS_URL`, required by the Redis cache backend
+    pub url: Option<String>,
+    /// `REDIS_KEY_PREFIX`, so that several deployments can share a Redis
+    pub key_prefix: String,
+    /// `REDIS_POOL_SIZE`
+    pub pool_size: usize,
+    /// `REDIS_TIMEOUT_MS`, of connecting and of waiting for a pooled connection. Short, so that
+    /// handlers fall back to the DB quickly when Redis is down
+    pub timeout_ms: u64,
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            key_prefix: String::new(),
+            pool_size: 16,
+            timeout_ms: 1000,
+        }
+    }
+}
+
+impl RedisConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// `RUST_LOG`, e.g. `info` or `warn,lucky_draw_web_service=debug`
+    pub level: String,
+    /// `LOG_FORMAT`
+    pub format: LogFormat,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            format: LogFormat::Json,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One JSON object per line, for log collectors
+    Json,
+    /// Human readable, for local development
+    Text,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "text" => Ok(Self::Text),
+            _ => Err("expected json or text".to_string()),
+        }
+    }
+}
+
+/// Token buckets limiting `/draw`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// `RATE_LIMIT_ENABLED`
+    pub enabled: bool,
+    /// `RATE_LIMIT_BACKEND`, where the buckets are kept, independently of the cache
+    pub backend: RateLimitBackend,
+    /// `RATE_LIMIT_USER_BURST`, draws a user can make at once
+    pub user_burst: u32,
+    /// `RATE_LIMIT_USER_PER_MINUTE`, draws a user regains per minute
+    pub user_per_minute: u32,
+    /// `RATE_LIMIT_IP_BURST`. Generous, as many users can share an IP behind a NAT
+    pub ip_burst: u32,
+    /// `RATE_LIMIT_IP_PER_MINUTE`
+    pub ip_per_minute: u32,
+    /// `RATE_LIMIT_TRUST_FORWARDED_FOR`, takes the client IP from the `X-Forwarded-For` header.
+    /// Only turn it on behind a proxy that sets the header, or clients can pick their own IP
+    pub trust_forwarded_for: bool,
+    /// `RATE_LIMIT_MAX_BUCKETS`, of the memory backend. A bucket evicted to make room starts out
+    /// full again, so this should cover the users and IPs drawing within a refill time
+    pub max_buckets: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            backend: RateLimitBackend::Redis,
+            user_burst: 5,
+            user_per_minute: 10,
+            ip_burst: 50,
+            ip_per_minute: 300,
+            trust_forwarded_for: false,
+            max_buckets: 100_000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdempotencyConfig {
+    /// `IDEMPOTENCY_WINDOW_SECS`, how long the response to a request with an `Idempotency-Key` is
+    /// replayed to retries
+    pub window_secs: u64,
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        Self {
+            window_secs: 24 * 60 * 60,
+        }
+    }
+}
+
+impl IdempotencyConfig {
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.window_secs)
+    }
+}
+
+/// Access to the admin endpoints, e.g. managing merchant API keys
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    /// `ADMIN_TOKEN`, sent by admins in the `x-admin-token` header. The admin endpoints reject
+    /// every request while it is unset
+    pub token: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeatureConfig {
+    /// `FEATURE_API_DOCS`, serves the Swagger, Redoc and Rapidoc UIs
+    pub api_docs: bool,
+    /// `FEATURE_POOL_STATS`, serves `/stats/pools`. Unauthenticated, so only turn it on where the
+    /// port isn't reachable from the internet
+    pub pool_stats: bool,
+    /// `FEATURE_METRICS`, serves Prometheus metrics on `/metrics`. Unauthenticated, like
+    /// `pool_stats`
+    pub metrics: bool,
+}
+
+impl Default for FeatureConfig {
+    fn default() -> Self {
+        Self {
+            api_docs: true,
+            pool_stats: false,
+            metrics: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    File { path: PathBuf, message: String },
+    Env { var: &'static str, message: String },
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File { path, message } => write!(f, "{}: {message}", path.display()),
+            Self::Env { var, message } => write!(f, "{var}: {message}"),
+            Self::Invalid(problems) => {
+                write!(f, "invalid configuration:")?;
+
+                for problem in problems {
+                    write!(f, "\n  - {problem}")?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Reads the configuration file and environment of the process. Validation is left to the
+    /// caller, so that settings can be adjusted first
+    pub fn read() -> Result<Self, ConfigError> {
+        let (path, required) = match std::env::var_os("CONFIG_FILE") {
+            Some(path) => (PathBuf::from(path), true),
+            None => (PathBuf::from(DEFAULT_CONFIG_FILE), false),
+        };
+
+        let mut config = match std::fs::read_to_string(&path) {
+            Ok(contents) => Self::from_toml(&contents).map_err(|message| ConfigError::File {
+                path: path.clone(),
+                message,
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => Self::default(),
+            Err(e) => {
+                return Err(ConfigError::File {
+                    path,
+                    message: e.to_string(),
+                })
+            }
+        };
+
+        config.apply_env(|var| std::env::var(var).ok())?;
+
+        Ok(config)
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self, String> {
+        toml::from_str(contents).map_err(|e| e.to_string())
+    }
+
+    /// Overrides settings with the variables `lookup` finds
+    pub fn apply_env(
+        &mut self,
+        lookup: impl Fn(&str) -> Option<String>,
+    ) -> Result<(), ConfigError> {
+        let lookup = &lookup;
+
+        override_with(&mut self.server.bind_address, "BIND_ADDRESS", lookup)?;
+        override_with(
+            &mut self.server.shutdown_timeout_secs,
+            "SHUTDOWN_TIMEOUT_SECS",
+            lookup,
+        )?;
+
+        override_with(&mut self.database.url, "DATABASE_URL", lookup)?;
+        override_with(
+            &mut self.database.max_connections,
+            "DB_MAX_CONNECTIONS",
+            lookup,
+        )?;
+        override_with(
+            &mut self.database.min_connections,
+            "DB_MIN_CONNECTIONS",
+            lookup,
+        )?;
+        override_with(
+            &mut self.database.acquire_timeout_secs,
+            "DB_ACQUIRE_TIMEOUT_SECS",
+            lookup,
+        )?;
+        override_with(
+            &mut self.database.idle_timeout_secs,
+            "DB_IDLE_TIMEOUT_SECS",
+            lookup,
+        )?;
+        override_with(
+            &mut self.database.migrate_on_startup,
+            "DB_MIGRATE_ON_STARTUP",
+            lookup,
+        )?;
+
+        override_with(&mut self.cache.backend, "CACHE_BACKEND", lookup)?;
+        override_with(
+            &mut self.cache.enrolled_campaigns_ttl_secs,
+            "CACHE_ENROLLED_CAMPAIGNS_TTL_SECS",
+            lookup,
+        )?;
+        override_with(
+            &mut self.cache.probability_distribution_ttl_secs,
+            "CACHE_PROBABILITY_DISTRIBUTION_TTL_SECS",
+            lookup,
+        )?;
+        override_with(&mut self.cache.max_entries, "CACHE_MAX_ENTRIES", lookup)?;
+
+        if let Some(url) = lookup("REDIS_URL") {
+            self.redis.url = Some(url);
+        }
+        override_with(&mut self.redis.key_prefix, "REDIS_KEY_PREFIX", lookup)?;
+        override_with(&mut self.redis.pool_size, "REDIS_POOL_SIZE", lookup)?;
+        override_with(&mut self.redis.timeout_ms, "REDIS_TIMEOUT_MS", lookup)?;
+
+        override_with(&mut self.log.level, "RUST_LOG", lookup)?;
+        override_with(&mut self.log.format, "LOG_FORMAT", lookup)?;
+
+        override_with(&mut self.rate_limit.enabled, "RATE_LIMIT_ENABLED", lookup)?;
+        override_with(&mut self.rate_limit.backend, "RATE_LIMIT_BACKEND", lookup)?;
+        override_with(
+            &mut self.rate_limit.user_burst,
+            "RATE_LIMIT_USER_BURST",
+            lookup,
+        )?;
+        override_with(
+            &mut self.rate_limit.user_per_minute,
+            "RATE_LIMIT_USER_PER_MINUTE",
+            lookup,
+        )?;
+        override_with(&mut self.rate_limit.ip_burst, "RATE_LIMIT_IP_BURST", lookup)?;
+        override_with(
+            &mut self.rate_limit.ip_per_minute,
+            "RATE_LIMIT_IP_PER_MINUTE",
+            lookup,
+        )?;
+        override_with(
+            &mut self.rate_limit.trust_forwarded_for,
+            "RATE_LIMIT_TRUST_FORWARDED_FOR",
+            lookup,
+        )?;
+        override_with(
+            &mut self.rate_limit.max_buckets,
+            "RATE_LIMIT_MAX_BUCKETS",
+            lookup,
+        )?;
+
+        override_with(
+            &mut self.idempotency.window_secs,
+            "IDEMPOTENCY_WINDOW_SECS",
+            lookup,
+        )?;
+
+        if let Some(token) = lookup("ADMIN_TOKEN") {
+            self.admin.token = Some(token);
+        }
+
+        override_with(&mut self.features.api_docs, "FEATURE_API_DOCS", lookup)?;
+        override_with(&mut self.features.pool_stats, "FEATURE_POOL_STATS", lookup)?;
+        override_with(&mut self.features.metrics, "FEATURE_METRICS", lookup)?;
+
+        Ok(())
+    }
+
+    /// Whether the cache or the rate limiter is kept in Redis
+    pub fn uses_redis(&self) -> bool {
+        self.cache.backend == CacheBackend::Redis
+            || (self.rate_limit.enabled && self.rate_limit.backend == RateLimitBackend::Redis)
+    }
+
+    /// Lists every problem at once rather than failing on the first
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = vec![];
+
+        let database = &self.database;
+
+        if database.url.is_empty() {
+            problems.push("database.url (DATABASE_URL) is required".to_string());
+        } else if !["postgres://", "postgresql://"]
+            .iter()
+            .any(|scheme| database.url.starts_with(scheme))
+        {
+            problems.push("database.url (DATABASE_URL) must be a postgres:// URL".to_string());
+        }
+
+        if database.max_connections == 0 {
+            problems.push("database.max_connections must be at least 1".to_string());
+        }
+
+        if database.min_connections > database.max_connections {
+            problems.push(format!(
+                "database.min_connections ({}) exceeds database.max_connections ({})",
+                database.min_connections, database.max_connections
+            ));
+        }
+
+        if database.acquire_timeout_secs == 0 {
+            problems.push("database.acquire_timeout_secs must be at least 1".to_string());
+        }
+
+        let cache = &self.cache;
+
+        if cache.enrolled_campaigns_ttl_secs == 0 {
+            problems.push("cache.enrolled_campaigns_ttl_secs must be at least 1".to_string());
+        }
+
+        if cache.probability_distribution_ttl_secs == 0 {
+            problems.push("cache.probability_distribution_ttl_secs must be at least 1".to_string());
+        }
+
+        if cache.max_entries == 0 {
+            problems.push("cache.max_entries must be at least 1".to_string());
+        }
+
+        if self.uses_redis() {
+            match &self.redis.url {
+                None => problems.push(
+                    "redis.url (REDIS_URL) is required by the redis backends, or set \
+                     cache.backend (CACHE_BACKEND) and rate_limit.backend (RATE_LIMIT_BACKEND) to \
+                     memory"
+                        .to_string(),
+                ),
+                Some(url) if !url.starts_with("redis://") && !url.starts_with("rediss://") => {
+                    problems.push("redis.url (REDIS_URL) must be a redis:// URL".to_string())
+                }
+                Some(_) => {}
+            }
+
+            if self.redis.pool_size == 0 {
+                problems.push("redis.pool_size must be at least 1".to_string());
+            }
+
+            if self.redis.timeout_ms == 0 {
+                problems.push("redis.timeout_ms must be at least 1".to_string());
+            }
+        }
+
+        if self.rate_limit.enabled {
+            for (setting, value) in [
+                ("user_burst", self.rate_limit.user_burst),
+                ("user_per_minute", self.rate_limit.user_per_minute),
+                ("ip_burst", self.rate_limit.ip_burst),
+                ("ip_per_minute", self.rate_limit.ip_per_minute),
+            ] {
+                if value == 0 {
+                    problems.push(format!("rate_limit.{setting} must be at least 1"));
+                }
+            }
+
+            if self.rate_limit.max_buckets == 0 {
+                problems.push("rate_limit.max_buckets must be at least 1".to_string());
+            }
+        }
+
+        if self.idempotency.window_secs == 0 {
+            problems.push("idempotency.window_secs must be at least 1".to_string());
+        }
+
+        if let Some(token) = &self.admin.token {
+            if token.len() < MIN_ADMIN_TOKEN_LEN {
+                problems.push(format!(
+                    "admin.token (ADMIN_TOKEN) must be at least {MIN_ADMIN_TOKEN_LEN} characters"
+                ));
+            }
+        }
+
+        if let Err(e) = EnvFilter::try_new(&self.log.level) {
+            problems.push(format!("log.level (RUST_LOG) is invalid: {e}"));
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+}
+
+fn override_with<T>(
+    setting: &mut T,
+    var: &'static str,
+    lookup: &impl Fn(&str) -> Option<String>,
+) -> Result<(), ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    if let Some(value) = lookup(var) {
+        *setting = value.parse().map_err(|e| ConfigError::Env {
+            var,
+            message: format!("invalid value {value:?}: {e}"),
+        })?;
+    }
+
+    Ok(())
+}
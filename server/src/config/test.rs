@@ -0,0 +1,119 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        cache::CacheBackend,
+        config::{Config, ConfigError},
+        rate_limit::RateLimitBackend,
+    };
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|&(var, value)| (var.to_string(), value.to_string()))
+            .collect();
+
+        move |var| vars.get(var).cloned()
+    }
+
+    #[test]
+    fn env_overrides_file_overrides_defaults() {
+        let mut config = Config::from_toml(
+            r#"
+                [server]
+                bind_address = "127.0.0.1:3000"
+
+                [database]
+                url = "postgres://file@localhost/pg"
+                max_connections = 20
+
+                [cache]
+                backend = "memory"
+            "#,
+        )
+        .unwrap();
+
+        config
+            .apply_env(env(&[
+                ("DATABASE_URL", "postgres://env@localhost/pg"),
+                ("CACHE_ENROLLED_CAMPAIGNS_TTL_SECS", "60"),
+                ("FEATURE_API_DOCS", "false"),
+                ("SHUTDOWN_TIMEOUT_SECS", "5"),
+                ("RATE_LIMIT_BACKEND", "memory"),
+                ("RATE_LIMIT_MAX_BUCKETS", "1000"),
+            ]))
+            .unwrap();
+
+        assert_eq!(config.server.bind_address.to_string(), "127.0.0.1:3000");
+        assert_eq!(config.server.shutdown_timeout_secs, 5);
+        assert_eq!(config.database.url, "postgres://env@localhost/pg");
+        assert_eq!(config.database.max_connections, 20);
+        assert_eq!(config.database.min_connections, 0);
+        assert_eq!(config.cache.backend, CacheBackend::Memory);
+        assert_eq!(config.cache.enrolled_campaigns_ttl_secs, 60);
+        assert!(!config.features.api_docs);
+        assert!(!config.features.pool_stats);
+
+        assert_eq!(config.rate_limit.backend, RateLimitBackend::Memory);
+        assert_eq!(config.rate_limit.max_buckets, 1000);
+
+        // The memory backends need no Redis, but either one kept in Redis does
+        config.validate().unwrap();
+
+        config.rate_limit.backend = RateLimitBackend::Redis;
+
+        let Err(ConfigError::Invalid(problems)) = config.validate() else {
+            panic!("Expected the configuration to be invalid");
+        };
+
+        assert!(problems[0].contains("REDIS_URL"), "{problems:#?}");
+    }
+
+    #[test]
+    fn invalid_settings_are_all_reported() {
+        let mut config = Config::default();
+
+        config
+            .apply_env(env(&[
+                ("DB_MAX_CONNECTIONS", "2"),
+                ("DB_MIN_CONNECTIONS", "5"),
+                ("RATE_LIMIT_USER_PER_MINUTE", "0"),
+                ("RUST_LOG", "lucky_draw_web_service=loud"),
+            ]))
+            .unwrap();
+
+        let Err(ConfigError::Invalid(problems)) = config.validate() else {
+            panic!("Expected the configuration to be invalid");
+        };
+
+        assert_eq!(problems.len(), 5, "{problems:#?}");
+        assert!(problems[0].contains("DATABASE_URL"));
+        assert!(problems[1].contains("min_connections"));
+        assert!(problems[2].contains("REDIS_URL"));
+        assert!(problems[3].contains("rate_limit.user_per_minute"));
+        assert!(problems[4].contains("RUST_LOG"));
+    }
+
+    #[test]
+    fn malformed_values_name_their_source() {
+        let error = Config::default()
+            .apply_env(env(&[("BIND_ADDRESS", "localhost")]))
+            .unwrap_err();
+
+        assert!(error.to_string().starts_with("BIND_ADDRESS: invalid value"));
+
+        let error = Config::default()
+            .apply_env(env(&[("CACHE_BACKEND", "memcached")]))
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            r#"CACHE_BACKEND: invalid value "memcached": expected redis or memory"#
+        );
+
+        let error = Config::from_toml("[database]\npool = 5").unwrap_err();
+
+        assert!(error.contains("unknown field `pool`"), "{error}");
+    }
+}
@@ -0,0 +1,282 @@
+use rand::distributions::WeightedIndex;
+use rand::prelude::*;
+use serde_json::json;
+
+use crate::cache::Cache;
+use crate::error::{AppError, ErrorCode};
+use crate::metrics::Metrics;
+use crate::prize_rules::paced_distribution;
+use crate::repository::{
+    CampaignRepository, CouponTypeRepository, DrawHistory, DrawRepository, UserRepository,
+};
+use crate::types::CampaignCoupon;
+
+/// Samples a coupon type from the `(coupon type id, probability)` pairs. Whatever probability is
+/// left over is the chance of winning nothing, in which case `None` is returned
+pub fn pick_coupon_type(
+    distribution: &[(i32, f32)],
+    rng: &mut impl Rng,
+) -> Result<Option<i32>, AppError> {
+    let mut probabilities: Vec<f32> = distribution.iter().map(|&(_, p)| p).collect();
+
+    // Campaigns and paced distributions sum to at most 1, but adding up f32s can overshoot it by
+    // a hair, which leaves a no-coupon weight of 0 rather than a negative one
+    probabilities.push((1.0 - probabilities.iter().sum::<f32>()).max(0.0));
+
+    let weighted_index = WeightedIndex::new(&probabilities).map_err(|e| {
+        AppError::Internal(format!(
+            "Invalid probability distribution {probabilities:?}: {e}"
+        ))
+    })?;
+
+    // The final category indicates no coupons
+    Ok(distribution
+        .get(weighted_index.sample(rng))
+        .map(|&(id, _)| id))
+}
+
+/// Draws from the campaign for the user, at most once a day. The cache saves DB round trips but
+/// the repository has the final say
+#[tracing::instrument(skip_all, fields(user_id = user_id, campaign_id = campaign_id, outcome))]
+pub async fn draw<R>(
+    repository: &R,
+    cache: &dyn Cache,
+    metrics: &Metrics,
+    user_id: i32,
+    campaign_id: i32,
+    now: chrono::DateTime<chrono::Utc>,
+    rng: &mut (impl Rng + Send),
+) -> Result<Option<CampaignCoupon>, AppError>
+where
+    R: UserRepository + CampaignRepository + CouponTypeRepository + DrawRepository,
+{
+    let mut campaign_exists = false;
+
+    let result = draw_once_a_day(
+        repository,
+        cache,
+        metrics,
+        user_id,
+        campaign_id,
+        now,
+        rng,
+        &mut campaign_exists,
+    )
+    .await;
+
+    let outcome = match &result {
+        Ok(Some(coupon)) => {
+            let coupon_type_id = coupon.campaign_coupon_type_id;
+
+            metrics
+                .coupons_issued
+                .with_label_values(&[&coupon_type_id.to_string()])
+                .inc();
+            tracing::info!(coupon_type_id, "Drew a coupon");
+
+            "coupon".to_string()
+        }
+        Ok(None) => {
+            tracing::info!("Drew no coupon");
+
+            "no_coupon".to_string()
+        }
+        Err(e) => {
+            tracing::info!(code = %e.code(), "Draw rejected");
+
+            e.code().to_string()
+        }
+    };
+
+    // The campaign id comes from the client, so draws from campaigns that don't exist are counted
+    // under one label rather than one each
+    let campaign_label = if campaign_exists {
+        campaign_id.to_string()
+    } else {
+        "unknown".to_string()
+    };
+
+    tracing::Span::current().record("outcome", outcome.as_str());
+    metrics
+        .draws
+        .with_label_values(&[&campaign_label, &outcome])
+        .inc();
+
+    result
+}
+
+/// Sets `campaign_exists` once the campaign is known to exist
+#[allow(clippy::too_many_arguments)]
+async fn draw_once_a_day<R>(
+    repository: &R,
+    cache: &dyn Cache,
+    metrics: &Metrics,
+    user_id: i32,
+    campaign_id: i32,
+    now: chrono::DateTime<chrono::Utc>,
+    rng: &mut (impl Rng + Send),
+    campaign_exists: &mut bool,
+) -> Result<Option<CampaignCoupon>, AppError>
+where
+    R: UserRepository + CampaignRepository + CouponTypeRepository + DrawRepository,
+{
+    let today_date = now.naive_utc().date();
+
+    // Check if user has already drawn from this campaign today, if so, return error. The cache
+    // comes back empty if it is unavailable, in which case the checks below take over
+
+    let enrolled = cache
+        .enrolled_campaigns(user_id, today_date)
+        .await
+        .contains(&campaign_id);
+
+    metrics.cache_lookup("enrolled_campaigns", enrolled);
+
+    if enrolled {
+        tracing::debug!(cache = "hit", "Enrolled campaigns");
+
+        // Only campaigns that were drawn from are cached
+        *campaign_exists = true;
+
+        return Err(AppError::new(
+            ErrorCode::AlreadyDrawn,
+            "User has already enrolled in this campaign. Come again tommorrow",
+        ));
+    }
+
+    tracing::debug!(cache = "miss", "Enrolled campaigns");
+
+    let user = repository.find_active(user_id).await?;
+    *campaign_exists = repository.exists(campaign_id).await?;
+
+    let Some(user) = user.filter(|_| *campaign_exists) else {
+        return Err(AppError::new(
+            ErrorCode::DrawTargetNotFound,
+            "Campaign or user doesn't exist",
+        ));
+    };
+
+    // Check manually if user has already drawn from this campaign today if cache miss
+
+    if repository
+        .has_drawn(user_id, campaign_id, today_date)
+        .await?
+    {
+        cache
+            .add_enrolled_campaign(user_id, today_date, campaign_id)
+            .await;
+
+        return Err(AppError::new(
+            ErrorCode::AlreadyDrawn,
+            "User has already drawn from this campaign. Come again tommorow",
+        ));
+    }
+
+    // Check if the user is eligible for the campaign before sampling
+
+    if let Some(eligibility) = repository.eligibility_rules(campaign_id).await? {
+        if let Err(ineligible) = eligibility.check(&user, now) {
+            return Err(AppError::new(ErrorCode::NotEligible, ineligible.message)
+                .with_details(json!({ "rule": ineligible.rule })));
+        }
+    }
+
+    // The prize rules can pace the distribution, decide what to issue instead if the coupon type
+    // picked has run out, and guarantee a coupon depending on the user's past draws
+
+    let prize_rules = repository
+        .prize_rules(campaign_id)
+        .await?
+        .unwrap_or_default();
+
+    // Check if probability distribution of the campaign coupon types is cached. Paced ones depend
+    // on the quotas left, so they are never cached
+
+    let cached_distribution = if prize_rules.pacing {
+        None
+    } else {
+        cache.probability_distribution(campaign_id).await
+    };
+
+    let distribution = match cached_distribution {
+        Some(distribution) => {
+            metrics.cache_lookup("probability_distribution", true);
+            tracing::debug!(cache = "hit", "Probability distribution");
+
+            distribution
+        }
+        None => {
+            if !prize_rules.pacing {
+                metrics.cache_lookup("probability_distribution", false);
+                tracing::debug!(cache = "miss", "Probability distribution");
+            }
+
+            // If cache miss, query from the repository and write to cache
+            let coupon_types = repository.list_by_campaign(campaign_id).await?;
+
+            if coupon_types.is_empty() {
+                return Err(AppError::new(
+                    ErrorCode::NoCouponTypes,
+                    "There is no coupon types in the campaign",
+                ));
+            }
+
+            if prize_rules.pacing {
+                paced_distribution(&coupon_types, now)
+            } else {
+                let distribution: Vec<(i32, f32)> =
+                    coupon_types.iter().map(|t| (t.id, t.probability)).collect();
+
+                cache
+                    .set_probability_distribution(campaign_id, &distribution)
+                    .await;
+
+                distribution
+            }
+        }
+    };
+
+    let coupon_type_id = pick_coupon_type(&distribution, rng)?;
+
+    let history = if prize_rules.needs_history() {
+        repository.history(user_id, campaign_id).await?
+    } else {
+        DrawHistory::default()
+    };
+
+    let candidates = prize_rules.candidates(coupon_type_id, &distribution, &history, rng);
+
+    tracing::debug!(
+        ?coupon_type_id,
+        ?candidates,
+        ?history,
+        ?distribution,
+        "Sampled"
+    );
+
+    let coupon = repository
+        .record(user_id, campaign_id, &candidates, today_date)
+        .await?;
+
+    // The coupon types tried before the one issued had run out, or all of them if none was
+    let exhausted = candidates.iter().take_while(|&&coupon_type_id| {
+        coupon
+            .as_ref()
+            .is_none_or(|c| c.campaign_coupon_type_id != coupon_type_id)
+    });
+
+    for coupon_type_id in exhausted {
+        tracing::info!(coupon_type_id, "Quota exhausted");
+
+        metrics
+            .quota_exhausted
+            .with_label_values(&[&coupon_type_id.to_string()])
+            .inc();
+    }
+
+    cache
+        .add_enrolled_campaign(user_id, today_date, campaign_id)
+        .await;
+
+    Ok(coupon)
+}
@@ -0,0 +1,344 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        cache::{CacheBackend, MemoryCache},
+        draw::service::{draw, pick_coupon_type},
+        eligibility::EligibilityRules,
+        error::ErrorCode,
+        metrics::Metrics,
+        prize_rules::{PrizeRules, WhenExhausted},
+        repository::fake::FakeRepository,
+        testing::{post_json, seeded_rng, TestContext},
+    };
+
+    use axum::{
+        body::Body,
+        http::{self, Method, Request, StatusCode},
+    };
+    use chrono::Utc;
+    use serde_json::json;
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    const CONCURRENT_DRAWS: usize = 8;
+
+    /// Handlers serializing on a lock would be a throughput ceiling. Rather than timing draws, which
+    /// is flaky next to the other tests, this holds the lock on the coupon type's row that every
+    /// draw waits for in the DB, and checks that all of them get there at once. Rate limiting is
+    /// off as every draw comes from the same client
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn concurrent_draws_do_not_wait_for_each_other() {
+        let ctx = TestContext::with_config(|config| {
+            config.cache.backend = CacheBackend::Memory;
+            config.rate_limit.enabled = false;
+        })
+        .await;
+        let app = ctx.app.clone();
+
+        let (status, _, body) = post_json(
+            &app,
+            "/campaign",
+            &[],
+            json!({
+                "coupon_types": [{ "description": "100%", "probability": 1.0 }]
+            }),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::CREATED);
+
+        let campaign_id = body["id"].as_i64().unwrap();
+
+        let store = &ctx.store;
+
+        // Every draw needs a fresh user since a user can only draw once a day
+
+        let random_phones: Vec<String> = (0..CONCURRENT_DRAWS)
+            .map(|_| Uuid::new_v4().to_string()[..20].to_owned())
+            .collect();
+
+        let user_ids: Vec<i32> = sqlx::query_scalar!(
+            "--sql
+                insert into users (phone)
+                select * from unnest($1::text[])
+                returning id;
+            ",
+            &random_phones[..],
+        )
+        .fetch_all(&store.db_pool)
+        .await
+        .unwrap();
+
+        let mut lock = store.db_pool.begin().await.unwrap();
+
+        sqlx::query!(
+            "--sql
+                select id from campaign_coupon_types where campaign_id = $1 for update;
+            ",
+            campaign_id as i32
+        )
+        .fetch_one(&mut *lock)
+        .await
+        .unwrap();
+
+        let draws: Vec<_> = user_ids
+            .into_iter()
+            .map(|user_id| {
+                let app = app.clone();
+
+                tokio::spawn(async move {
+                    app.oneshot(
+                        Request::builder()
+                            .uri("/draw")
+                            .method(Method::POST)
+                            .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                            .body(Body::from(
+                                serde_json::to_string(&json!({
+                                    "campaign_id": campaign_id,
+                                    "user_id": user_id
+                                }))
+                                .unwrap(),
+                            ))
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap()
+                })
+            })
+            .collect();
+
+        let mut waiting = 0;
+
+        for _ in 0..100 {
+            waiting = sqlx::query_scalar!(
+                "--sql
+                    select count(*)
+                    from pg_stat_activity
+                    where datname = current_database() and wait_event_type = 'Lock';
+                "
+            )
+            .fetch_one(&store.db_pool)
+            .await
+            .unwrap()
+            .unwrap_or(0);
+
+            if waiting == CONCURRENT_DRAWS as i64 {
+                break;
+            }
+
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        lock.commit().await.unwrap();
+
+        assert_eq!(waiting, CONCURRENT_DRAWS as i64);
+
+        for draw in draws {
+            assert_eq!(draw.await.unwrap().status(), StatusCode::OK);
+        }
+    }
+
+    #[test]
+    fn pick_coupon_type_follows_the_distribution() {
+        let mut rng = seeded_rng();
+
+        assert_eq!(pick_coupon_type(&[], &mut rng).unwrap(), None);
+        assert_eq!(pick_coupon_type(&[(1, 0.0)], &mut rng).unwrap(), None);
+        assert_eq!(pick_coupon_type(&[(1, 1.0)], &mut rng).unwrap(), Some(1));
+        // Sums that overshoot 1 by a hair leave no chance of no coupon
+        assert!(pick_coupon_type(&[(1, 0.7), (2, 0.300_001)], &mut rng)
+            .unwrap()
+            .is_some());
+
+        let mut counts = [0; 3];
+
+        for _ in 0..10_000 {
+            match pick_coupon_type(&[(1, 0.2), (2, 0.5)], &mut rng).unwrap() {
+                Some(1) => counts[0] += 1,
+                Some(2) => counts[1] += 1,
+                None => counts[2] += 1,
+                id => panic!("Unexpected coupon type {id:?}"),
+            }
+        }
+
+        assert!((1_800..2_200).contains(&counts[0]), "{counts:?}");
+        assert!((4_800..5_200).contains(&counts[1]), "{counts:?}");
+        assert!((2_800..3_200).contains(&counts[2]), "{counts:?}");
+    }
+
+    #[tokio::test]
+    async fn draw_service_allows_one_draw_a_day() {
+        let repository = FakeRepository::default();
+        let cache = MemoryCache::default();
+        let metrics = Metrics::default();
+        let mut rng = seeded_rng();
+
+        repository.add_user(1);
+        repository.add_campaign(1, None);
+        repository.add_coupon_type(1, 1, 1.0, None, None);
+
+        let coupon = draw(&repository, &cache, &metrics, 1, 1, Utc::now(), &mut rng)
+            .await
+            .unwrap();
+
+        assert_eq!(coupon.unwrap().campaign_coupon_type_id, 1);
+
+        // Stopped by the cache
+
+        let result = draw(&repository, &cache, &metrics, 1, 1, Utc::now(), &mut rng).await;
+
+        assert!(matches!(result, Err(e) if e.code() == ErrorCode::AlreadyDrawn));
+
+        // Stopped by the repository when the cache has forgotten
+
+        let result = draw(
+            &repository,
+            &MemoryCache::default(),
+            &metrics,
+            1,
+            1,
+            Utc::now(),
+            &mut rng,
+        )
+        .await;
+
+        assert!(matches!(result, Err(e) if e.code() == ErrorCode::AlreadyDrawn));
+    }
+
+    #[tokio::test]
+    async fn draw_service_rejects_invalid_draws() {
+        let repository = FakeRepository::default();
+        let cache = MemoryCache::default();
+        let metrics = Metrics::default();
+        let mut rng = seeded_rng();
+
+        repository.add_user(1);
+        repository.add_campaign(
+            1,
+            Some(EligibilityRules {
+                denied_user_ids: Some(vec![1]),
+                ..Default::default()
+            }),
+        );
+        repository.add_coupon_type(1, 1, 1.0, None, None);
+        repository.add_campaign(2, None);
+
+        let result = draw(&repository, &cache, &metrics, 2, 1, Utc::now(), &mut rng).await;
+
+        assert!(matches!(result, Err(e) if e.code() == ErrorCode::DrawTargetNotFound));
+
+        let result = draw(&repository, &cache, &metrics, 1, 1, Utc::now(), &mut rng).await;
+
+        assert!(matches!(result, Err(e) if e.code() == ErrorCode::NotEligible));
+
+        let result = draw(&repository, &cache, &metrics, 1, 2, Utc::now(), &mut rng).await;
+
+        assert!(matches!(result, Err(e) if e.code() == ErrorCode::NoCouponTypes));
+        assert!(repository.draws.lock().unwrap().is_empty());
+
+        // Made up campaigns don't each get a metrics label
+
+        for campaign_id in [3, 4] {
+            let result = draw(
+                &repository,
+                &cache,
+                &metrics,
+                1,
+                campaign_id,
+                Utc::now(),
+                &mut rng,
+            )
+            .await;
+
+            assert!(matches!(result, Err(e) if e.code() == ErrorCode::DrawTargetNotFound));
+        }
+
+        let draws_by_campaign = |campaign_label: &str| {
+            metrics
+                .draws
+                .with_label_values(&[campaign_label, "draw_target_not_found"])
+                .get()
+        };
+
+        assert_eq!(draws_by_campaign("unknown"), 2);
+        assert_eq!(draws_by_campaign("1"), 1);
+        assert_eq!(draws_by_campaign("3"), 0);
+    }
+
+    #[tokio::test]
+    async fn draw_service_yields_no_coupon_once_the_quota_runs_out() {
+        let repository = FakeRepository::default();
+        let cache = MemoryCache::default();
+        let metrics = Metrics::default();
+        let mut rng = seeded_rng();
+
+        repository.add_campaign(1, None);
+        repository.add_coupon_type(1, 1, 1.0, Some(1), None);
+
+        for user_id in 1..=2 {
+            repository.add_user(user_id);
+        }
+
+        let coupon = draw(&repository, &cache, &metrics, 1, 1, Utc::now(), &mut rng)
+            .await
+            .unwrap();
+
+        assert!(coupon.is_some());
+
+        let coupon = draw(&repository, &cache, &metrics, 2, 1, Utc::now(), &mut rng)
+            .await
+            .unwrap();
+
+        assert!(coupon.is_none());
+        assert_eq!(repository.draws.lock().unwrap().len(), 2);
+        assert_eq!(metrics.quota_exhausted.with_label_values(&["1"]).get(), 1);
+    }
+
+    #[tokio::test]
+    async fn draw_service_falls_back_once_the_quota_runs_out() {
+        let repository = FakeRepository::default();
+        let cache = MemoryCache::default();
+        let metrics = Metrics::default();
+        let mut rng = seeded_rng();
+
+        repository.add_campaign(1, None);
+        repository.add_coupon_type(1, 1, 1.0, Some(1), None);
+        repository.add_coupon_type(2, 1, 0.0, None, Some(1));
+        repository.set_prize_rules(
+            1,
+            PrizeRules {
+                when_exhausted: WhenExhausted::Fallback,
+                fallback_coupon_type_id: Some(2),
+                ..Default::default()
+            },
+        );
+
+        for user_id in 1..=3 {
+            repository.add_user(user_id);
+        }
+
+        let mut coupon_type_ids = vec![];
+
+        for user_id in 1..=3 {
+            let coupon = draw(
+                &repository,
+                &cache,
+                &metrics,
+                user_id,
+                1,
+                Utc::now(),
+                &mut rng,
+            )
+            .await
+            .unwrap();
+
+            coupon_type_ids.push(coupon.map(|c| c.campaign_coupon_type_id));
+        }
+
+        assert_eq!(coupon_type_ids, [Some(1), Some(2), None]);
+        assert_eq!(metrics.quota_exhausted.with_label_values(&["1"]).get(), 2);
+        assert_eq!(metrics.quota_exhausted.with_label_values(&["2"]).get(), 1);
+    }
+}
@@ -0,0 +1,153 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::types::User;
+
+mod test;
+
+/// Restricts who can draw from a campaign. Every rule that is set must pass
+#[derive(Serialize, Deserialize, ToSchema, Clone, Default, Debug)]
+pub struct EligibilityRules {
+    /// Only users whose account is at least this many days old can draw
+    #[schema(example = "30")]
+    pub min_account_age_days: Option<i32>,
+    /// Only users whose account is at most this many days old can draw, e.g. for new users
+    #[schema(example = "7")]
+    pub max_account_age_days: Option<i32>,
+    /// Only users whose phone number starts with one of these prefixes can draw
+    #[schema(example = json!(["+852", "+853"]))]
+    pub phone_prefixes: Option<Vec<String>>,
+    /// Only users of one of these membership tiers can draw
+    #[schema(example = json!(["gold", "platinum"]))]
+    pub membership_tiers: Option<Vec<String>>,
+    /// Only users with a verified phone number can draw
+    #[serde(default)]
+    pub require_verified_phone: bool,
+    /// Only these users can draw
+    pub allowed_user_ids: Option<Vec<i32>>,
+    /// These users can't draw
+    pub denied_user_ids: Option<Vec<i32>>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EligibilityRule {
+    DenyList,
+    AllowList,
+    MinAccountAge,
+    MaxAccountAge,
+    PhonePrefix,
+    MembershipTier,
+    VerifiedPhone,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
+pub struct Ineligible {
+    pub rule: EligibilityRule,
+    #[schema(example = "Account must be at most 7 days old")]
+    pub message: String,
+}
+
+impl Ineligible {
+    fn new(rule: EligibilityRule, message: String) -> Self {
+        Self { rule, message }
+    }
+}
+
+/// Strips the formatting customers commonly type into phone numbers. This is also the form phone
+/// numbers are looked up and kept unique in, as `users.phone_normalized`
+pub fn normalize_phone(phone: &str) -> String {
+    phone
+        .chars()
+        .filter(|c| !(c.is_whitespace() || matches!(c, '-' | '(' | ')')))
+        .collect()
+}
+
+impl EligibilityRules {
+    /// Returns the first rule that the user fails
+    pub fn check(&self, user: &User, now: chrono::DateTime<chrono::Utc>) -> Result<(), Ineligible> {
+        if let Some(denied_user_ids) = &self.denied_user_ids {
+            if denied_user_ids.contains(&user.id) {
+                return Err(Ineligible::new(
+                    EligibilityRule::DenyList,
+                    "User is not allowed to draw from this campaign".to_string(),
+                ));
+            }
+        }
+
+        if let Some(allowed_user_ids) = &self.allowed_user_ids {
+            if !allowed_user_ids.contains(&user.id) {
+                return Err(Ineligible::new(
+                    EligibilityRule::AllowList,
+                    "Campaign is only open to invited users".to_string(),
+                ));
+            }
+        }
+
+        let account_age_days = (now - user.created_at).num_days();
+
+        if let Some(min_account_age_days) = self.min_account_age_days {
+            if account_age_days < min_account_age_days.into() {
+                return Err(Ineligible::new(
+                    EligibilityRule::MinAccountAge,
+                    format!("Account must be at least {min_account_age_days} days old"),
+                ));
+            }
+        }
+
+        if let Some(max_account_age_days) = self.max_account_age_days {
+            if account_age_days > max_account_age_days.into() {
+                return Err(Ineligible::new(
+                    EligibilityRule::MaxAccountAge,
+                    format!("Account must be at most {max_account_age_days} days old"),
+                ));
+            }
+        }
+
+        if let Some(phone_prefixes) = &self.phone_prefixes {
+            let phone = user.phone.as_deref().map(normalize_phone);
+
+            let matches = phone.is_some_and(|phone| {
+                phone_prefixes
+                    .iter()
+                    .any(|prefix| phone.starts_with(&normalize_phone(prefix)))
+            });
+
+            if !matches {
+                return Err(Ineligible::new(
+                    EligibilityRule::PhonePrefix,
+                    format!(
+                        "Phone number must start with one of {}",
+                        phone_prefixes.join(", ")
+                    ),
+                ));
+            }
+        }
+
+        if let Some(membership_tiers) = &self.membership_tiers {
+            let matches = user
+                .membership_tier
+                .as_ref()
+                .is_some_and(|tier| membership_tiers.contains(tier));
+
+            if !matches {
+                return Err(Ineligible::new(
+                    EligibilityRule::MembershipTier,
+                    format!(
+                        "Membership tier must be one of {}",
+                        membership_tiers.join(", ")
+                    ),
+                ));
+            }
+        }
+
+        if self.require_verified_phone && user.phone_verified_at.is_none() {
+            return Err(Ineligible::new(
+                EligibilityRule::VerifiedPhone,
+                "Phone number must be verified".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+#[cfg(test)]
+mod tests {
+    use crate::{
+        eligibility::{EligibilityRule, EligibilityRules},
+        types::User,
+    };
+
+    use chrono::{Duration, Utc};
+
+    fn user(account_age_days: i64) -> User {
+        User {
+            id: 1,
+            phone: Some("+852 1234 5678".to_string()),
+            name: None,
+            email: None,
+            locale: None,
+            marketing_opt_in: false,
+            marketing_opt_in_at: None,
+            phone_verified_at: None,
+            membership_tier: None,
+            created_at: Utc::now() - Duration::days(account_age_days),
+            deleted_at: None,
+            anonymized_at: None,
+        }
+    }
+
+    fn failed_rule(rules: &EligibilityRules, user: &User) -> Option<EligibilityRule> {
+        rules.check(user, Utc::now()).err().map(|e| e.rule)
+    }
+
+    #[test]
+    fn no_rules_allow_everyone() {
+        assert_eq!(failed_rule(&EligibilityRules::default(), &user(0)), None);
+    }
+
+    #[test]
+    fn account_age() {
+        let new_users_only = EligibilityRules {
+            max_account_age_days: Some(7),
+            ..Default::default()
+        };
+
+        assert_eq!(failed_rule(&new_users_only, &user(3)), None);
+        assert_eq!(
+            failed_rule(&new_users_only, &user(30)),
+            Some(EligibilityRule::MaxAccountAge)
+        );
+
+        let loyal_users_only = EligibilityRules {
+            min_account_age_days: Some(7),
+            ..Default::default()
+        };
+
+        assert_eq!(failed_rule(&loyal_users_only, &user(30)), None);
+        assert_eq!(
+            failed_rule(&loyal_users_only, &user(3)),
+            Some(EligibilityRule::MinAccountAge)
+        );
+    }
+
+    #[test]
+    fn phone_prefix_ignores_formatting() {
+        let hong_kong_only = EligibilityRules {
+            phone_prefixes: Some(vec!["+852".to_string()]),
+            ..Default::default()
+        };
+
+        assert_eq!(failed_rule(&hong_kong_only, &user(0)), None);
+
+        let macau_only = EligibilityRules {
+            phone_prefixes: Some(vec!["+853".to_string()]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            failed_rule(&macau_only, &user(0)),
+            Some(EligibilityRule::PhonePrefix)
+        );
+
+        let mut anonymized_user = user(0);
+        anonymized_user.phone = None;
+
+        assert_eq!(
+            failed_rule(&hong_kong_only, &anonymized_user),
+            Some(EligibilityRule::PhonePrefix)
+        );
+    }
+
+    #[test]
+    fn membership_tier_and_verified_phone() {
+        let rules = EligibilityRules {
+            membership_tiers: Some(vec!["gold".to_string()]),
+            require_verified_phone: true,
+            ..Default::default()
+        };
+
+        let mut user = user(0);
+
+        assert_eq!(
+            failed_rule(&rules, &user),
+            Some(EligibilityRule::MembershipTier)
+        );
+
+        user.membership_tier = Some("gold".to_string());
+
+        assert_eq!(
+            failed_rule(&rules, &user),
+            Some(EligibilityRule::VerifiedPhone)
+        );
+
+        user.phone_verified_at = Some(Utc::now());
+
+        assert_eq!(failed_rule(&rules, &user), None);
+    }
+
+    #[test]
+    fn deny_list_takes_precedence_over_allow_list() {
+        let rules = EligibilityRules {
+            allowed_user_ids: Some(vec![1, 2]),
+            denied_user_ids: Some(vec![1]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            failed_rule(&rules, &user(0)),
+            Some(EligibilityRule::DenyList)
+        );
+
+        let mut user = user(0);
+        user.id = 2;
+
+        assert_eq!(failed_rule(&rules, &user), None);
+
+        user.id = 3;
+
+        assert_eq!(failed_rule(&rules, &user), Some(EligibilityRule::AllowList));
+    }
+}
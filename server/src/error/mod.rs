@@ -0,0 +1,328 @@
+use axum::{
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    http::header,
+    response::{IntoResponse, Response},
+    Json,
+};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+mod test;
+
+/// Broad category of an error, each maps to one HTTP status
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorType {
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    Conflict,
+    UnprocessableEntity,
+    TooManyRequests,
+    Unavailable,
+    Internal,
+}
+
+impl ErrorType {
+    pub fn status(self) -> StatusCode {
+        match self {
+            ErrorType::BadRequest => StatusCode::BAD_REQUEST,
+            ErrorType::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorType::Forbidden => StatusCode::FORBIDDEN,
+            ErrorType::NotFound => StatusCode::NOT_FOUND,
+            ErrorType::Conflict => StatusCode::CONFLICT,
+            ErrorType::UnprocessableEntity => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorType::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            ErrorType::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorType::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Stable, machine-readable error codes that clients can switch on
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request body, path or query string is malformed
+    InvalidRequest,
+    /// A phone number is blank or longer than 20 characters
+    InvalidPhone,
+    InvalidEmail,
+    InvalidLocale,
+    InvalidProbabilities,
+    /// A quota of a coupon type is negative
+    InvalidQuotas,
+    InvalidEligibilityRules,
+    /// E.g. falling back to a coupon type when there is none to fall back to
+    InvalidPrizeRules,
+    AdminTokenMissing,
+    AdminTokenInvalid,
+    ApiKeyMissing,
+    ApiKeyInvalid,
+    ApiKeyUnscoped,
+    ApiKeyNotFound,
+    UserNotFound,
+    CampaignNotFound,
+    /// The campaign or the user to draw for doesn't exist
+    DrawTargetNotFound,
+    /// The campaign doesn't have any coupon types to draw from
+    NoCouponTypes,
+    PhoneTaken,
+    AlreadyDrawn,
+    NotEligible,
+    CouponNotRedeemable,
+    /// A request with the same `Idempotency-Key` is still in progress
+    IdempotencyKeyInUse,
+    /// The `Idempotency-Key` was already used for a different request
+    IdempotencyKeyReused,
+    /// Too many draws from the user or the client IP, retry after the `Retry-After` header
+    RateLimited,
+    DatabaseError,
+    DatabaseUnavailable,
+    CacheError,
+    CacheUnavailable,
+    InternalError,
+}
+
+impl ErrorCode {
+    pub fn error_type(self) -> ErrorType {
+        match self {
+            ErrorCode::InvalidRequest
+            | ErrorCode::InvalidPhone
+            | ErrorCode::InvalidEmail
+            | ErrorCode::InvalidLocale => ErrorType::BadRequest,
+            ErrorCode::AdminTokenMissing
+            | ErrorCode::AdminTokenInvalid
+            | ErrorCode::ApiKeyMissing
+            | ErrorCode::ApiKeyInvalid => ErrorType::Unauthorized,
+            ErrorCode::NotEligible => ErrorType::Forbidden,
+            ErrorCode::ApiKeyNotFound
+            | ErrorCode::UserNotFound
+            | ErrorCode::CampaignNotFound
+            | ErrorCode::DrawTargetNotFound
+            | ErrorCode::NoCouponTypes => ErrorType::NotFound,
+            ErrorCode::InvalidProbabilities
+            | ErrorCode::InvalidQuotas
+            | ErrorCode::InvalidEligibilityRules
+            | ErrorCode::InvalidPrizeRules
+            | ErrorCode::ApiKeyUnscoped
+            | ErrorCode::PhoneTaken
+            | ErrorCode::AlreadyDrawn
+            | ErrorCode::CouponNotRedeemable
+            | ErrorCode::IdempotencyKeyInUse => ErrorType::Conflict,
+            ErrorCode::IdempotencyKeyReused => ErrorType::UnprocessableEntity,
+            ErrorCode::RateLimited => ErrorType::TooManyRequests,
+            ErrorCode::DatabaseUnavailable | ErrorCode::CacheUnavailable => ErrorType::Unavailable,
+            ErrorCode::DatabaseError | ErrorCode::CacheError | ErrorCode::InternalError => {
+                ErrorType::Internal
+            }
+        }
+    }
+}
+
+/// As serialized, e.g. `already_drawn`
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match serde_json::to_value(self) {
+            Ok(serde_json::Value::String(code)) => f.write_str(&code),
+            _ => Err(std::fmt::Error),
+        }
+    }
+}
+
+/// The body of every error response, loosely following RFC 7807 problem details
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ErrorBody {
+    #[serde(rename = "type")]
+    #[schema(example = "conflict")]
+    pub error_type: ErrorType,
+    #[schema(example = "already_drawn")]
+    pub code: ErrorCode,
+    /// Human readable, not meant to be parsed
+    #[schema(example = "User has already drawn from this campaign today")]
+    pub message: String,
+    /// Extra context specific to the code, e.g. the failed eligibility rule for `not_eligible`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>, example = json!({ "rule": "max_account_age" }))]
+    pub details: Option<serde_json::Value>,
+}
+
+/// Every way a handler can fail. Request errors are reported to the client as is, whereas
+/// failures of the DB, the cache or the service itself are logged and reported vaguely
+#[derive(Debug)]
+pub enum AppError {
+    Request {
+        code: ErrorCode,
+        message: String,
+        details: Option<serde_json::Value>,
+    },
+    Database(sqlx::Error),
+    Cache(redis::RedisError),
+    CachePool(deadpool_redis::PoolError),
+    RateLimited {
+        retry_after: std::time::Duration,
+    },
+    Internal(String),
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(e: sqlx::Error) -> Self {
+        AppError::Database(e)
+    }
+}
+
+impl From<redis::RedisError> for AppError {
+    fn from(e: redis::RedisError) -> Self {
+        AppError::Cache(e)
+    }
+}
+
+impl From<deadpool_redis::PoolError> for AppError {
+    fn from(e: deadpool_redis::PoolError) -> Self {
+        AppError::CachePool(e)
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        AppError::new(ErrorCode::InvalidRequest, rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for AppError {
+    fn from(rejection: PathRejection) -> Self {
+        AppError::new(ErrorCode::InvalidRequest, rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        AppError::new(ErrorCode::InvalidRequest, rejection.body_text())
+    }
+}
+
+impl AppError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        AppError::Request {
+            code,
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    pub fn with_details(self, new_details: impl Serialize) -> Self {
+        match self {
+            AppError::Request { code, message, .. } => AppError::Request {
+                code,
+                message,
+                details: serde_json::to_value(new_details).ok(),
+            },
+            other => other,
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.code().error_type().status()
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AppError::Request { code, .. } => *code,
+            AppError::Database(
+                sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed | sqlx::Error::Io(_),
+            ) => ErrorCode::DatabaseUnavailable,
+            AppError::Database(_) => ErrorCode::DatabaseError,
+            AppError::Cache(e)
+                if e.is_io_error() || e.is_timeout() || e.is_connection_dropped() =>
+            {
+                ErrorCode::CacheUnavailable
+            }
+            AppError::Cache(_) => ErrorCode::CacheError,
+            AppError::CachePool(_) => ErrorCode::CacheUnavailable,
+            AppError::RateLimited { .. } => ErrorCode::RateLimited,
+            AppError::Internal(_) => ErrorCode::InternalError,
+        }
+    }
+
+    /// Infrastructure errors are deliberately vague so that no internals leak to the client
+    fn message(&self) -> String {
+        match self {
+            AppError::Request { message, .. } => message.clone(),
+            AppError::RateLimited { retry_after } => format!(
+                "Too many draws, retry in {} seconds",
+                retry_after_secs(*retry_after)
+            ),
+            _ => match self.code() {
+                ErrorCode::DatabaseUnavailable => "Database is temporarily unavailable",
+                ErrorCode::CacheError => "Cache error",
+                ErrorCode::CacheUnavailable => "Cache is temporarily unavailable",
+                ErrorCode::DatabaseError => "Database error",
+                _ => "Internal server error",
+            }
+            .to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::Request { code, message, .. } => write!(f, "{code:?}: {message}"),
+            AppError::Database(e) => write!(f, "Database error: {e}"),
+            AppError::Cache(e) => write!(f, "Cache error: {e}"),
+            AppError::CachePool(e) => write!(f, "Cache pool error: {e}"),
+            AppError::RateLimited { retry_after } => write!(f, "Rate limited for {retry_after:?}"),
+            AppError::Internal(e) => write!(f, "Internal error: {e}"),
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+
+        if status.is_server_error() {
+            tracing::error!(code = ?self.code(), "{self}");
+        }
+
+        let retry_after = match &self {
+            AppError::RateLimited { retry_after } => Some(retry_after_secs(*retry_after)),
+            _ => None,
+        };
+
+        let body = ErrorBody {
+            error_type: self.code().error_type(),
+            code: self.code(),
+            message: self.message(),
+            details: match self {
+                AppError::Request { details, .. } => details,
+                AppError::RateLimited { .. } => {
+                    retry_after.map(|secs| serde_json::json!({ "retry_after_secs": secs }))
+                }
+                _ => None,
+            },
+        };
+
+        let mut response = (
+            status,
+            [(header::CONTENT_TYPE, "application/problem+json")],
+            Json(body),
+        )
+            .into_response();
+
+        if let Some(secs) = retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, secs.into());
+        }
+
+        response
+    }
+}
+
+/// Whole seconds, as `Retry-After` takes, rounded up so that retrying on time succeeds
+fn retry_after_secs(retry_after: std::time::Duration) -> u64 {
+    retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0)
+}
@@ -0,0 +1,93 @@
+#[cfg(test)]
+mod tests {
+    use crate::error::{AppError, ErrorCode};
+    use crate::testing::TestContext;
+
+    use axum::{
+        body::Body,
+        http::{self, Method, Request},
+        response::IntoResponse,
+    };
+    use hyper::StatusCode;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn infrastructure_errors_map_to_5xx_with_stable_codes() {
+        let response = AppError::Database(sqlx::Error::PoolTimedOut).into_response();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(body["type"], "unavailable");
+        assert_eq!(body["code"], "database_unavailable");
+        assert!(body["message"].is_string());
+
+        let error = AppError::Database(sqlx::Error::RowNotFound);
+        assert_eq!(error.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(error.code(), ErrorCode::DatabaseError);
+
+        let error = AppError::Cache(redis::RedisError::from(std::io::Error::from(
+            std::io::ErrorKind::ConnectionRefused,
+        )));
+        assert_eq!(error.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(error.code(), ErrorCode::CacheUnavailable);
+
+        let error = AppError::Cache(redis::RedisError::from((
+            redis::ErrorKind::TypeError,
+            "Response was of incompatible type",
+        )));
+        assert_eq!(error.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(error.code(), ErrorCode::CacheError);
+    }
+
+    #[tokio::test]
+    async fn malformed_requests_get_the_same_error_body() {
+        let ctx = TestContext::new().await;
+        let app = ctx.app.clone();
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/draw")
+                    .method(Method::POST)
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(r#"{ "user_id": "not a number" }"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            response.headers()[http::header::CONTENT_TYPE],
+            "application/problem+json"
+        );
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(body["type"], "bad_request");
+        assert_eq!(body["code"], "invalid_request");
+        assert!(body["message"].is_string());
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/user/not-a-number")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(body["code"], "invalid_request");
+    }
+}
@@ -0,0 +1,58 @@
+//! Drop-in replacements of axum's extractors that reject malformed requests with an
+//! [`ErrorBody`](crate::error::ErrorBody) instead of plain text, and a body reader for middleware
+//! that has to look at the body before the handler does
+
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::{FromRequest, FromRequestParts},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+
+use crate::error::{AppError, ErrorCode};
+
+/// The requests that middleware reads are small, anything bigger isn't worth buffering
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(AppError))]
+pub struct Json<T>(pub T);
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(AppError))]
+pub struct Path<T>(pub T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(AppError))]
+pub struct Query<T>(pub T);
+
+/// Buffers the request body, up to [`MAX_BODY_BYTES`]
+pub async fn read_body(mut body: Body) -> Result<Bytes, AppError> {
+    let mut bytes = Vec::new();
+
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| {
+            AppError::new(
+                ErrorCode::InvalidRequest,
+                format!("Failed to read the request body: {e}"),
+            )
+        })?;
+
+        if bytes.len() + chunk.len() > MAX_BODY_BYTES {
+            return Err(AppError::new(
+                ErrorCode::InvalidRequest,
+                format!("Request body exceeds {MAX_BODY_BYTES} bytes"),
+            ));
+        }
+
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(bytes.into())
+}
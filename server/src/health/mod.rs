@@ -0,0 +1,166 @@
+//! Liveness and readiness probes for the orchestrator
+
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::migrate::{self, MigrationState};
+use crate::store::Store;
+
+mod test;
+
+/// A probe has to answer before the orchestrator gives up on it, however slow a dependency is
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum Readiness {
+    Ready,
+    /// Serving, but without the Redis cache, i.e. draws are checked against the DB only
+    Degraded,
+    NotReady,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub(super) struct DependencyCheck {
+    pub ok: bool,
+    pub latency_ms: u64,
+    /// Why the check failed
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub(super) struct ReadinessChecks {
+    pub db: DependencyCheck,
+    /// Absent unless the cache is backed by Redis
+    pub redis: Option<DependencyCheck>,
+    /// Whether every migration known to the binary has been applied
+    pub migrations: DependencyCheck,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub(super) struct ReadinessResult {
+    pub status: Readiness,
+    pub checks: ReadinessChecks,
+}
+
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    responses(
+        (status = 200, description = "The process is up")
+    )
+)]
+pub(super) async fn get_health() -> StatusCode {
+    StatusCode::OK
+}
+
+/// Redis being down doesn't make the service unready, as draws fall back to the DB
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    responses(
+        (status = 200, description = "The DB is reachable and migrated. Redis may be unreachable, in which case the status is degraded", body = ReadinessResult),
+        (status = 503, description = "The DB is unreachable or has pending migrations", body = ReadinessResult)
+    )
+)]
+pub(super) async fn get_readiness(State(store): State<Store>) -> Response {
+    let (db, redis, migrations) = tokio::join!(
+        check(async {
+            sqlx::query("select 1;")
+                .execute(&store.db_pool)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        }),
+        async {
+            match &store.redis_pool {
+                Some(redis_pool) => Some(
+                    check(async {
+                        let mut redis = redis_pool.get().await.map_err(|e| e.to_string())?;
+
+                        redis::cmd("PING")
+                            .query_async::<_, String>(&mut redis)
+                            .await
+                            .map(|_| ())
+                            .map_err(|e| e.to_string())
+                    })
+                    .await,
+                ),
+                None => None,
+            }
+        },
+        check(async {
+            let statuses = migrate::status(&store.db_pool)
+                .await
+                .map_err(|e| e.to_string())?;
+
+            // Migrations unknown to this binary were applied by a newer one, e.g. mid rollout, and
+            // don't stop this one from serving
+            let unapplied: Vec<_> = statuses
+                .iter()
+                .filter(|s| matches!(s.state, MigrationState::Pending | MigrationState::Failed))
+                .map(|s| format!("{} is {}", s.version, s.state))
+                .collect();
+
+            if unapplied.is_empty() {
+                Ok(())
+            } else {
+                Err(unapplied.join(", "))
+            }
+        }),
+    );
+
+    let status = if !db.ok || !migrations.ok {
+        Readiness::NotReady
+    } else if redis.as_ref().is_some_and(|redis| !redis.ok) {
+        Readiness::Degraded
+    } else {
+        Readiness::Ready
+    };
+
+    if status != Readiness::Ready {
+        tracing::warn!(?status, ?db, ?redis, ?migrations, "Not fully ready");
+    }
+
+    let status_code = match status {
+        Readiness::NotReady => StatusCode::SERVICE_UNAVAILABLE,
+        Readiness::Ready | Readiness::Degraded => StatusCode::OK,
+    };
+
+    (
+        status_code,
+        Json(ReadinessResult {
+            status,
+            checks: ReadinessChecks {
+                db,
+                redis,
+                migrations,
+            },
+        }),
+    )
+        .into_response()
+}
+
+async fn check(probe: impl Future<Output = Result<(), String>>) -> DependencyCheck {
+    let start = Instant::now();
+
+    let result = match tokio::time::timeout(CHECK_TIMEOUT, probe).await {
+        Ok(result) => result,
+        Err(_) => Err(format!("Timed out after {CHECK_TIMEOUT:?}")),
+    };
+
+    DependencyCheck {
+        ok: result.is_ok(),
+        latency_ms: start.elapsed().as_millis() as u64,
+        error: result.err(),
+    }
+}
@@ -0,0 +1,134 @@
+#[cfg(test)]
+mod tests {
+    use crate::{cache::CacheBackend, testing::TestContext};
+
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+        Router,
+    };
+    use tower::ServiceExt;
+
+    async fn get_readiness(app: &Router) -> (StatusCode, serde_json::Value) {
+        let readiness_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/readyz")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let status = readiness_response.status();
+
+        let body = hyper::body::to_bytes(readiness_response.into_body())
+            .await
+            .unwrap();
+
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn ready_once_the_db_and_redis_are_reachable_and_migrated() {
+        let ctx = TestContext::with_redis().await;
+        let app = ctx.app.clone();
+
+        let health_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/healthz")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(health_response.status(), StatusCode::OK);
+
+        let (status, body) = get_readiness(&app).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ready");
+        assert_eq!(body["checks"]["db"]["ok"], true);
+        assert_eq!(body["checks"]["redis"]["ok"], true);
+        assert_eq!(body["checks"]["migrations"]["ok"], true);
+
+        // Without Redis there is nothing to check
+
+        let ctx = TestContext::new().await;
+
+        let (status, body) = get_readiness(&ctx.app).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ready");
+        assert!(body["checks"]["redis"].is_null());
+    }
+
+    #[tokio::test]
+    async fn degraded_while_redis_is_unreachable() {
+        let ctx = TestContext::with_config(|config| {
+            config.cache.backend = CacheBackend::Redis;
+            // Nothing listens on the discard port
+            config.redis.url = Some("redis://127.0.0.1:9/".to_string());
+        })
+        .await;
+
+        let (status, body) = get_readiness(&ctx.app).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "degraded");
+        assert_eq!(body["checks"]["db"]["ok"], true);
+        assert_eq!(body["checks"]["redis"]["ok"], false);
+        assert!(body["checks"]["redis"]["error"].is_string());
+    }
+
+    #[tokio::test]
+    async fn not_ready_with_pending_migrations() {
+        let ctx = TestContext::new().await;
+
+        sqlx::query!(
+            "--sql
+                delete from _sqlx_migrations
+                where version = (select max(version) from _sqlx_migrations);
+            "
+        )
+        .execute(&ctx.store.db_pool)
+        .await
+        .unwrap();
+
+        let (status, body) = get_readiness(&ctx.app).await;
+
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "not_ready");
+        assert_eq!(body["checks"]["db"]["ok"], true);
+        assert_eq!(body["checks"]["migrations"]["ok"], false);
+        assert!(body["checks"]["migrations"]["error"]
+            .as_str()
+            .unwrap()
+            .contains("pending"));
+    }
+
+    #[tokio::test]
+    async fn ready_with_migrations_from_a_newer_binary() {
+        let ctx = TestContext::new().await;
+
+        sqlx::query!(
+            "--sql
+                insert into _sqlx_migrations
+                    (version, description, success, checksum, execution_time)
+                values (99999999, 'from a newer binary', true, '\\x00', 0);
+            "
+        )
+        .execute(&ctx.store.db_pool)
+        .await
+        .unwrap();
+
+        let (status, body) = get_readiness(&ctx.app).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["checks"]["migrations"]["ok"], true);
+    }
+}
@@ -0,0 +1,244 @@
+//! `Idempotency-Key` support, so that a client retrying a draw or a redemption after a timeout gets
+//! the original response instead of a conflict. Responses are kept in the DB, as a retry can land
+//! on any node
+
+use std::time::Duration;
+
+use axum::{
+    body::{self, Body},
+    extract::State,
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::api_key::AuthorizedApiKey;
+use crate::error::{AppError, ErrorCode};
+use crate::extract::read_body;
+use crate::repository::{IdempotencyClaim, IdempotencyRepository, PgRepository, StoredResponse};
+use crate::store::Store;
+
+mod test;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Set on replayed responses
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "idempotent-replayed";
+
+const MAX_KEY_LENGTH: usize = 255;
+
+/// A claimed key whose request hasn't completed in this long was abandoned, e.g. by a node that
+/// was killed. Well past the DB's acquire timeout, so no request is still running by then
+const ABANDON_AFTER: Duration = Duration::from_secs(60);
+
+/// How often expired keys are deleted
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// The part of the body of a request without an API key that identifies its caller
+#[derive(Deserialize)]
+struct UserScoped {
+    user_id: i64,
+}
+
+/// Replays the stored response to a request with an `Idempotency-Key` header that has been made
+/// before, or runs the request and stores its response. Server errors and rate limiting aren't
+/// stored, so that the request can be retried. Requests without the header are let through as is
+pub(super) async fn idempotent(
+    State(store): State<Store>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Result<Response, AppError> {
+    let Some(key) = request.headers().get(IDEMPOTENCY_KEY_HEADER) else {
+        return Ok(next.run(request).await);
+    };
+
+    let key = match key.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => key.to_string(),
+        _ => {
+            let message = format!(
+                "{IDEMPOTENCY_KEY_HEADER} must be 1 to {MAX_KEY_LENGTH} visible ASCII characters"
+            );
+
+            return Err(AppError::new(ErrorCode::InvalidRequest, message));
+        }
+    };
+
+    let (parts, body) = request.into_parts();
+    let body = read_body(body).await?;
+
+    // Keys are only unique per caller, so that callers who happen to pick the same key get a
+    // response of their own. Callers without an API key are told apart by the user the request is
+    // made for, so that one user's response is never replayed to another
+    let scope = match parts.extensions.get::<AuthorizedApiKey>() {
+        Some(api_key) => format!("api_key:{}", api_key.id),
+        None => {
+            let user_id = serde_json::from_slice::<UserScoped>(&body)
+                .map(|request| request.user_id.to_string())
+                .unwrap_or_default();
+
+            format!("route:{} {} user:{user_id}", parts.method, parts.uri.path())
+        }
+    };
+
+    // Within its scope, a key only stands for the request it was first used with
+    let mut hasher = Sha256::new();
+    hasher.update(parts.method.as_str());
+    hasher.update(b"\n");
+    hasher.update(parts.uri.path());
+    hasher.update(b"\n");
+    hasher.update(&body);
+    let fingerprint = hex::encode(hasher.finalize());
+
+    let repository = PgRepository::new(store.db_pool.clone(), store.metrics.clone());
+
+    let claim = repository
+        .claim(
+            &scope,
+            &key,
+            &fingerprint,
+            store.config.idempotency.window(),
+            ABANDON_AFTER,
+        )
+        .await?;
+
+    let response = match claim {
+        IdempotencyClaim::Claimed => {
+            let request = Request::from_parts(parts, Body::from(body));
+
+            // On a task of its own, so that the outcome is stored even if the client disconnects
+            // and the request future is dropped halfway. Otherwise a coupon that was won would be
+            // lost, with retries told that the request is in progress until it is abandoned
+            let task = tokio::spawn({
+                let repository = repository.clone();
+                let scope = scope.clone();
+                let key = key.clone();
+
+                async move { run_and_store(&repository, &scope, &key, next.run(request).await).await }
+            });
+
+            return match task.await {
+                Ok(response) => response,
+                Err(e) => {
+                    release(&repository, &scope, &key).await;
+
+                    Err(AppError::Internal(format!(
+                        "Idempotent request failed: {e}"
+                    )))
+                }
+            };
+        }
+        IdempotencyClaim::InProgress { fingerprint: f }
+        | IdempotencyClaim::Completed { fingerprint: f, .. }
+            if f != fingerprint =>
+        {
+            return Err(AppError::new(
+                ErrorCode::IdempotencyKeyReused,
+                format!("{IDEMPOTENCY_KEY_HEADER} {key} was already used for a different request"),
+            ));
+        }
+        IdempotencyClaim::InProgress { .. } => {
+            return Err(AppError::new(
+                ErrorCode::IdempotencyKeyInUse,
+                format!("A request with {IDEMPOTENCY_KEY_HEADER} {key} is still in progress"),
+            ));
+        }
+        IdempotencyClaim::Completed { response, .. } => response,
+    };
+
+    tracing::info!(key, status = response.status, "Replaying response");
+
+    let status = StatusCode::from_u16(response.status)
+        .map_err(|e| AppError::Internal(format!("Stored status is invalid: {e}")))?;
+
+    let mut replay = (status, response.body).into_response();
+    let headers = replay.headers_mut();
+
+    headers.remove(header::CONTENT_TYPE);
+
+    if let Some(content_type) = response
+        .content_type
+        .and_then(|content_type| HeaderValue::from_str(&content_type).ok())
+    {
+        headers.insert(header::CONTENT_TYPE, content_type);
+    }
+
+    headers.insert(IDEMPOTENT_REPLAYED_HEADER, HeaderValue::from_static("true"));
+
+    Ok(replay)
+}
+
+async fn run_and_store(
+    repository: &PgRepository,
+    scope: &str,
+    key: &str,
+    response: Response,
+) -> Result<Response, AppError> {
+    let status = response.status();
+
+    if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+        release(repository, scope, key).await;
+
+        return Ok(response);
+    }
+
+    let (parts, response_body) = response.into_parts();
+
+    let response_body = match hyper::body::to_bytes(response_body).await {
+        Ok(response_body) => response_body,
+        Err(e) => {
+            release(repository, scope, key).await;
+
+            return Err(AppError::Internal(format!(
+                "Failed to buffer the response: {e}"
+            )));
+        }
+    };
+
+    let stored = StoredResponse {
+        status: status.as_u16(),
+        content_type: parts
+            .headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .map(str::to_string),
+        body: response_body.to_vec(),
+    };
+
+    // The request has been carried out by now, so its response is returned regardless. Until the
+    // claim is abandoned, retries are told that the request is in progress
+    if let Err(e) = repository.complete(scope, key, &stored).await {
+        tracing::error!(key, error = %e, "Failed to store the response of an idempotent request");
+    }
+
+    Ok(Response::from_parts(
+        parts,
+        body::boxed(body::Full::from(response_body)),
+    ))
+}
+
+/// Failing that, the claim is released once abandoned
+async fn release(repository: &PgRepository, scope: &str, key: &str) {
+    if let Err(e) = repository.release(scope, key).await {
+        tracing::warn!(key, error = %e, "Failed to release an idempotency key");
+    }
+}
+
+/// Deletes expired keys every [`CLEANUP_INTERVAL`]. Expired keys are ignored anyway, this only
+/// keeps the table small
+pub fn spawn_cleanup(store: Store) {
+    tokio::spawn(async move {
+        let repository = PgRepository::new(store.db_pool.clone(), store.metrics.clone());
+        let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            match repository.delete_expired().await {
+                Ok(deleted) => tracing::debug!(deleted, "Deleted expired idempotency keys"),
+                Err(e) => tracing::warn!(error = %e, "Failed to delete expired idempotency keys"),
+            }
+        }
+    });
+}
@@ -0,0 +1,402 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        admin::ADMIN_TOKEN_HEADER,
+        api_key::API_KEY_HEADER,
+        idempotency::{IDEMPOTENCY_KEY_HEADER, IDEMPOTENT_REPLAYED_HEADER},
+        testing::{post_json, TestContext, ADMIN_TOKEN},
+    };
+
+    use axum::{
+        http::{self, StatusCode},
+        Router,
+    };
+    use serde_json::json;
+    use uuid::Uuid;
+
+    /// Creates a campaign whose every draw wins a coupon, and a user, and returns their ids
+    async fn campaign_and_user(app: &Router) -> (i64, i64) {
+        let (status, _, campaign) = post_json(
+            app,
+            "/campaign",
+            &[],
+            json!({
+                "coupon_types": [{
+                    "description": "100%",
+                    "probability": 1.0,
+                    "total_quota": null,
+                    "daily_quota": null
+                }]
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let (status, _, user) = post_json(
+            app,
+            "/user",
+            &[],
+            json!({ "phone": &Uuid::new_v4().to_string()[..20] }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        (
+            campaign["id"].as_i64().unwrap(),
+            user["id"].as_i64().unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn retried_draws_get_the_original_response() {
+        let ctx = TestContext::new().await;
+        let app = ctx.app.clone();
+
+        let (campaign_id, user_id) = campaign_and_user(&app).await;
+        let draw_payload = json!({ "campaign_id": campaign_id, "user_id": user_id });
+        let key = Uuid::new_v4().to_string();
+
+        let (status, headers, draw) = post_json(
+            &app,
+            "/draw",
+            &[(IDEMPOTENCY_KEY_HEADER, &key)],
+            draw_payload.clone(),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(draw["maybe_coupon"]["id"].is_i64());
+        assert!(!headers.contains_key(IDEMPOTENT_REPLAYED_HEADER));
+
+        // The retry gets the coupon that was won, rather than a conflict
+
+        let (status, headers, retried_draw) = post_json(
+            &app,
+            "/draw",
+            &[(IDEMPOTENCY_KEY_HEADER, &key)],
+            draw_payload.clone(),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(retried_draw, draw);
+        assert_eq!(headers[IDEMPOTENT_REPLAYED_HEADER], "true");
+        assert_eq!(headers[http::header::CONTENT_TYPE], "application/json");
+
+        // Without the key, the draw is made again and rejected
+
+        let (status, _, body) = post_json(&app, "/draw", &[], draw_payload).await;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["code"], "already_drawn");
+
+        // The key can't be reused for another request of the user
+
+        let (status, _, body) = post_json(
+            &app,
+            "/draw",
+            &[(IDEMPOTENCY_KEY_HEADER, &key)],
+            json!({ "campaign_id": campaign_id + 1, "user_id": user_id }),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "idempotency_key_reused");
+
+        let draws = sqlx::query_scalar!(
+            r#"--sql
+                select count(*) as "count!" from draws where user_id = $1;
+            "#,
+            user_id as i32
+        )
+        .fetch_one(&ctx.store.db_pool)
+        .await
+        .unwrap();
+
+        assert_eq!(draws, 1);
+    }
+
+    #[tokio::test]
+    async fn keys_are_per_user_for_draws() {
+        let ctx = TestContext::new().await;
+        let app = ctx.app.clone();
+
+        let (campaign_id, user_id) = campaign_and_user(&app).await;
+        let (_, other_user_id) = campaign_and_user(&app).await;
+        let key = Uuid::new_v4().to_string();
+
+        // Users whose clients pick the same key each get a coupon of their own
+
+        let mut coupon_ids = vec![];
+
+        for user_id in [user_id, other_user_id] {
+            let (status, headers, draw) = post_json(
+                &app,
+                "/draw",
+                &[(IDEMPOTENCY_KEY_HEADER, &key)],
+                json!({ "campaign_id": campaign_id, "user_id": user_id }),
+            )
+            .await;
+
+            assert_eq!(status, StatusCode::OK);
+            assert!(!headers.contains_key(IDEMPOTENT_REPLAYED_HEADER));
+
+            coupon_ids.push(draw["maybe_coupon"]["id"].as_i64().unwrap());
+        }
+
+        assert_ne!(coupon_ids[0], coupon_ids[1]);
+    }
+
+    #[tokio::test]
+    async fn draws_complete_when_the_client_disconnects() {
+        let ctx = TestContext::new().await;
+        let app = ctx.app.clone();
+
+        let (campaign_id, user_id) = campaign_and_user(&app).await;
+        let draw_payload = json!({ "campaign_id": campaign_id, "user_id": user_id });
+        let key = Uuid::new_v4().to_string();
+
+        // Holds the draw up on deducting the quota, while the client gives up on it
+
+        let mut lock = ctx.store.db_pool.begin().await.unwrap();
+
+        sqlx::query!(
+            "--sql
+                select id from campaign_coupon_types where campaign_id = $1 for update;
+            ",
+            campaign_id as i32
+        )
+        .fetch_all(&mut *lock)
+        .await
+        .unwrap();
+
+        let disconnected = tokio::time::timeout(
+            Duration::from_millis(500),
+            post_json(
+                &app,
+                "/draw",
+                &[(IDEMPOTENCY_KEY_HEADER, &key)],
+                draw_payload.clone(),
+            ),
+        )
+        .await;
+
+        assert!(disconnected.is_err());
+
+        lock.commit().await.unwrap();
+
+        // The draw is carried out regardless, and its coupon handed to the retry
+
+        let mut retries = 0;
+
+        let draw = loop {
+            let (status, headers, body) = post_json(
+                &app,
+                "/draw",
+                &[(IDEMPOTENCY_KEY_HEADER, &key)],
+                draw_payload.clone(),
+            )
+            .await;
+
+            if status == StatusCode::CONFLICT && body["code"] == "idempotency_key_in_use" {
+                retries += 1;
+                assert!(retries < 50, "The draw never completed");
+
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(headers[IDEMPOTENT_REPLAYED_HEADER], "true");
+
+            break body;
+        };
+
+        assert!(draw["maybe_coupon"]["id"].is_i64());
+    }
+
+    #[tokio::test]
+    async fn keys_are_reclaimed_once_expired_or_abandoned() {
+        let ctx = TestContext::new().await;
+        let app = ctx.app.clone();
+
+        let (campaign_id, user_id) = campaign_and_user(&app).await;
+        let key = Uuid::new_v4().to_string();
+
+        // Error responses are replayed too, as long as they aren't transient
+
+        let draw_payload = json!({ "campaign_id": campaign_id, "user_id": 0 });
+
+        for _ in 0..2 {
+            let (status, _, body) = post_json(
+                &app,
+                "/draw",
+                &[(IDEMPOTENCY_KEY_HEADER, &key)],
+                draw_payload.clone(),
+            )
+            .await;
+
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert_eq!(body["code"], "draw_target_not_found");
+        }
+
+        // Once expired, the key is free for a new request
+
+        sqlx::query!(
+            "--sql
+                update idempotency_keys
+                set expires_at = now() - interval '1 second'
+                where key = $1;
+            ",
+            key
+        )
+        .execute(&ctx.store.db_pool)
+        .await
+        .unwrap();
+
+        let draw_payload = json!({ "campaign_id": campaign_id, "user_id": user_id });
+
+        let (status, _, _) = post_json(
+            &app,
+            "/draw",
+            &[(IDEMPOTENCY_KEY_HEADER, &key)],
+            draw_payload.clone(),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+
+        // A request still in progress holds the key, until it is deemed abandoned
+
+        sqlx::query!(
+            "--sql
+                update idempotency_keys
+                set response_status = null
+                where key = $1;
+            ",
+            key
+        )
+        .execute(&ctx.store.db_pool)
+        .await
+        .unwrap();
+
+        let (status, _, body) = post_json(
+            &app,
+            "/draw",
+            &[(IDEMPOTENCY_KEY_HEADER, &key)],
+            draw_payload.clone(),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["code"], "idempotency_key_in_use");
+
+        sqlx::query!(
+            "--sql
+                update idempotency_keys
+                set created_at = now() - interval '1 hour'
+                where key = $1;
+            ",
+            key
+        )
+        .execute(&ctx.store.db_pool)
+        .await
+        .unwrap();
+
+        let (status, _, body) = post_json(
+            &app,
+            "/draw",
+            &[(IDEMPOTENCY_KEY_HEADER, &key)],
+            draw_payload,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["code"], "already_drawn");
+    }
+
+    #[tokio::test]
+    async fn retried_redemptions_get_the_original_response() {
+        let ctx = TestContext::new().await;
+        let app = ctx.app.clone();
+
+        let (campaign_id, user_id) = campaign_and_user(&app).await;
+
+        let (status, _, draw) = post_json(
+            &app,
+            "/draw",
+            &[],
+            json!({ "campaign_id": campaign_id, "user_id": user_id }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let coupon_id = draw["maybe_coupon"]["id"].as_i64().unwrap();
+
+        let mut api_keys = vec![];
+
+        for _ in 0..2 {
+            let (status, _, created) = post_json(
+                &app,
+                "/api-key",
+                &[(ADMIN_TOKEN_HEADER, ADMIN_TOKEN)],
+                json!({ "merchant": Uuid::new_v4().to_string(), "campaign_ids": [campaign_id] }),
+            )
+            .await;
+            assert_eq!(status, StatusCode::CREATED);
+
+            api_keys.push(created["key"].as_str().unwrap().to_string());
+        }
+
+        let redeem_payload = json!({ "coupon_id": coupon_id, "user_id": user_id });
+        let key = Uuid::new_v4().to_string();
+
+        let (status, _, coupon) = post_json(
+            &app,
+            "/redeem",
+            &[
+                (API_KEY_HEADER, &api_keys[0]),
+                (IDEMPOTENCY_KEY_HEADER, &key),
+            ],
+            redeem_payload.clone(),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(coupon["redeemed"], true);
+
+        let (status, headers, retried_coupon) = post_json(
+            &app,
+            "/redeem",
+            &[
+                (API_KEY_HEADER, &api_keys[0]),
+                (IDEMPOTENCY_KEY_HEADER, &key),
+            ],
+            redeem_payload.clone(),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(retried_coupon, coupon);
+        assert_eq!(headers[IDEMPOTENT_REPLAYED_HEADER], "true");
+
+        // Keys are per API key, another merchant using the same one gets a response of its own
+        // rather than the replay or a conflict
+
+        let (status, headers, body) = post_json(
+            &app,
+            "/redeem",
+            &[
+                (API_KEY_HEADER, &api_keys[1]),
+                (IDEMPOTENCY_KEY_HEADER, &key),
+            ],
+            redeem_payload,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["code"], "coupon_not_redeemable");
+        assert!(!headers.contains_key(IDEMPOTENT_REPLAYED_HEADER));
+    }
+}
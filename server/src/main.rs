@@ -1,35 +1,81 @@
-use std::{
-    net::{Ipv4Addr, SocketAddr},
-    sync::Arc,
-};
+use std::future::Future;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::time::Duration;
 
-use axum::{routing, Router, Server};
+use axum::body::Body;
+use axum::http::{HeaderName, Request};
+use axum::{middleware, routing, Router, Server};
 use dotenv::dotenv;
 use hyper::Error;
-use utoipa::OpenApi;
+use indoc::eprintdoc;
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use tokio::signal;
+use tokio::sync::oneshot;
+use tower::ServiceBuilder;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
+use tracing::Level;
+use tracing_subscriber::EnvFilter;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
+use utoipa::{Modify, OpenApi};
 use utoipa_rapidoc::RapiDoc;
 use utoipa_redoc::{Redoc, Servable};
 use utoipa_swagger_ui::SwaggerUi;
 
-use crate::store::{Store, StoreInternal};
-use crate::types::{CampaignCoupon, CampaignCouponType, Draw, User};
+use crate::cache::{Cache, CacheBackend, MemoryCache, RedisCache};
+use crate::config::{Config, DatabaseConfig, LogConfig, LogFormat};
+use crate::metrics::Metrics;
+use crate::migrate::MigrateCommand;
+use crate::rate_limit::{MemoryRateLimiter, RateLimitBackend, RateLimiter, RedisRateLimiter};
+use crate::store::Store;
+use crate::types::{CampaignCoupon, CampaignCouponType, User, UserDataExportDraw};
 
+use api_key::{CreateApiKeyPayload, CreateApiKeyResult};
 use campaign::{
-    CampaignError, CreateCampaignPayload, CreateCampaignPayloadCouponType, GetCampaignResult,
+    CreateCampaignPayload, CreateCampaignPayloadCouponType, GetCampaignResult,
     GetCampaignResultCouponType,
 };
-use draw::{DrawError, DrawPayload, DrawResult};
-use redeem::{RedeemError, RedeemPayload};
-use user::{CreateUserPayload, UserError};
+use draw::{DrawPayload, DrawResult};
+use eligibility::{EligibilityRule, EligibilityRules};
+use error::{ErrorBody, ErrorCode, ErrorType};
+use health::{DependencyCheck, Readiness, ReadinessChecks, ReadinessResult};
+use prize_rules::{PrizeRules, WhenExhausted};
+use redeem::RedeemPayload;
+use stats::{CacheStats, DbPoolStats, PoolStats, RedisPoolStats};
+use user::{CreateUserPayload, ListUsersResult, UpdateUserPayload, UserDataExport};
 
+mod admin;
+mod api_key;
+mod cache;
 mod campaign;
 mod draw;
+mod eligibility;
+mod health;
+mod idempotency;
+mod metrics;
+mod prize_rules;
+mod rate_limit;
 mod redeem;
+mod stats;
 mod user;
 
+mod config;
+mod error;
+mod extract;
+mod migrate;
+mod quota;
+mod repository;
 mod store;
+mod test;
+#[cfg(test)]
+mod testing;
 mod types;
 
+/// Taken from the request if the client (or a proxy) set it, generated otherwise, and echoed in
+/// the response so that clients can quote it when reporting a problem
+pub static REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     #[derive(OpenApi)]
@@ -37,82 +83,424 @@ async fn main() -> Result<(), Error> {
         paths(
             user::list_users,
             user::create_user,
+            user::get_user,
+            user::update_user,
             user::delete_user,
+            user::export_user,
             campaign::create_campaign,
             campaign::get_campaign,
             draw::draw,
             redeem::redeem_coupon,
+            api_key::list_api_keys,
+            api_key::create_api_key,
+            api_key::revoke_api_key,
+            stats::get_pool_stats,
+            metrics::get_metrics,
+            health::get_health,
+            health::get_readiness,
         ),
         components(
-            schemas(CampaignCouponType, CampaignCoupon, Draw, User),
-            schemas(UserError, CreateUserPayload),
-            schemas(RedeemError, RedeemPayload),
-            schemas(CampaignError, CampaignError, CreateCampaignPayload, CreateCampaignPayloadCouponType, GetCampaignResult, GetCampaignResultCouponType),
-            schemas(DrawError, DrawError, DrawPayload, DrawResult),
+            schemas(CampaignCouponType, CampaignCoupon, User, types::ApiKey),
+            schemas(ListUsersResult, CreateUserPayload, UpdateUserPayload, UserDataExport, UserDataExportDraw),
+            schemas(RedeemPayload),
+            schemas(CreateCampaignPayload, CreateCampaignPayloadCouponType, GetCampaignResult, GetCampaignResultCouponType),
+            schemas(DrawPayload, DrawResult),
+            schemas(EligibilityRules, EligibilityRule),
+            schemas(PrizeRules, WhenExhausted),
+            schemas(CreateApiKeyPayload, CreateApiKeyResult),
+            schemas(PoolStats, DbPoolStats, RedisPoolStats, CacheStats),
+            schemas(ReadinessResult, ReadinessChecks, DependencyCheck, Readiness),
+            schemas(ErrorBody, ErrorType, ErrorCode),
         ),
+        modifiers(&SecurityAddon),
         tags(
             (name = "user", description = "User management API"),
             (name = "campaign", description = "Campaign management API"),
             (name = "draw", description = "Draw API"),
-            (name = "redeem", description = "Redeem API")
+            (name = "redeem", description = "Redeem API"),
+            (name = "api_key", description = "Merchant API key management API"),
+            (name = "stats", description = "Service stats API"),
+            (name = "metrics", description = "Prometheus metrics"),
+            (name = "health", description = "Liveness and readiness probes")
         )
     )]
     struct ApiDoc;
 
-    let app = create_app()
-        .await
-        .merge(SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", ApiDoc::openapi()))
-        .merge(Redoc::with_url("/redoc", ApiDoc::openapi()))
-        .merge(RapiDoc::new("/api-docs/openapi.json").path("/rapidoc"));
-
-    let address = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 8080));
-    print!(
-        r#"
------------------------
-API playgrounds available on:
-Swagger: {address}/swagger-ui
-Redoc: {address}/redoc
-Rapidoc: {address}/rapidoc
-------------------------
-    "#
-    );
-    Server::bind(&address).serve(app.into_make_service()).await
+    dotenv().ok();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let migrate_command = match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [] => None,
+        ["migrate", command] => Some(
+            command
+                .parse::<MigrateCommand>()
+                .unwrap_or_else(|e| exit_with_usage(e)),
+        ),
+        _ => exit_with_usage(format!("Unknown arguments {args:?}")),
+    };
+
+    let mut config = Config::read().unwrap_or_else(|e| {
+        eprintln!("Failed to load configuration: {e}");
+        std::process::exit(1);
+    });
+
+    if migrate_command.is_some() {
+        // Migrating needs no cache nor rate limiter
+        config.cache.backend = CacheBackend::Memory;
+        config.rate_limit.backend = RateLimitBackend::Memory;
+    }
+
+    if let Err(e) = config.validate() {
+        eprintln!("Failed to load configuration: {e}");
+        std::process::exit(1);
+    }
+
+    init_tracing(&config.log);
+
+    if let Some(command) = migrate_command {
+        let db_pool = connect_db(&config.database).await;
+
+        if let Err(e) = migrate::run(command, &db_pool).await {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+
+        return Ok(());
+    }
+
+    struct SecurityAddon;
+
+    impl Modify for SecurityAddon {
+        fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+            if let Some(components) = openapi.components.as_mut() {
+                components.add_security_scheme(
+                    "api_key",
+                    SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(
+                        api_key::API_KEY_HEADER,
+                    ))),
+                );
+                components.add_security_scheme(
+                    "admin_token",
+                    SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(
+                        admin::ADMIN_TOKEN_HEADER,
+                    ))),
+                );
+            }
+        }
+    }
+
+    let address = config.server.bind_address;
+    let shutdown_timeout = config.server.shutdown_timeout();
+    let api_docs = config.features.api_docs;
+
+    let store = connect_store(config).await;
+    let mut app = create_router(store.clone());
+
+    idempotency::spawn_cleanup(store.clone());
+
+    if api_docs {
+        app = app
+            .merge(SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", ApiDoc::openapi()))
+            .merge(Redoc::with_url("/redoc", ApiDoc::openapi()))
+            .merge(RapiDoc::new("/api-docs/openapi.json").path("/rapidoc"));
+    }
+
+    let listener = TcpListener::bind(address).expect("Failed to bind");
+
+    tracing::info!(%address, api_docs, "Listening");
+
+    let drained = serve(listener, app, shutdown_signal(), shutdown_timeout).await?;
+
+    if let Some(redis_pool) = &store.redis_pool {
+        redis_pool.close();
+    }
+
+    if drained {
+        store.db_pool.close().await;
+    } else {
+        // Closing the pool would wait for them. Their DB transactions are rolled back instead, as
+        // the connections drop with the process
+        tracing::warn!(
+            ?shutdown_timeout,
+            "In-flight requests didn't finish in time and were abandoned"
+        );
+    }
+
+    tracing::info!("Shut down");
+
+    Ok(())
 }
 
-pub async fn create_app() -> Router {
-    let store = create_store().await;
+/// Serves until `shutdown` resolves, then stops accepting connections and gives the in-flight
+/// requests up to `timeout` to finish. Returns whether they all did
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    shutdown: impl Future<Output = ()>,
+    timeout: Duration,
+) -> Result<bool, Error> {
+    let (drain, draining) = oneshot::channel();
+
+    let server = Server::from_tcp(listener)?
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(async {
+            draining.await.ok();
+        });
+
+    tokio::pin!(server, shutdown);
+
+    tokio::select! {
+        result = &mut server => return result.map(|()| true),
+        () = &mut shutdown => {}
+    }
+
+    tracing::info!(?timeout, "Shutting down, draining in-flight requests");
+
+    drain.send(()).ok();
+
+    match tokio::time::timeout(timeout, server).await {
+        Ok(result) => result.map(|()| true),
+        Err(_) => Ok(false),
+    }
+}
 
-    Router::new()
+/// Resolves on SIGINT or SIGTERM, the latter being what orchestrators send before killing the
+/// process
+async fn shutdown_signal() {
+    let interrupt = async {
+        signal::ctrl_c().await.expect("Failed to listen for SIGINT");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    let signal = tokio::select! {
+        () = interrupt => "SIGINT",
+        () = terminate => "SIGTERM",
+    };
+
+    tracing::info!(signal, "Received shutdown signal");
+}
+
+fn exit_with_usage(error: impl std::fmt::Display) -> ! {
+    eprintdoc!(
+        "
+        {error}
+
+        Usage:
+          lucky-draw-web-service                  Serve the API
+          lucky-draw-web-service migrate up       Apply the pending migrations
+          lucky-draw-web-service migrate status   List every migration and whether it is applied
+          lucky-draw-web-service migrate dry-run  List the migrations `up` would apply
+        "
+    );
+    std::process::exit(2);
+}
+
+pub fn create_router(store: Store) -> Router {
+    let mut router = Router::new()
         .route(
             "/user",
             routing::get(user::list_users).post(user::create_user),
         )
-        .route("/user/:id", routing::delete(user::delete_user))
-        .route("/redeem", routing::post(redeem::redeem_coupon))
+        .route(
+            "/user/:id",
+            routing::get(user::get_user)
+                .patch(user::update_user)
+                .delete(user::delete_user),
+        )
+        .route(
+            "/redeem",
+            routing::post(redeem::redeem_coupon)
+                .route_layer(middleware::from_fn_with_state(
+                    store.clone(),
+                    idempotency::idempotent,
+                ))
+                // Authenticates before the idempotency key is looked up, as keys are per caller
+                .route_layer(middleware::from_fn_with_state(
+                    store.clone(),
+                    api_key::require_api_key,
+                )),
+        )
         .route("/campaign", routing::post(campaign::create_campaign))
         .route("/campaign/:id", routing::get(campaign::get_campaign))
-        .route("/draw", routing::post(draw::draw))
+        .route(
+            "/draw",
+            routing::post(draw::draw)
+                .route_layer(middleware::from_fn_with_state(
+                    store.clone(),
+                    idempotency::idempotent,
+                ))
+                // Rate limits before the idempotency key is looked up, so that replays count too
+                .route_layer(middleware::from_fn_with_state(
+                    store.clone(),
+                    rate_limit::limit_draws,
+                )),
+        )
+        .merge(
+            Router::new()
+                .route(
+                    "/api-key",
+                    routing::get(api_key::list_api_keys).post(api_key::create_api_key),
+                )
+                .route("/api-key/:id", routing::delete(api_key::revoke_api_key))
+                .route("/user/:id/export", routing::get(user::export_user))
+                .route_layer(middleware::from_fn_with_state(
+                    store.clone(),
+                    admin::require_admin,
+                )),
+        )
+        .route("/healthz", routing::get(health::get_health))
+        .route("/readyz", routing::get(health::get_readiness));
+
+    if store.config.features.pool_stats {
+        router = router.route("/stats/pools", routing::get(stats::get_pool_stats));
+    }
+
+    if store.config.features.metrics {
+        router = router.route("/metrics", routing::get(metrics::get_metrics));
+    }
+
+    // Unlike `route_layer`, this also times the requests that no route matched
+    router
+        .layer(middleware::from_fn_with_state(
+            store.clone(),
+            metrics::track_http,
+        ))
         .with_state(store)
+        .layer(
+            ServiceBuilder::new()
+                .layer(SetRequestIdLayer::new(
+                    REQUEST_ID_HEADER.clone(),
+                    MakeRequestUuid,
+                ))
+                .layer(
+                    TraceLayer::new_for_http()
+                        .make_span_with(|request: &Request<Body>| {
+                            let request_id = request
+                                .headers()
+                                .get(&REQUEST_ID_HEADER)
+                                .and_then(|id| id.to_str().ok())
+                                .unwrap_or_default();
+
+                            tracing::info_span!(
+                                "request",
+                                request_id,
+                                method = %request.method(),
+                                path = request.uri().path(),
+                            )
+                        })
+                        .on_response(DefaultOnResponse::new().level(Level::INFO))
+                        // Server errors are logged along with their cause by `AppError`
+                        .on_failure(()),
+                )
+                .layer(PropagateRequestIdLayer::new(REQUEST_ID_HEADER.clone())),
+        )
 }
 
-pub async fn create_store() -> Arc<Store> {
-    dotenv().ok();
+fn init_tracing(log: &LogConfig) {
+    let subscriber = tracing_subscriber::fmt().with_env_filter(EnvFilter::new(&log.level));
 
-    let redis_url = std::env::var("REDIS_URL").expect("REDIS_URL missing in .env");
-    let redis_client = redis::Client::open(redis_url).expect("Redis connection failed");
+    match log.format {
+        LogFormat::Json => subscriber.json().init(),
+        LogFormat::Text => subscriber.init(),
+    }
+}
 
-    let db_url = std::env::var("DATABASE_URL").expect("DATABASE_URL missing in .env");
-    let db_pool = sqlx::postgres::PgPool::connect(&db_url)
+pub async fn connect_db(database: &DatabaseConfig) -> PgPool {
+    PgPoolOptions::new()
+        .max_connections(database.max_connections)
+        .min_connections(database.min_connections)
+        .acquire_timeout(database.acquire_timeout())
+        .idle_timeout(database.idle_timeout())
+        .connect(&database.url)
         .await
-        .expect("Failed to connect to DB");
+        .expect("Failed to connect to DB")
+}
 
-    sqlx::migrate!("./migrations")
-        .run(&db_pool)
-        .await
-        .expect("Failed to migrate DB");
+/// Connecting to Redis is lazy, so the service starts (and degrades to the DB) even if Redis is
+/// unreachable
+pub async fn connect_store(config: Config) -> Store {
+    let redis_pool = config.uses_redis().then(|| {
+        let redis = &config.redis;
+        let url = redis.url.clone().expect("REDIS_URL is validated");
+
+        let mut redis_config = deadpool_redis::Config::from_url(url);
+        // Fail fast instead of hanging a handler when Redis is unreachable or the pool is
+        // exhausted
+        redis_config.pool = Some(deadpool_redis::PoolConfig {
+            max_size: redis.pool_size,
+            timeouts: deadpool_redis::Timeouts {
+                wait: Some(redis.timeout()),
+                create: Some(redis.timeout()),
+                recycle: Some(redis.timeout()),
+            },
+            ..Default::default()
+        });
+
+        redis_config
+            .create_pool(Some(deadpool_redis::Runtime::Tokio1))
+            .expect("Failed to create Redis pool")
+    });
+
+    let cache: Arc<dyn Cache> = match (config.cache.backend, &redis_pool) {
+        (CacheBackend::Redis, Some(redis_pool)) => Arc::new(RedisCache::new(
+            redis_pool.clone(),
+            config.redis.key_prefix.clone(),
+            &config.cache,
+        )),
+        _ => Arc::new(MemoryCache::new(&config.cache)),
+    };
+
+    let rate_limit = &config.rate_limit;
+
+    let rate_limiter: Option<Arc<dyn RateLimiter>> = match (rate_limit.backend, &redis_pool) {
+        _ if !rate_limit.enabled => None,
+        (RateLimitBackend::Redis, Some(redis_pool)) => Some(Arc::new(RedisRateLimiter::new(
+            redis_pool.clone(),
+            config.redis.key_prefix.clone(),
+        ))),
+        _ => Some(Arc::new(MemoryRateLimiter::new(
+            rate_limit.max_buckets,
+            rate_limit
+                .user_limit()
+                .refill_time()
+                .max(rate_limit.ip_limit().refill_time()),
+        ))),
+    };
+
+    let db_pool = connect_db(&config.database).await;
+
+    if config.database.migrate_on_startup {
+        migrate::up(&db_pool).await.expect("Failed to migrate DB");
+    } else {
+        let pending = migrate::pending(&db_pool)
+            .await
+            .expect("Failed to read migrations");
+
+        if !pending.is_empty() {
+            tracing::warn!(
+                pending = pending.len(),
+                "Migrations are pending, run the migrate subcommand to apply them"
+            );
+        }
+    }
 
-    Arc::new(Store::new(StoreInternal {
+    Store {
         db_pool,
-        redis: redis_client,
-    }))
+        redis_pool,
+        cache,
+        rate_limiter,
+        config: Arc::new(config),
+        metrics: Arc::new(Metrics::new()),
+    }
 }
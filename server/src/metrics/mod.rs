@@ -0,0 +1,186 @@
+use std::time::Instant;
+
+use axum::{
+    extract::{MatchedPath, State},
+    http::{header, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use prometheus::core::Collector;
+use prometheus::{
+    exponential_buckets, HistogramOpts, HistogramTimer, HistogramVec, IntCounterVec, Opts,
+    Registry, TextEncoder,
+};
+
+use crate::store::Store;
+
+mod test;
+
+/// Prometheus metrics of the service. Each [`Store`] has a registry of its own, so that tests
+/// running in parallel don't count each other's requests
+pub struct Metrics {
+    registry: Registry,
+    /// By campaign and outcome, which is `coupon`, `no_coupon` or the error code of a failed draw
+    pub draws: IntCounterVec,
+    pub coupons_issued: IntCounterVec,
+    pub coupons_redeemed: IntCounterVec,
+    /// Draws that picked a coupon type whose quota had run out, by the chosen coupon type. The
+    /// campaign's prize rules may still have issued a coupon of another type
+    pub quota_exhausted: IntCounterVec,
+    /// By cache and result, `hit` or `miss`
+    pub cache_lookups: IntCounterVec,
+    /// Draws turned away by the rate limiter, by whose bucket ran out, `user` or `ip`
+    pub rate_limited: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    pub db_query_duration: HistogramVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("lucky_draw".to_string()), None)
+            .expect("Metric prefix is valid");
+
+        // 1ms to ~4s
+        let latency_buckets = exponential_buckets(0.001, 2.0, 13).expect("Buckets are valid");
+
+        let metrics = Self {
+            draws: IntCounterVec::new(
+                Opts::new("draws_total", "Draws by campaign and outcome"),
+                &["campaign_id", "outcome"],
+            )
+            .expect("Metric is valid"),
+            coupons_issued: IntCounterVec::new(
+                Opts::new("coupons_issued_total", "Coupons issued by coupon type"),
+                &["coupon_type_id"],
+            )
+            .expect("Metric is valid"),
+            coupons_redeemed: IntCounterVec::new(
+                Opts::new("coupons_redeemed_total", "Coupons redeemed by coupon type"),
+                &["coupon_type_id"],
+            )
+            .expect("Metric is valid"),
+            quota_exhausted: IntCounterVec::new(
+                Opts::new(
+                    "quota_exhausted_total",
+                    "Draws of a coupon type whose quota had run out, by coupon type",
+                ),
+                &["coupon_type_id"],
+            )
+            .expect("Metric is valid"),
+            cache_lookups: IntCounterVec::new(
+                Opts::new("cache_lookups_total", "Cache lookups by cache and result"),
+                &["cache", "result"],
+            )
+            .expect("Metric is valid"),
+            rate_limited: IntCounterVec::new(
+                Opts::new("rate_limited_total", "Rate limited draws by bucket"),
+                &["scope"],
+            )
+            .expect("Metric is valid"),
+            http_request_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "http_request_duration_seconds",
+                    "Handler latency by method, route and status",
+                )
+                .buckets(latency_buckets.clone()),
+                &["method", "path", "status"],
+            )
+            .expect("Metric is valid"),
+            db_query_duration: HistogramVec::new(
+                HistogramOpts::new("db_query_duration_seconds", "DB latency by query")
+                    .buckets(latency_buckets),
+                &["query"],
+            )
+            .expect("Metric is valid"),
+            registry,
+        };
+
+        let collectors: [Box<dyn Collector>; 8] = [
+            Box::new(metrics.draws.clone()),
+            Box::new(metrics.coupons_issued.clone()),
+            Box::new(metrics.coupons_redeemed.clone()),
+            Box::new(metrics.quota_exhausted.clone()),
+            Box::new(metrics.cache_lookups.clone()),
+            Box::new(metrics.rate_limited.clone()),
+            Box::new(metrics.http_request_duration.clone()),
+            Box::new(metrics.db_query_duration.clone()),
+        ];
+
+        for collector in collectors {
+            metrics
+                .registry
+                .register(collector)
+                .expect("Metric is registered once");
+        }
+
+        metrics
+    }
+
+    /// Observes the latency of a DB query once dropped
+    pub fn db_timer(&self, query: &str) -> HistogramTimer {
+        self.db_query_duration
+            .with_label_values(&[query])
+            .start_timer()
+    }
+
+    pub fn cache_lookup(&self, cache: &str, hit: bool) {
+        self.cache_lookups
+            .with_label_values(&[cache, if hit { "hit" } else { "miss" }])
+            .inc();
+    }
+
+    /// In the Prometheus text format
+    pub fn encode(&self) -> String {
+        TextEncoder::new()
+            .encode_to_string(&self.registry.gather())
+            .expect("Metrics are encodable")
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses(
+        (status = 200, description = "Prometheus metrics in the text exposition format", body = String, content_type = "text/plain")
+    )
+)]
+pub(super) async fn get_metrics(State(store): State<Store>) -> Response {
+    (
+        [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        store.metrics.encode(),
+    )
+        .into_response()
+}
+
+/// Times every request. Labelled by route rather than path, so that IDs in paths don't create a
+/// time series each, and requests that no route matched share the `unmatched` label
+pub(super) async fn track_http<B>(
+    State(store): State<Store>,
+    matched_path: Option<MatchedPath>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let start = Instant::now();
+    let method = request.method().clone();
+
+    let response = next.run(request).await;
+
+    let path = matched_path
+        .as_ref()
+        .map(|path| path.as_str())
+        .unwrap_or("unmatched");
+
+    store
+        .metrics
+        .http_request_duration
+        .with_label_values(&[method.as_str(), path, response.status().as_str()])
+        .observe(start.elapsed().as_secs_f64());
+
+    response
+}
@@ -0,0 +1,193 @@
+#[cfg(test)]
+mod tests {
+    use crate::{cache::CacheBackend, rate_limit::RateLimitBackend, testing::TestContext};
+
+    use axum::{
+        body::Body,
+        http::{self, Method, Request, StatusCode},
+    };
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn metrics_count_draws_and_exhausted_quotas() {
+        let ctx = TestContext::with_config(|config| {
+            config.cache.backend = CacheBackend::Memory;
+            config.rate_limit.backend = RateLimitBackend::Memory;
+            config.features.metrics = true;
+        })
+        .await;
+        let app = ctx.app.clone();
+
+        let create_campaign_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/campaign")
+                    .method(Method::POST)
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(
+                        serde_json::to_string(&json!({
+                            "coupon_types": [{
+                                "description": "Only one",
+                                "probability": 1.0,
+                                "total_quota": 1,
+                                "daily_quota": null
+                            }]
+                        }))
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(create_campaign_response.status(), StatusCode::CREATED);
+
+        let body = hyper::body::to_bytes(create_campaign_response.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        let campaign_id = body["id"].as_i64().unwrap();
+
+        let coupon_type_id = sqlx::query_scalar!(
+            "--sql
+                select id from campaign_coupon_types where campaign_id = $1;
+            ",
+            campaign_id as i32
+        )
+        .fetch_one(&ctx.store.db_pool)
+        .await
+        .unwrap();
+
+        let user_ids: Vec<i32> = sqlx::query_scalar!(
+            "--sql
+                insert into users (phone)
+                values ('+852 00000001'), ('+852 00000002')
+                returning id;
+            "
+        )
+        .fetch_all(&ctx.store.db_pool)
+        .await
+        .unwrap();
+
+        // The first draw takes the only coupon, the second finds the quota exhausted
+
+        for user_id in user_ids {
+            let draw_response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .uri("/draw")
+                        .method(Method::POST)
+                        .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                        .body(Body::from(
+                            serde_json::to_string(&json!({
+                                "campaign_id": campaign_id,
+                                "user_id": user_id
+                            }))
+                            .unwrap(),
+                        ))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(draw_response.status(), StatusCode::OK);
+        }
+
+        let metrics_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/metrics")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(metrics_response.status(), StatusCode::OK);
+        assert_eq!(
+            metrics_response.headers()[http::header::CONTENT_TYPE],
+            prometheus::TEXT_FORMAT
+        );
+
+        let body = hyper::body::to_bytes(metrics_response.into_body())
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        for line in [
+            format!(r#"lucky_draw_draws_total{{campaign_id="{campaign_id}",outcome="coupon"}} 1"#),
+            format!(
+                r#"lucky_draw_draws_total{{campaign_id="{campaign_id}",outcome="no_coupon"}} 1"#
+            ),
+            format!(r#"lucky_draw_coupons_issued_total{{coupon_type_id="{coupon_type_id}"}} 1"#),
+            format!(r#"lucky_draw_quota_exhausted_total{{coupon_type_id="{coupon_type_id}"}} 1"#),
+            r#"lucky_draw_cache_lookups_total{cache="enrolled_campaigns",result="miss"} 2"#
+                .to_string(),
+            r#"lucky_draw_http_request_duration_seconds_count{method="POST",path="/draw",status="200"} 2"#
+                .to_string(),
+        ] {
+            assert!(body.lines().any(|l| l == line), "{line} not in\n{body}");
+        }
+
+        assert!(
+            body.contains(r#"lucky_draw_db_query_duration_seconds_count{query="draws.record"} 2"#)
+        );
+    }
+
+    #[tokio::test]
+    async fn internal_endpoints_are_off_by_default() {
+        let ctx = TestContext::new().await;
+
+        for uri in ["/metrics", "/stats/pools"] {
+            let response = ctx
+                .app
+                .clone()
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn unmatched_requests_share_a_label() {
+        let ctx = TestContext::with_config(|config| config.features.metrics = true).await;
+
+        for uri in ["/no-such-route/1", "/no-such-route/2"] {
+            let response = ctx
+                .app
+                .clone()
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+
+        let metrics_response = ctx
+            .app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/metrics")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let body = hyper::body::to_bytes(metrics_response.into_body())
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        let line = r#"lucky_draw_http_request_duration_seconds_count{method="GET",path="unmatched",status="404"} 2"#;
+        assert!(body.lines().any(|l| l == line), "{line} not in\n{body}");
+    }
+}
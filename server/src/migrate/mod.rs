@@ -0,0 +1,221 @@
+//! Schema migrations, run at startup unless `database.migrate_on_startup` is off, or as a separate
+//! deployment step with the `migrate` subcommand
+
+use std::fmt;
+use std::str::FromStr;
+
+use sqlx::migrate::{MigrateError, Migrator};
+use sqlx::PgPool;
+
+mod test;
+
+/// Migrations applied to the database but unknown to the binary are ignored, as an older replica
+/// meets them during a rolling deploy
+pub static MIGRATOR: Migrator = Migrator {
+    ignore_missing: true,
+    ..sqlx::migrate!("./migrations")
+};
+
+/// `migrate <command>` of the binary
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MigrateCommand {
+    /// Applies the pending migrations
+    Up,
+    /// Lists every migration and whether it has been applied
+    Status,
+    /// Lists the migrations `up` would apply, without applying them
+    DryRun,
+}
+
+impl FromStr for MigrateCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "up" => Ok(Self::Up),
+            "status" => Ok(Self::Status),
+            "dry-run" => Ok(Self::DryRun),
+            _ => Err(format!(
+                "Unknown migrate command {s:?}, expected up, status or dry-run"
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    /// Applied, but the migration file has changed since
+    Modified,
+    /// Failed part way, the database needs fixing by hand
+    Failed,
+    /// Applied, but there is no such migration file, e.g. the binary is older than the database
+    Unknown,
+}
+
+impl fmt::Display for MigrationState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Applied => "applied",
+            Self::Pending => "pending",
+            Self::Modified => "modified since applied",
+            Self::Failed => "failed",
+            Self::Unknown => "applied, unknown to this binary",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub state: MigrationState,
+}
+
+impl fmt::Display for MigrationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04} {:<40} {}",
+            self.version, self.description, self.state
+        )
+    }
+}
+
+/// Every migration, known to the binary or applied to the database, by version. Only reads, so
+/// it can't interfere with a concurrent `up`
+pub async fn status(db_pool: &PgPool) -> Result<Vec<MigrationStatus>, sqlx::Error> {
+    let table_exists = sqlx::query_scalar!(
+        r#"--sql
+            select to_regclass('_sqlx_migrations') is not null as "exists!";
+        "#
+    )
+    .fetch_one(db_pool)
+    .await?;
+
+    let applied = if table_exists {
+        sqlx::query!(
+            "--sql
+                select version, description, success, checksum
+                from _sqlx_migrations
+                order by version;
+            "
+        )
+        .fetch_all(db_pool)
+        .await?
+    } else {
+        vec![]
+    };
+
+    let mut statuses: Vec<_> = MIGRATOR
+        .iter()
+        .map(|migration| {
+            let state = match applied.iter().find(|a| a.version == migration.version) {
+                None => MigrationState::Pending,
+                Some(a) if !a.success => MigrationState::Failed,
+                Some(a) if a.checksum != *migration.checksum => MigrationState::Modified,
+                Some(_) => MigrationState::Applied,
+            };
+
+            MigrationStatus {
+                version: migration.version,
+                description: migration.description.to_string(),
+                state,
+            }
+        })
+        .collect();
+
+    for a in &applied {
+        if !MIGRATOR.iter().any(|m| m.version == a.version) {
+            statuses.push(MigrationStatus {
+                version: a.version,
+                description: a.description.clone(),
+                state: MigrationState::Unknown,
+            });
+        }
+    }
+
+    statuses.sort_by_key(|s| s.version);
+
+    Ok(statuses)
+}
+
+/// Applies the pending migrations. Replicas running this at once are serialized by an advisory
+/// lock. Returns the migrations that were applied
+pub async fn up(db_pool: &PgPool) -> Result<Vec<MigrationStatus>, MigrateError> {
+    let pending = pending(db_pool).await?;
+
+    MIGRATOR.run(db_pool).await?;
+
+    Ok(pending)
+}
+
+pub async fn pending(db_pool: &PgPool) -> Result<Vec<MigrationStatus>, sqlx::Error> {
+    Ok(status(db_pool)
+        .await?
+        .into_iter()
+        .filter(|s| s.state == MigrationState::Pending)
+        .collect())
+}
+
+/// Runs the subcommand, printing its outcome. Fails if migrating failed, or if `status` or
+/// `dry-run` found migrations that `up` would refuse to run past
+pub async fn run(command: MigrateCommand, db_pool: &PgPool) -> Result<(), String> {
+    match command {
+        MigrateCommand::Up => {
+            let applied = up(db_pool)
+                .await
+                .map_err(|e| format!("Failed to migrate DB: {e}"))?;
+
+            if applied.is_empty() {
+                println!("DB is up to date");
+            }
+
+            for migration in applied {
+                println!("Applied {migration}");
+            }
+
+            Ok(())
+        }
+        MigrateCommand::Status | MigrateCommand::DryRun => {
+            let statuses = status(db_pool)
+                .await
+                .map_err(|e| format!("Failed to read migrations: {e}"))?;
+
+            if command == MigrateCommand::Status {
+                for migration in &statuses {
+                    println!("{migration}");
+                }
+            } else {
+                let pending: Vec<_> = statuses
+                    .iter()
+                    .filter(|s| s.state == MigrationState::Pending)
+                    .collect();
+
+                if pending.is_empty() {
+                    println!("DB is up to date, nothing to apply");
+                }
+
+                for migration in pending {
+                    println!("Would apply {migration}");
+                }
+            }
+
+            let broken: Vec<_> = statuses
+                .iter()
+                .filter(|s| matches!(s.state, MigrationState::Modified | MigrationState::Failed))
+                .map(|s| s.version.to_string())
+                .collect();
+
+            if broken.is_empty() {
+                Ok(())
+            } else {
+                Err(format!(
+                    "Migrations {} need attention before migrating",
+                    broken.join(", ")
+                ))
+            }
+        }
+    }
+}
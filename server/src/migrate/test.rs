@@ -0,0 +1,260 @@
+#[cfg(test)]
+mod tests {
+    use crate::{
+        migrate::{self, MigrationState, MIGRATOR},
+        testing::TestContext,
+    };
+
+    use sqlx::{migrate::Migrator, Executor};
+
+    #[tokio::test]
+    async fn migrate_up_applies_pending_migrations_once() {
+        let ctx =
+            TestContext::with_config(|config| config.database.migrate_on_startup = false).await;
+        let db_pool = &ctx.store.db_pool;
+
+        let statuses = migrate::status(db_pool).await.unwrap();
+
+        assert_eq!(statuses.len(), MIGRATOR.iter().count());
+        assert!(statuses.iter().all(|s| s.state == MigrationState::Pending));
+
+        // Reading the status doesn't create the migrations table
+
+        let table_exists = sqlx::query_scalar!(
+            r#"--sql
+                select to_regclass('_sqlx_migrations') is not null as "exists!";
+            "#
+        )
+        .fetch_one(db_pool)
+        .await
+        .unwrap();
+
+        assert!(!table_exists);
+        assert_eq!(migrate::pending(db_pool).await.unwrap(), statuses);
+
+        let applied = migrate::up(db_pool).await.unwrap();
+
+        assert_eq!(applied, statuses);
+        assert!(migrate::status(db_pool)
+            .await
+            .unwrap()
+            .iter()
+            .all(|s| s.state == MigrationState::Applied));
+
+        assert!(migrate::up(db_pool).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn migrate_up_ignores_migrations_from_a_newer_binary() {
+        let ctx =
+            TestContext::with_config(|config| config.database.migrate_on_startup = false).await;
+        let db_pool = &ctx.store.db_pool;
+
+        // A newer replica of a rolling deploy got there first, with a migration this binary lacks
+
+        let last = MIGRATOR.iter().last().unwrap().version;
+
+        let older = Migrator {
+            migrations: MIGRATOR
+                .iter()
+                .take_while(|m| m.version < last)
+                .cloned()
+                .collect(),
+            ignore_missing: false,
+            locking: true,
+        };
+
+        older.run(db_pool).await.unwrap();
+
+        sqlx::query!(
+            "--sql
+                insert into _sqlx_migrations (version, description, success, checksum, execution_time)
+                values (99999999, 'from a newer binary', true, '\\x00', 0);
+            "
+        )
+        .execute(db_pool)
+        .await
+        .unwrap();
+
+        let statuses = migrate::status(db_pool).await.unwrap();
+
+        assert_eq!(statuses.last().unwrap().version, 99999999);
+        assert_eq!(statuses.last().unwrap().state, MigrationState::Unknown);
+
+        // Migrating on startup applies what it knows of and carries on
+
+        let applied = migrate::up(db_pool).await.unwrap();
+
+        assert_eq!(applied.len(), 1);
+        assert_eq!(applied[0].version, last);
+        assert!(migrate::up(db_pool).await.unwrap().is_empty());
+        assert!(migrate::run(migrate::MigrateCommand::DryRun, db_pool)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn migrate_status_flags_unknown_and_modified_migrations() {
+        let ctx = TestContext::new().await;
+        let db_pool = &ctx.store.db_pool;
+
+        sqlx::query!(
+            "--sql
+                insert into _sqlx_migrations (version, description, success, checksum, execution_time)
+                values (99999999, 'from a newer binary', true, '\\x00', 0);
+            "
+        )
+        .execute(db_pool)
+        .await
+        .unwrap();
+
+        sqlx::query!(
+            "--sql
+                update _sqlx_migrations
+                set checksum = '\\x00'
+                where version = 1;
+            "
+        )
+        .execute(db_pool)
+        .await
+        .unwrap();
+
+        let statuses = migrate::status(db_pool).await.unwrap();
+
+        assert_eq!(statuses[0].state, MigrationState::Modified);
+        assert_eq!(statuses.last().unwrap().version, 99999999);
+        assert_eq!(statuses.last().unwrap().state, MigrationState::Unknown);
+
+        // `up` refuses to run past the modified one
+
+        assert!(migrate::up(db_pool).await.is_err());
+        assert!(migrate::run(migrate::MigrateCommand::DryRun, db_pool)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn repeated_draws_are_removed_before_enforcing_one_a_day() {
+        let ctx =
+            TestContext::with_config(|config| config.database.migrate_on_startup = false).await;
+        let db_pool = &ctx.store.db_pool;
+
+        // Draws from before a user could only draw once a day per campaign
+
+        let before_unique_draws = Migrator {
+            migrations: MIGRATOR
+                .iter()
+                .take_while(|m| m.version < 7)
+                .cloned()
+                .collect(),
+            ignore_missing: false,
+            locking: true,
+        };
+
+        before_unique_draws.run(db_pool).await.unwrap();
+
+        db_pool
+            .execute(
+                "
+                    insert into campaigns (id) values (1);
+                    insert into campaign_coupon_types (id, campaign_id, description, probability)
+                    values (1, 1, 'Coupon type', 1);
+                    insert into campaign_coupons (id, campaign_coupon_type_id, redeem_code)
+                    values (1, 1, 'won');
+                    insert into users (id, phone) values (1, '+852 0000 0001'), (2, '+852 0000 0002');
+                    insert into draws (id, user_id, campaign_id, campaign_coupon_id, date)
+                    values
+                        (1, 1, 1, null, '2023-11-01'),
+                        (2, 1, 1, 1, '2023-11-01'),
+                        (3, 1, 1, null, '2023-11-01'),
+                        (4, 1, 1, null, '2023-11-02'),
+                        (5, 1, 1, null, '2023-11-02'),
+                        (6, 2, 1, null, '2023-11-01');
+                ",
+            )
+            .await
+            .unwrap();
+
+        migrate::up(db_pool).await.unwrap();
+
+        // The draw that won is kept over the earlier one that didn't
+
+        let draw_ids = sqlx::query_scalar!(
+            "--sql
+                select id from draws order by id;
+            "
+        )
+        .fetch_all(db_pool)
+        .await
+        .unwrap();
+
+        assert_eq!(draw_ids, [2, 4, 6]);
+    }
+
+    #[tokio::test]
+    async fn existing_users_are_not_backfilled_as_new_accounts() {
+        let ctx =
+            TestContext::with_config(|config| config.database.migrate_on_startup = false).await;
+        let db_pool = &ctx.store.db_pool;
+
+        // Users and draws from before users had a creation time
+
+        let before_created_at = Migrator {
+            migrations: MIGRATOR
+                .iter()
+                .take_while(|m| m.version < 5)
+                .cloned()
+                .collect(),
+            ignore_missing: false,
+            locking: true,
+        };
+
+        before_created_at.run(db_pool).await.unwrap();
+
+        db_pool
+            .execute(
+                "
+                    insert into campaigns (id) values (default);
+                    insert into users (phone) values ('+852 0000 0001'), ('+852 0000 0002');
+                    insert into draws (user_id, campaign_id, date)
+                    select min(users.id), min(campaigns.id), date::date
+                    from users, campaigns, unnest(array['2023-05-02', '2023-06-01']) as date
+                    group by date;
+                ",
+            )
+            .await
+            .unwrap();
+
+        migrate::up(db_pool).await.unwrap();
+
+        let users = sqlx::query!(
+            "--sql
+                select id, created_at from users order by id;
+            "
+        )
+        .fetch_all(db_pool)
+        .await
+        .unwrap();
+
+        // Users who drew are as old as their first draw, the others as the migration
+
+        assert_eq!(
+            users[0].created_at.to_rfc3339(),
+            "2023-05-02T00:00:00+00:00"
+        );
+        assert!(chrono::Utc::now() - users[1].created_at < chrono::Duration::minutes(1));
+
+        // Users created since get the default
+
+        let created_at = sqlx::query_scalar!(
+            "--sql
+                insert into users (phone) values ('+852 0000 0003') returning created_at;
+            "
+        )
+        .fetch_one(db_pool)
+        .await
+        .unwrap();
+
+        assert!(chrono::Utc::now() - created_at < chrono::Duration::minutes(1));
+    }
+}
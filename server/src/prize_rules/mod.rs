@@ -0,0 +1,185 @@
+use std::str::FromStr;
+
+use chrono::Timelike;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::repository::DrawHistory;
+use crate::types::CampaignCouponType;
+
+mod test;
+
+/// What a draw yields when the coupon type it picked has no quota left
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WhenExhausted {
+    /// No coupon, as if the draw had picked none
+    #[default]
+    NoPrize,
+    /// Another coupon type with quota left, picked in proportion to their probabilities, so the
+    /// chance of winning a coupon stays the same
+    Renormalize,
+    /// The coupon type flagged `fallback`, e.g. a consolation coupon, if it has quota left
+    Fallback,
+}
+
+impl WhenExhausted {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WhenExhausted::NoPrize => "no_prize",
+            WhenExhausted::Renormalize => "renormalize",
+            WhenExhausted::Fallback => "fallback",
+        }
+    }
+}
+
+impl FromStr for WhenExhausted {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "no_prize" => Ok(Self::NoPrize),
+            "renormalize" => Ok(Self::Renormalize),
+            "fallback" => Ok(Self::Fallback),
+            _ => Err(format!("Unknown when_exhausted {s:?}")),
+        }
+    }
+}
+
+/// The most the probability of a coupon type is raised by pacing, so that a quiet morning doesn't
+/// get the rest of the daily quota handed out in a rush
+const MAX_PACING_FACTOR: f64 = 4.0;
+
+/// How the prizes of a campaign are handed out, beyond sampling the probabilities of its coupon
+/// types
+#[derive(Serialize, Deserialize, ToSchema, Clone, Default, Debug)]
+pub struct PrizeRules {
+    #[serde(default)]
+    pub when_exhausted: WhenExhausted,
+    /// A user who has drawn this many times in a row without winning a coupon is guaranteed one on
+    /// their next draw, picked in proportion to the probabilities of the coupon types with quota
+    /// left. A coupon type flagged `fallback` is the last resort
+    #[schema(example = "5")]
+    pub guaranteed_win_after: Option<i32>,
+    /// Spreads the daily quotas over the day instead of handing them out first come first served,
+    /// see [`paced_distribution`]
+    #[serde(default)]
+    pub pacing: bool,
+    /// The coupon type flagged `fallback`, looked up along with the rules
+    #[serde(skip)]
+    pub fallback_coupon_type_id: Option<i32>,
+    /// The coupon type flagged `first_draw`, looked up along with the rules
+    #[serde(skip)]
+    pub first_draw_coupon_type_id: Option<i32>,
+}
+
+impl PrizeRules {
+    /// Whether [`PrizeRules::candidates`] depends on the history of the user's draws, which is
+    /// otherwise not worth looking up
+    pub fn needs_history(&self) -> bool {
+        self.guaranteed_win_after.is_some() || self.first_draw_coupon_type_id.is_some()
+    }
+
+    /// Whether the user's next draw is guaranteed a coupon
+    pub fn guarantees_win(&self, history: &DrawHistory) -> bool {
+        self.guaranteed_win_after
+            .is_some_and(|after| history.losing_streak >= after as i64)
+    }
+
+    /// The coupon types to issue a coupon of, in order of preference, given the one the draw
+    /// picked from the `(coupon type id, probability)` pairs and the user's draws so far. The
+    /// first with quota left is issued
+    pub fn candidates(
+        &self,
+        picked: Option<i32>,
+        distribution: &[(i32, f32)],
+        history: &DrawHistory,
+        rng: &mut impl Rng,
+    ) -> Vec<i32> {
+        let guaranteed = self.guarantees_win(history);
+        let mut candidates = vec![];
+
+        if history.draws == 0 {
+            candidates.extend(self.first_draw_coupon_type_id);
+        }
+
+        candidates.extend(picked.filter(|id| !candidates.contains(id)));
+
+        if candidates.is_empty() && !guaranteed {
+            return candidates;
+        }
+
+        if guaranteed || self.when_exhausted == WhenExhausted::Renormalize {
+            // A weighted shuffle of the other coupon types, so that whichever comes first among
+            // those with quota left is picked in proportion to its probability
+            let mut others: Vec<(f64, i32)> = distribution
+                .iter()
+                .filter(|&&(id, probability)| !candidates.contains(&id) && probability > 0.0)
+                .map(|&(id, probability)| (rng.gen::<f64>().powf(1.0 / probability as f64), id))
+                .collect();
+
+            others.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+            candidates.extend(others.into_iter().map(|(_, id)| id));
+        }
+
+        if guaranteed || self.when_exhausted == WhenExhausted::Fallback {
+            if let Some(fallback) = self.fallback_coupon_type_id {
+                if !candidates.contains(&fallback) {
+                    candidates.push(fallback);
+                }
+            }
+        }
+
+        candidates
+    }
+}
+
+/// The `(coupon type id, probability)` pairs of the coupon types, with the probabilities of those
+/// with a daily quota scaled by how far ahead or behind schedule they are at `now`: by the share
+/// of the daily quota left over the share of the (UTC) day left. A coupon type that has handed out
+/// half its daily quota by noon keeps its probability, one that is ahead is slowed down, and one
+/// that is behind is sped up, by at most [`MAX_PACING_FACTOR`]. Speeding up never raises the
+/// chance of winning a coupon at all: if the paced probabilities sum to more than the campaign's,
+/// they are scaled down to it, so the behind ones catch up at the expense of the others
+pub fn paced_distribution(
+    coupon_types: &[CampaignCouponType],
+    now: chrono::DateTime<chrono::Utc>,
+) -> Vec<(i32, f32)> {
+    let today_date = now.naive_utc().date();
+    let day_left = 1.0 - now.num_seconds_from_midnight() as f64 / (24 * 60 * 60) as f64;
+
+    let paced: Vec<(i32, f64)> = coupon_types
+        .iter()
+        .map(|t| {
+            let factor = match (t.daily_quota, t.daily_quota_left(today_date)) {
+                (Some(daily_quota), Some(left)) if daily_quota > 0 => {
+                    (left as f64 / daily_quota as f64 / day_left).min(MAX_PACING_FACTOR)
+                }
+                (Some(_), _) => 0.0,
+                (None, _) => 1.0,
+            };
+
+            (t.id, t.probability as f64 * factor)
+        })
+        .collect();
+
+    let win_probability = coupon_types
+        .iter()
+        .map(|t| t.probability as f64)
+        .sum::<f64>()
+        .min(1.0);
+    let paced_win_probability: f64 = paced.iter().map(|&(_, p)| p).sum();
+
+    let scale = if paced_win_probability > win_probability {
+        win_probability / paced_win_probability
+    } else {
+        1.0
+    };
+
+    paced
+        .into_iter()
+        .map(|(id, p)| (id, (p * scale) as f32))
+        .collect()
+}
@@ -0,0 +1,546 @@
+#[cfg(test)]
+mod tests {
+    use crate::{
+        prize_rules::{paced_distribution, PrizeRules, WhenExhausted},
+        repository::DrawHistory,
+        testing::{post_json, seeded_rng, TestContext},
+        types::CampaignCouponType,
+    };
+
+    use crate::{
+        cache::{Cache, MemoryCache},
+        draw::service::{draw, pick_coupon_type},
+        metrics::Metrics,
+        repository::fake::FakeRepository,
+    };
+
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use chrono::NaiveDate;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    /// Creates the campaign, then has `draws` new users draw from it, and returns the descriptions
+    /// of the coupon types of the coupons they got
+    async fn draw_coupons(
+        ctx: &TestContext,
+        campaign: serde_json::Value,
+        draws: usize,
+    ) -> Vec<Option<String>> {
+        let (status, _, campaign) = post_json(&ctx.app, "/campaign", &[], campaign).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let campaign_id = campaign["id"].as_i64().unwrap();
+        let mut descriptions = vec![];
+
+        for n in 0..draws {
+            let (status, _, user) = post_json(
+                &ctx.app,
+                "/user",
+                &[],
+                json!({ "phone": format!("+852 {campaign_id:04}{n:04}") }),
+            )
+            .await;
+            assert_eq!(status, StatusCode::CREATED);
+
+            let (status, _, draw) = post_json(
+                &ctx.app,
+                "/draw",
+                &[],
+                json!({ "campaign_id": campaign_id, "user_id": user["id"] }),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+
+            let description = match draw["maybe_coupon"]["campaign_coupon_type_id"].as_i64() {
+                Some(coupon_type_id) => Some(
+                    sqlx::query_scalar!(
+                        "--sql
+                            select description from campaign_coupon_types where id = $1;
+                        ",
+                        coupon_type_id as i32
+                    )
+                    .fetch_one(&ctx.store.db_pool)
+                    .await
+                    .unwrap(),
+                ),
+                None => None,
+            };
+
+            descriptions.push(description);
+        }
+
+        descriptions
+    }
+
+    #[test]
+    fn renormalize_picks_in_proportion_to_the_probabilities_left() {
+        let prize_rules = PrizeRules {
+            when_exhausted: WhenExhausted::Renormalize,
+            ..Default::default()
+        };
+        let distribution = [(1, 0.2), (2, 0.3), (3, 0.1), (4, 0.0)];
+        let history = DrawHistory::default();
+        let mut rng = seeded_rng();
+
+        // With coupon type 1 run out, the first of the others tried is 2 three times as often as 3,
+        // and never 4 which can't be won
+        let mut counts = [0; 2];
+
+        for _ in 0..10_000 {
+            let candidates = prize_rules.candidates(Some(1), &distribution, &history, &mut rng);
+
+            assert_eq!(candidates.len(), 3);
+            assert_eq!(candidates[0], 1);
+
+            match candidates[1] {
+                2 => counts[0] += 1,
+                3 => counts[1] += 1,
+                id => panic!("Unexpected coupon type {id}"),
+            }
+        }
+
+        assert!((7_200..7_800).contains(&counts[0]), "{counts:?}");
+
+        // Draws that picked nothing still yield nothing
+        assert!(prize_rules
+            .candidates(None, &distribution, &history, &mut rng)
+            .is_empty());
+    }
+
+    #[test]
+    fn candidates_follow_when_exhausted() {
+        let distribution = [(1, 0.2), (2, 0.3)];
+        let history = DrawHistory::default();
+        let mut rng = seeded_rng();
+
+        let no_prize = PrizeRules::default();
+
+        assert_eq!(
+            no_prize.candidates(Some(1), &distribution, &history, &mut rng),
+            [1]
+        );
+
+        let fallback = PrizeRules {
+            when_exhausted: WhenExhausted::Fallback,
+            fallback_coupon_type_id: Some(3),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            fallback.candidates(Some(1), &distribution, &history, &mut rng),
+            [1, 3]
+        );
+        assert_eq!(
+            fallback.candidates(Some(3), &distribution, &history, &mut rng),
+            [3]
+        );
+        assert!(fallback
+            .candidates(None, &distribution, &history, &mut rng)
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn exhausted_prizes_fall_back_to_the_consolation_prize() {
+        let ctx = TestContext::new().await;
+
+        let descriptions = draw_coupons(
+            &ctx,
+            json!({
+                "coupon_types": [
+                    { "description": "Grand prize", "probability": 1.0, "total_quota": 2, "daily_quota": null },
+                    { "description": "Consolation", "probability": 0.0, "total_quota": 1, "daily_quota": null, "fallback": true }
+                ],
+                "prize_rules": { "when_exhausted": "fallback" }
+            }),
+            4,
+        )
+        .await;
+
+        assert_eq!(
+            descriptions,
+            [
+                Some("Grand prize".to_string()),
+                Some("Grand prize".to_string()),
+                Some("Consolation".to_string()),
+                None
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn exhausted_prizes_are_renormalized() {
+        let ctx = TestContext::new().await;
+
+        // Half the draws pick the coupon type that is out from the start, and get the other
+        // instead of nothing
+
+        let descriptions = draw_coupons(
+            &ctx,
+            json!({
+                "coupon_types": [
+                    { "description": "Out", "probability": 0.5, "total_quota": 0, "daily_quota": null },
+                    { "description": "Left", "probability": 0.5, "total_quota": null, "daily_quota": 8 }
+                ],
+                "prize_rules": { "when_exhausted": "renormalize" }
+            }),
+            10,
+        )
+        .await;
+
+        let mut expected = vec![Some("Left".to_string()); 8];
+        expected.extend([None, None]);
+
+        assert_eq!(descriptions, expected);
+
+        // By default, they get nothing
+
+        let descriptions = draw_coupons(
+            &ctx,
+            json!({
+                "coupon_types": [
+                    { "description": "Out", "probability": 1.0, "total_quota": 0, "daily_quota": null },
+                    { "description": "Left", "probability": 0.0, "total_quota": null, "daily_quota": null }
+                ]
+            }),
+            3,
+        )
+        .await;
+
+        assert_eq!(descriptions, [None, None, None]);
+    }
+
+    #[tokio::test]
+    async fn create_campaign_fail_if_prize_rules_invalid() {
+        let ctx = TestContext::new().await;
+
+        // Wins can't be guaranteed from the start, and only one coupon type can be
+        for (coupon_types, prize_rules) in [
+            (
+                vec![json!({ "first_draw": true }), json!({ "first_draw": true })],
+                json!(null),
+            ),
+            (vec![json!({})], json!({ "guaranteed_win_after": 0 })),
+        ] {
+            let (status, _, body) = post_json(
+                &ctx.app,
+                "/campaign",
+                &[],
+                json!({
+                    "coupon_types": coupon_types.into_iter().map(|mut coupon_type| {
+                        coupon_type["description"] = json!("Coupon");
+                        coupon_type["probability"] = json!(0.5);
+                        coupon_type["total_quota"] = json!(null);
+                        coupon_type["daily_quota"] = json!(null);
+                        coupon_type
+                    }).collect::<Vec<_>>(),
+                    "prize_rules": prize_rules
+                }),
+            )
+            .await;
+
+            assert_eq!(status, StatusCode::CONFLICT, "{prize_rules}");
+            assert_eq!(body["code"], "invalid_prize_rules");
+        }
+
+        for (fallbacks, when_exhausted) in [
+            (vec![false, false], "fallback"),
+            (vec![true, false], "no_prize"),
+            (vec![true, true], "fallback"),
+        ] {
+            let (status, _, body) = post_json(
+                &ctx.app,
+                "/campaign",
+                &[],
+                json!({
+                    "coupon_types": fallbacks.iter().map(|&fallback| json!({
+                        "description": "Coupon",
+                        "probability": 0.5,
+                        "total_quota": null,
+                        "daily_quota": null,
+                        "fallback": fallback
+                    })).collect::<Vec<_>>(),
+                    "prize_rules": { "when_exhausted": when_exhausted }
+                }),
+            )
+            .await;
+
+            assert_eq!(
+                status,
+                StatusCode::CONFLICT,
+                "{fallbacks:?} {when_exhausted}"
+            );
+            assert_eq!(body["code"], "invalid_prize_rules");
+        }
+
+        let (status, _, campaign) = post_json(&ctx.app, "/campaign", &[],
+            json!({
+                "coupon_types": [
+                    { "description": "Coupon", "probability": 0.5, "total_quota": null, "daily_quota": null },
+                    { "description": "Consolation", "probability": 0.0, "total_quota": null, "daily_quota": null, "fallback": true }
+                ],
+                "prize_rules": { "when_exhausted": "fallback" }
+            }),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::CREATED);
+
+        let response = ctx
+            .app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/campaign/{}", campaign["id"]))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(
+            body["prize_rules"],
+            json!({ "when_exhausted": "fallback", "guaranteed_win_after": null, "pacing": false })
+        );
+        assert_eq!(body["coupon_types"][0]["fallback"], false);
+        assert_eq!(body["coupon_types"][1]["fallback"], true);
+    }
+
+    #[test]
+    fn pity_guarantees_a_coupon() {
+        let prize_rules = PrizeRules {
+            guaranteed_win_after: Some(2),
+            fallback_coupon_type_id: Some(3),
+            first_draw_coupon_type_id: Some(4),
+            ..Default::default()
+        };
+        let distribution = [(1, 0.2), (2, 0.0)];
+        let mut rng = seeded_rng();
+
+        let history = |draws, losing_streak| DrawHistory {
+            draws,
+            losing_streak,
+        };
+
+        // Coupon types that can't be won aren't guaranteed, the fallback is the last resort
+        assert_eq!(
+            prize_rules.candidates(None, &distribution, &history(5, 2), &mut rng),
+            [1, 3]
+        );
+        assert!(prize_rules
+            .candidates(None, &distribution, &history(5, 1), &mut rng)
+            .is_empty());
+        assert_eq!(
+            prize_rules.candidates(Some(1), &distribution, &history(5, 1), &mut rng),
+            [1]
+        );
+
+        // The first draw gets the first draw coupon type, or what it picked if that has run out
+        assert_eq!(
+            prize_rules.candidates(None, &distribution, &history(0, 0), &mut rng),
+            [4]
+        );
+        assert_eq!(
+            prize_rules.candidates(Some(1), &distribution, &history(0, 0), &mut rng),
+            [4, 1]
+        );
+    }
+
+    #[tokio::test]
+    async fn draws_win_after_a_losing_streak() {
+        let repository = FakeRepository::default();
+        let metrics = Metrics::default();
+        let mut rng = seeded_rng();
+
+        repository.add_user(1);
+        repository.add_campaign(1, None);
+        repository.add_coupon_type(1, 1, 0.0001, None, None);
+        repository.set_prize_rules(
+            1,
+            PrizeRules {
+                guaranteed_win_after: Some(2),
+                ..Default::default()
+            },
+        );
+
+        let mut coupon_type_ids = vec![];
+
+        for _ in 0..6 {
+            // A day later for every draw, with a fresh cache to forget the enrolled campaigns
+            for (_, _, date, _) in repository.draws.lock().unwrap().iter_mut() {
+                *date = date.pred_opt().unwrap();
+            }
+
+            let coupon = draw(
+                &repository,
+                &MemoryCache::default(),
+                &metrics,
+                1,
+                1,
+                chrono::Utc::now(),
+                &mut rng,
+            )
+            .await
+            .unwrap();
+
+            coupon_type_ids.push(coupon.map(|c| c.campaign_coupon_type_id));
+        }
+
+        assert_eq!(coupon_type_ids, [None, None, Some(1), None, None, Some(1)]);
+    }
+
+    #[tokio::test]
+    async fn first_draws_get_the_first_draw_coupon() {
+        let ctx = TestContext::new().await;
+
+        let descriptions = draw_coupons(
+            &ctx,
+            json!({
+                "coupon_types": [
+                    { "description": "Welcome", "probability": 0.0, "total_quota": 1, "daily_quota": null, "first_draw": true },
+                    { "description": "Never", "probability": 0.0, "total_quota": null, "daily_quota": null }
+                ]
+            }),
+            2,
+        )
+        .await;
+
+        assert_eq!(descriptions, [Some("Welcome".to_string()), None]);
+    }
+
+    /// A coupon type of which `left` of the daily quota were left on the day of `last_drawn`
+    fn paced_coupon_type(
+        id: i32,
+        probability: f32,
+        daily_quota: Option<i32>,
+        left: Option<i32>,
+        last_drawn: NaiveDate,
+    ) -> CampaignCouponType {
+        CampaignCouponType {
+            id,
+            description: format!("Coupon type {id}"),
+            probability,
+            total_quota: None,
+            daily_quota,
+            current_quota: None,
+            current_daily_quota: left,
+            last_drawn_date: Some(last_drawn),
+            fallback: false,
+            first_draw: false,
+        }
+    }
+
+    #[test]
+    fn pacing_follows_the_share_of_the_day_left() {
+        let today = NaiveDate::from_ymd_opt(2023, 11, 1).unwrap();
+        let yesterday = today.pred_opt().unwrap();
+
+        let coupon_types = [
+            // On schedule
+            paced_coupon_type(1, 0.2, Some(10), Some(5), today),
+            // Ahead of schedule
+            paced_coupon_type(2, 0.2, Some(10), Some(1), today),
+            // Not drawn from yet today, so behind schedule
+            paced_coupon_type(3, 0.1, Some(10), Some(0), yesterday),
+            // Out for today
+            paced_coupon_type(4, 0.1, Some(10), Some(0), today),
+            // No daily quota to pace
+            paced_coupon_type(5, 0.1, None, None, today),
+        ];
+
+        let at = |hour| today.and_hms_opt(hour, 0, 0).unwrap().and_utc();
+
+        let probabilities = |hour| -> Vec<f32> {
+            paced_distribution(&coupon_types, at(hour))
+                .into_iter()
+                .map(|(_, probability)| (probability * 1000.0).round() / 1000.0)
+                .collect()
+        };
+
+        assert_eq!(probabilities(12), [0.2, 0.04, 0.2, 0.0, 0.1]);
+
+        // Catching up is capped, to four times the probability and then to the campaign's chance
+        // of winning a coupon at all (0.7), which the types behind on schedule take more of
+        assert_eq!(probabilities(21), [0.384, 0.077, 0.192, 0.0, 0.048]);
+
+        // The day has only just started, so the quotas left are as expected
+        assert_eq!(probabilities(0)[2], 0.1);
+    }
+
+    #[test]
+    fn pacing_keeps_the_chance_of_no_coupon() {
+        let today = NaiveDate::from_ymd_opt(2023, 11, 1).unwrap();
+        let late_evening = today.and_hms_opt(21, 0, 0).unwrap().and_utc();
+
+        let coupon_types = [
+            // Not drawn from yet today, so paced at four times its probability
+            paced_coupon_type(1, 0.1, Some(10), Some(10), today),
+            paced_coupon_type(2, 0.2, None, None, today),
+        ];
+
+        // 0.4 and 0.2, scaled down to the campaign's 0.3
+        let distribution = paced_distribution(&coupon_types, late_evening);
+
+        assert!((distribution[0].1 - 0.2).abs() < 1e-6, "{distribution:?}");
+        assert!((distribution[1].1 - 0.1).abs() < 1e-6, "{distribution:?}");
+
+        let mut rng = seeded_rng();
+        let no_coupons = (0..10_000)
+            .filter(|_| pick_coupon_type(&distribution, &mut rng).unwrap().is_none())
+            .count();
+
+        assert!((6_800..7_200).contains(&no_coupons), "{no_coupons}");
+    }
+
+    #[tokio::test]
+    async fn paced_draws_slow_down_ahead_of_schedule() {
+        let repository = FakeRepository::default();
+        let cache = MemoryCache::default();
+        let metrics = Metrics::default();
+        let mut rng = seeded_rng();
+
+        let now = chrono::Utc::now();
+        let noon = now.date_naive().and_hms_opt(12, 0, 0).unwrap().and_utc();
+
+        repository.add_campaign(1, None);
+        repository.add_coupon_type(1, 1, 1.0, None, Some(1000));
+        repository.set_prize_rules(
+            1,
+            PrizeRules {
+                pacing: true,
+                ..Default::default()
+            },
+        );
+
+        // 900 of the 1000 handed out by noon, so drawn at a fifth of the probability
+        {
+            let mut coupon_types = repository.coupon_types.lock().unwrap();
+            coupon_types[0].1.current_daily_quota = Some(100);
+            coupon_types[0].1.last_drawn_date = Some(now.date_naive());
+        }
+
+        let mut coupons = 0;
+
+        for user_id in 1..=300 {
+            repository.add_user(user_id);
+
+            if draw(&repository, &cache, &metrics, user_id, 1, noon, &mut rng)
+                .await
+                .unwrap()
+                .is_some()
+            {
+                coupons += 1;
+            }
+        }
+
+        assert!((40..80).contains(&coupons), "{coupons}");
+
+        // Paced distributions are never cached
+        assert!(cache.probability_distribution(1).await.is_none());
+    }
+}
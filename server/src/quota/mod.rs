@@ -0,0 +1,23 @@
+//! Quota semantics of coupon types. A coupon type can cap the coupons it issues in total, a day,
+//! both or neither, where a `None` quota is unlimited. Running out isn't an error, the draw just
+//! yields no coupon. The DB deducts quotas in `DrawRepository::record`, `test::take_quota` is the
+//! same rules in Rust for the fake repository, and for the tests to check the DB against
+
+use chrono::NaiveDate;
+
+use crate::types::CampaignCouponType;
+
+#[cfg(test)]
+pub mod test;
+
+impl CampaignCouponType {
+    /// The daily quota left on `today`, which is the full daily quota until the first draw of the
+    /// day. A node whose clock is behind that of the last draw shares its day. `None` if unlimited
+    pub fn daily_quota_left(&self, today: NaiveDate) -> Option<i32> {
+        if self.last_drawn_date >= Some(today) {
+            self.current_daily_quota
+        } else {
+            self.daily_quota
+        }
+    }
+}
@@ -0,0 +1,406 @@
+use chrono::NaiveDate;
+
+use crate::types::CampaignCouponType;
+
+/// Coupons that can still be issued on `today`, the lesser of the quotas left. `None` if unlimited
+pub fn quota_left(coupon_type: &CampaignCouponType, today: NaiveDate) -> Option<i32> {
+    match (
+        coupon_type.current_quota,
+        coupon_type.daily_quota_left(today),
+    ) {
+        (Some(total), Some(daily)) => Some(total.min(daily)),
+        (total, daily) => total.or(daily),
+    }
+}
+
+/// Deducts a coupon from the quotas, or leaves them as they are and returns `false` if either has
+/// run out
+pub fn take_quota(coupon_type: &mut CampaignCouponType, today: NaiveDate) -> bool {
+    if quota_left(coupon_type, today).is_some_and(|left| left <= 0) {
+        return false;
+    }
+
+    coupon_type.current_daily_quota = coupon_type.daily_quota_left(today).map(|left| left - 1);
+    coupon_type.current_quota = coupon_type.current_quota.map(|left| left - 1);
+    coupon_type.last_drawn_date = coupon_type.last_drawn_date.max(Some(today));
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quota_left, take_quota};
+    use crate::{
+        repository::{CampaignRepository, DrawRepository, NewCouponType, PgRepository},
+        testing::TestContext,
+        types::CampaignCouponType,
+    };
+
+    use chrono::{Days, NaiveDate, Utc};
+    use proptest::{
+        prelude::*,
+        test_runner::{Config, TestRunner},
+    };
+
+    /// Limited or unlimited, including quotas that are out from the start
+    fn quota() -> impl Strategy<Value = Option<i32>> {
+        proptest::option::of(0..6)
+    }
+
+    /// The number of draws that won the coupon type, on each of a few days
+    fn draws_by_day() -> impl Strategy<Value = Vec<usize>> {
+        proptest::collection::vec(0..8usize, 1..5)
+    }
+
+    fn coupon_type(total_quota: Option<i32>, daily_quota: Option<i32>) -> CampaignCouponType {
+        CampaignCouponType {
+            id: 1,
+            description: "Coupon type 1".to_string(),
+            probability: 1.0,
+            total_quota,
+            daily_quota,
+            current_quota: total_quota,
+            current_daily_quota: daily_quota,
+            last_drawn_date: None,
+            fallback: false,
+            first_draw: false,
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn take_quota_issues_as_many_coupons_as_the_quotas_allow(
+            total_quota in quota(),
+            daily_quota in quota(),
+            draws_by_day in draws_by_day(),
+        ) {
+            let mut coupon_type = coupon_type(total_quota, daily_quota);
+            let first_day = NaiveDate::from_ymd_opt(2023, 11, 1).unwrap();
+            let mut issued_in_total = 0;
+
+            for (day, &draws) in draws_by_day.iter().enumerate() {
+                let today = first_day + Days::new(day as u64);
+                let mut issued_today = 0;
+
+                for _ in 0..draws {
+                    let quota_left = quota_left(&coupon_type, today);
+
+                    if take_quota(&mut coupon_type, today) {
+                        prop_assert!(quota_left.is_none_or(|left| left > 0));
+                        issued_today += 1;
+                    } else {
+                        prop_assert_eq!(quota_left, Some(0));
+                    }
+                }
+
+                issued_in_total += issued_today;
+
+                // Draws only go without a coupon once a quota is exhausted
+                let total_left = total_quota.map(|total| total - (issued_in_total - issued_today));
+                let expected = [Some(draws as i32), total_left, daily_quota]
+                    .into_iter()
+                    .flatten()
+                    .min()
+                    .unwrap();
+
+                prop_assert_eq!(issued_today, expected);
+                prop_assert_eq!(coupon_type.current_quota, total_quota.map(|total| total - issued_in_total));
+
+                if issued_today > 0 {
+                    prop_assert_eq!(coupon_type.current_daily_quota, daily_quota.map(|daily| daily - issued_today));
+                }
+
+                // Quotas never go below 0, unlimited ones stay unlimited
+                prop_assert!(coupon_type.current_quota.map_or(total_quota.is_none(), |left| left >= 0));
+                prop_assert!(coupon_type.current_daily_quota.map_or(daily_quota.is_none(), |left| left >= 0));
+            }
+        }
+    }
+
+    #[test]
+    fn db_deducts_quotas_like_take_quota() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let ctx = runtime.block_on(TestContext::new());
+        let repository = PgRepository::new(ctx.store.db_pool.clone(), ctx.store.metrics.clone());
+        let db_pool = &ctx.store.db_pool;
+
+        let user_ids: Vec<i32> = runtime.block_on(async {
+            sqlx::query_scalar!(
+                "--sql
+                    insert into users (phone)
+                    select '+852 ' || lpad(n::text, 8, '0')
+                    from generate_series(1, 8) as n
+                    returning id;
+                "
+            )
+            .fetch_all(db_pool)
+            .await
+            .unwrap()
+        });
+
+        let fetch_coupon_type = |campaign_id: i32| async move {
+            sqlx::query_as!(
+                CampaignCouponType,
+                "--sql
+                    select id, description, probability, total_quota, daily_quota, current_quota,
+                    current_daily_quota, last_drawn_date, fallback, first_draw
+                from campaign_coupon_types
+                where campaign_id = $1;
+                ",
+                campaign_id
+            )
+            .fetch_one(db_pool)
+            .await
+            .unwrap()
+        };
+
+        // Every case is a campaign of its own in the same DB, which is too slow to create per case
+        let mut runner = TestRunner::new(Config::with_cases(48));
+
+        let result = runner.run(
+            &(quota(), quota(), draws_by_day()),
+            |(total_quota, daily_quota, draws_by_day)| {
+                runtime.block_on(async {
+                    let campaign = repository
+                        .create(
+                            &[NewCouponType {
+                                description: "Coupon type".to_string(),
+                                probability: 1.0,
+                                total_quota,
+                                daily_quota,
+                                fallback: false,
+                                first_draw: false,
+                            }],
+                            None,
+                            None,
+                        )
+                        .await
+                        .unwrap();
+
+                    let mut model = fetch_coupon_type(campaign.id).await;
+
+                    prop_assert_eq!(model.current_quota, total_quota);
+                    prop_assert_eq!(model.current_daily_quota, daily_quota);
+
+                    let today = Utc::now().date_naive();
+
+                    for &draws in &draws_by_day {
+                        for &user_id in &user_ids[..draws] {
+                            let coupon = repository
+                                .record(user_id, campaign.id, &[model.id], today)
+                                .await
+                                .unwrap();
+
+                            prop_assert_eq!(coupon.is_some(), take_quota(&mut model, today));
+                        }
+
+                        let coupon_type = fetch_coupon_type(campaign.id).await;
+
+                        prop_assert_eq!(coupon_type.current_quota, model.current_quota);
+                        prop_assert_eq!(coupon_type.current_daily_quota, model.current_daily_quota);
+                        prop_assert_eq!(coupon_type.last_drawn_date, model.last_drawn_date);
+
+                        // Move on to the next day, by moving the last draw a day back instead
+                        sqlx::query!(
+                            "--sql
+                                update campaign_coupon_types
+                                set last_drawn_date = last_drawn_date - 1
+                                where campaign_id = $1;
+                            ",
+                            campaign.id
+                        )
+                        .execute(db_pool)
+                        .await
+                        .unwrap();
+
+                        sqlx::query!(
+                            "--sql
+                                delete from draws where campaign_id = $1;
+                            ",
+                            campaign.id
+                        )
+                        .execute(db_pool)
+                        .await
+                        .unwrap();
+
+                        model.last_drawn_date =
+                            model.last_drawn_date.map(|date| date - Days::new(1));
+                    }
+
+                    Ok(())
+                })
+            },
+        );
+
+        if let Err(e) = result {
+            panic!("{e}");
+        }
+    }
+
+    #[tokio::test]
+    async fn clocks_behind_the_last_draw_dont_reset_the_daily_quota() {
+        let ctx = TestContext::new().await;
+        let repository = PgRepository::new(ctx.store.db_pool.clone(), ctx.store.metrics.clone());
+
+        let campaign = repository
+            .create(
+                &[NewCouponType {
+                    description: "Coupon type".to_string(),
+                    probability: 1.0,
+                    total_quota: None,
+                    daily_quota: Some(2),
+                    fallback: false,
+                    first_draw: false,
+                }],
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+
+        let mut model = sqlx::query_as!(
+            CampaignCouponType,
+            "--sql
+                select id, description, probability, total_quota, daily_quota, current_quota,
+                    current_daily_quota, last_drawn_date, fallback, first_draw
+                from campaign_coupon_types
+                where campaign_id = $1;
+            ",
+            campaign.id
+        )
+        .fetch_one(&ctx.store.db_pool)
+        .await
+        .unwrap();
+
+        let user_ids: Vec<i32> = sqlx::query_scalar!(
+            "--sql
+                insert into users (phone)
+                select '+852 ' || lpad(n::text, 8, '0')
+                from generate_series(1, 4) as n
+                returning id;
+            "
+        )
+        .fetch_all(&ctx.store.db_pool)
+        .await
+        .unwrap();
+
+        // Around midnight, draws alternate between a node that is already on the next day and one
+        // that is still on the day before
+        let today = NaiveDate::from_ymd_opt(2023, 11, 1).unwrap();
+        let tomorrow = today + Days::new(1);
+
+        for (&user_id, date, won) in [
+            (&user_ids[0], tomorrow, true),
+            (&user_ids[1], today, true),
+            (&user_ids[2], tomorrow, false),
+            (&user_ids[3], today, false),
+        ] {
+            let coupon = repository
+                .record(user_id, campaign.id, &[model.id], date)
+                .await
+                .unwrap();
+
+            assert_eq!(coupon.is_some(), won, "Draw on {date}");
+            assert_eq!(take_quota(&mut model, date), won, "Draw on {date}");
+        }
+
+        let coupon_type = sqlx::query_as!(
+            CampaignCouponType,
+            "--sql
+                select id, description, probability, total_quota, daily_quota, current_quota,
+                    current_daily_quota, last_drawn_date, fallback, first_draw
+                from campaign_coupon_types
+                where campaign_id = $1;
+            ",
+            campaign.id
+        )
+        .fetch_one(&ctx.store.db_pool)
+        .await
+        .unwrap();
+
+        assert_eq!(coupon_type.current_daily_quota, Some(0));
+        assert_eq!(coupon_type.last_drawn_date, Some(tomorrow));
+        assert_eq!(model.current_daily_quota, Some(0));
+        assert_eq!(model.last_drawn_date, Some(tomorrow));
+    }
+
+    #[tokio::test]
+    async fn concurrent_draws_never_take_more_than_the_quota() {
+        let ctx = TestContext::new().await;
+        let repository = PgRepository::new(ctx.store.db_pool.clone(), ctx.store.metrics.clone());
+
+        let campaign = repository
+            .create(
+                &[NewCouponType {
+                    description: "Coupon type".to_string(),
+                    probability: 1.0,
+                    total_quota: Some(5),
+                    daily_quota: Some(3),
+                    fallback: false,
+                    first_draw: false,
+                }],
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+
+        let coupon_type_id = sqlx::query_scalar!(
+            "--sql
+                select id from campaign_coupon_types where campaign_id = $1;
+            ",
+            campaign.id
+        )
+        .fetch_one(&ctx.store.db_pool)
+        .await
+        .unwrap();
+
+        let user_ids: Vec<i32> = sqlx::query_scalar!(
+            "--sql
+                insert into users (phone)
+                select '+852 ' || lpad(n::text, 8, '0')
+                from generate_series(1, 20) as n
+                returning id;
+            "
+        )
+        .fetch_all(&ctx.store.db_pool)
+        .await
+        .unwrap();
+
+        let today = Utc::now().date_naive();
+
+        let draws = user_ids.into_iter().map(|user_id| {
+            let repository =
+                PgRepository::new(ctx.store.db_pool.clone(), ctx.store.metrics.clone());
+
+            tokio::spawn(async move {
+                repository
+                    .record(user_id, campaign.id, &[coupon_type_id], today)
+                    .await
+            })
+        });
+
+        let mut coupons = 0;
+
+        for draw in draws.collect::<Vec<_>>() {
+            // Exhausted quotas aren't errors, every draw is recorded
+            if draw.await.unwrap().unwrap().is_some() {
+                coupons += 1;
+            }
+        }
+
+        assert_eq!(coupons, 3);
+
+        let current_daily_quota = sqlx::query_scalar!(
+            "--sql
+                select current_daily_quota from campaign_coupon_types where id = $1;
+            ",
+            coupon_type_id
+        )
+        .fetch_one(&ctx.store.db_pool)
+        .await
+        .unwrap();
+
+        assert_eq!(current_daily_quota, Some(0));
+    }
+}
@@ -0,0 +1,72 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+
+use super::{Bucket, Limit, RateLimiter};
+
+/// Keeps the buckets in the memory of the process, so every node limits on its own. Buckets left
+/// idle for `idle_time` are evicted, which has to be at least the longest refill time so that
+/// only full buckets are
+pub struct MemoryRateLimiter {
+    buckets: moka::future::Cache<String, Arc<Mutex<Bucket>>>,
+}
+
+impl MemoryRateLimiter {
+    pub fn new(max_buckets: u64, idle_time: Duration) -> Self {
+        Self {
+            buckets: moka::future::Cache::builder()
+                .max_capacity(max_buckets)
+                .time_to_idle(idle_time)
+                .build(),
+        }
+    }
+}
+
+#[async_trait]
+impl RateLimiter for MemoryRateLimiter {
+    async fn take(&self, buckets: &[(&str, Limit)]) -> Result<(), (usize, Duration)> {
+        let now = Instant::now();
+        let mut entries = Vec::with_capacity(buckets.len());
+
+        for &(key, limit) in buckets {
+            let bucket = self
+                .buckets
+                .get_with_by_ref(key, async {
+                    Arc::new(Mutex::new(Bucket::full(limit, now)))
+                })
+                .await;
+
+            entries.push(bucket);
+        }
+
+        // Locked in the order of their keys, so that requests sharing buckets can't deadlock
+        let mut order: Vec<usize> = (0..buckets.len()).collect();
+        order.sort_by_key(|&index| buckets[index].0);
+
+        let mut guards: Vec<_> = order
+            .into_iter()
+            .map(|index| (index, entries[index].lock().unwrap()))
+            .collect();
+        guards.sort_by_key(|&(index, _)| index);
+
+        // Taken from copies, which only replace the buckets once every one of them had a token
+        let mut taken = Vec::with_capacity(guards.len());
+
+        for (index, bucket) in &guards {
+            let mut bucket = **bucket;
+
+            bucket
+                .take(buckets[*index].1, now)
+                .map_err(|retry_after| (*index, retry_after))?;
+
+            taken.push(bucket);
+        }
+
+        for ((_, bucket), taken) in guards.iter_mut().zip(taken) {
+            **bucket = taken;
+        }
+
+        Ok(())
+    }
+}
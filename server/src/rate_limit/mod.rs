@@ -0,0 +1,232 @@
+//! Token bucket rate limiting of `/draw`, per user and per client IP. The enrolled-campaigns cache
+//! already turns away repeated draws cheaply, this stops a client from hammering the DB with draws
+//! for users or campaigns that fail further down
+
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, Request},
+    middleware::Next,
+    response::Response,
+};
+use serde::Deserialize;
+
+use crate::config::RateLimitConfig;
+use crate::draw::DrawPayload;
+use crate::error::AppError;
+use crate::extract::read_body;
+use crate::store::Store;
+
+mod memory_rate_limiter;
+mod redis_rate_limiter;
+mod test;
+
+pub use memory_rate_limiter::MemoryRateLimiter;
+pub use redis_rate_limiter::RedisRateLimiter;
+
+/// Which [`RateLimiter`] implementation the service runs with
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitBackend {
+    /// Shared by every node, see [`RedisRateLimiter`]
+    Redis,
+    /// Local to the process, so every node limits on its own, see [`MemoryRateLimiter`]
+    Memory,
+}
+
+impl FromStr for RateLimitBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "redis" => Ok(Self::Redis),
+            "memory" => Ok(Self::Memory),
+            _ => Err("expected redis or memory".to_string()),
+        }
+    }
+}
+
+/// A bucket holds up to `burst` tokens and regains `per_minute` of them a minute. Every request
+/// takes a token
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limit {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+impl Limit {
+    /// How long a bucket takes to refill from empty, after which it is as good as new
+    pub fn refill_time(self) -> Duration {
+        Duration::from_secs_f64(self.burst as f64 * 60.0 / self.per_minute as f64)
+    }
+
+    fn per_sec(self) -> f64 {
+        self.per_minute as f64 / 60.0
+    }
+}
+
+impl RateLimitConfig {
+    pub fn user_limit(&self) -> Limit {
+        Limit {
+            burst: self.user_burst,
+            per_minute: self.user_per_minute,
+        }
+    }
+
+    pub fn ip_limit(&self) -> Limit {
+        Limit {
+            burst: self.ip_burst,
+            per_minute: self.ip_per_minute,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    pub fn full(limit: Limit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst as f64,
+            updated_at: now,
+        }
+    }
+
+    /// Takes a token, or returns how long until one is available
+    pub fn take(&mut self, limit: Limit, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * limit.per_sec()).min(limit.burst as f64);
+        self.updated_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - self.tokens) / limit.per_sec(),
+            ))
+        }
+    }
+}
+
+/// Where the token buckets are kept. Like the cache, a rate limiter never fails a request: if its
+/// backend is unavailable, requests are let through
+#[async_trait]
+pub trait RateLimiter: Send + Sync {
+    /// Takes a token from each of the buckets, keyed by name, or from none of them if any is
+    /// empty. Returns the index of the first empty bucket and how long until it has a token
+    async fn take(&self, buckets: &[(&str, Limit)]) -> Result<(), (usize, Duration)>;
+}
+
+/// The IP of the client, taken from the last `X-Forwarded-For` entry (the one the proxy in front
+/// of the service added) if it is trusted, or the peer address of the connection otherwise
+pub fn client_ip(
+    peer: Option<SocketAddr>,
+    headers: &HeaderMap,
+    trust_forwarded_for: bool,
+) -> Option<IpAddr> {
+    if trust_forwarded_for {
+        let forwarded_ip = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .last()
+            .and_then(|ip| ip.trim().parse().ok());
+
+        if forwarded_ip.is_some() {
+            return forwarded_ip;
+        }
+    }
+
+    peer.map(|peer| peer.ip())
+}
+
+/// Takes a token from the buckets of the client IP and of the user, where known, or from neither if
+/// either is empty. Otherwise requests turned away per IP would still drain the user's bucket
+async fn check_draw(
+    store: &Store,
+    user_id: Option<i32>,
+    ip: Option<IpAddr>,
+) -> Result<(), AppError> {
+    let Some(rate_limiter) = &store.rate_limiter else {
+        return Ok(());
+    };
+
+    let config = &store.config.rate_limit;
+
+    let mut buckets = vec![];
+
+    if let Some(ip) = ip {
+        buckets.push(("ip", format!("ip-{ip}"), config.ip_limit()));
+    }
+
+    if let Some(user_id) = user_id {
+        buckets.push(("user", format!("user-{user_id}"), config.user_limit()));
+    }
+
+    if buckets.is_empty() {
+        return Ok(());
+    }
+
+    let keys: Vec<_> = buckets
+        .iter()
+        .map(|(_, key, limit)| (key.as_str(), *limit))
+        .collect();
+
+    if let Err((index, retry_after)) = rate_limiter.take(&keys).await {
+        let scope = buckets[index].0;
+
+        tracing::info!(scope, ?retry_after, "Rate limited");
+
+        store.metrics.rate_limited.with_label_values(&[scope]).inc();
+
+        return Err(AppError::RateLimited { retry_after });
+    }
+
+    Ok(())
+}
+
+/// Rate limits `/draw` ahead of every other layer, so that retries with an `Idempotency-Key` are
+/// limited too. Every request takes a token from its IP's bucket. That includes bodies that aren't
+/// a draw, on purpose: they still cost a request, and would otherwise be a way around the limit.
+/// Only draws have a user's bucket to take from, the rest are let through for the handler to reject
+pub(super) async fn limit_draws(
+    State(store): State<Store>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Result<Response, AppError> {
+    if store.rate_limiter.is_none() {
+        return Ok(next.run(request).await);
+    }
+
+    let (parts, body) = request.into_parts();
+
+    let ip = client_ip(
+        parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|&ConnectInfo(peer)| peer),
+        &parts.headers,
+        store.config.rate_limit.trust_forwarded_for,
+    );
+
+    let body = read_body(body).await?;
+
+    let user_id = serde_json::from_slice::<DrawPayload>(&body)
+        .ok()
+        .map(|payload| payload.user_id);
+
+    check_draw(&store, user_id, ip).await?;
+
+    Ok(next.run(Request::from_parts(parts, Body::from(body))).await)
+}
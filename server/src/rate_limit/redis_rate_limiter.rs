@@ -0,0 +1,108 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use super::{Limit, RateLimiter};
+
+/// Refills the buckets at `KEYS`, with the burst and rate per minute of each in `ARGV`, and takes a
+/// token from every one of them if none is empty, in one step. It runs on the clock of Redis so
+/// that the nodes' clocks don't matter. Returns `{0, 0}` if the tokens were taken, or the (1-based)
+/// index of the first empty bucket and the milliseconds until it has a token. Buckets expire once
+/// they would have refilled
+const TAKE_SCRIPT: &str = r#"
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+
+local taken = {}
+
+for i, key in ipairs(KEYS) do
+    local burst = tonumber(ARGV[2 * i - 1])
+    local per_ms = tonumber(ARGV[2 * i]) / 60000
+
+    local bucket = redis.call('HMGET', key, 'tokens', 'updated_at')
+    local tokens = tonumber(bucket[1]) or burst
+    local updated_at = tonumber(bucket[2]) or now
+
+    tokens = math.min(burst, tokens + math.max(0, now - updated_at) * per_ms)
+
+    if tokens < 1 then
+        return {i, math.ceil((1 - tokens) / per_ms)}
+    end
+
+    taken[i] = {tokens - 1, math.ceil(burst / per_ms)}
+end
+
+for i, key in ipairs(KEYS) do
+    redis.call('HSET', key, 'tokens', tostring(taken[i][1]), 'updated_at', now)
+    redis.call('PEXPIRE', key, taken[i][2])
+end
+
+return {0, 0}
+"#;
+
+/// Shares the buckets between every node of the service. Requests are let through while Redis is
+/// unavailable, as the DB still stops repeated draws
+pub struct RedisRateLimiter {
+    redis_pool: deadpool_redis::Pool,
+    key_prefix: String,
+    script: redis::Script,
+    degraded: AtomicBool,
+}
+
+impl RedisRateLimiter {
+    pub fn new(redis_pool: deadpool_redis::Pool, key_prefix: impl Into<String>) -> Self {
+        Self {
+            redis_pool,
+            key_prefix: key_prefix.into(),
+            script: redis::Script::new(TAKE_SCRIPT),
+            degraded: AtomicBool::new(false),
+        }
+    }
+
+    fn bucket_key(&self, key: &str) -> String {
+        format!("{}rate-limit:{key}", self.key_prefix)
+    }
+
+    async fn try_take(&self, buckets: &[(&str, Limit)]) -> Result<(usize, u64), String> {
+        let mut redis = self.redis_pool.get().await.map_err(|e| e.to_string())?;
+        let mut invocation = self.script.prepare_invoke();
+
+        for &(key, limit) in buckets {
+            invocation
+                .key(self.bucket_key(key))
+                .arg(limit.burst)
+                .arg(limit.per_minute);
+        }
+
+        invocation
+            .invoke_async(&mut redis)
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[async_trait]
+impl RateLimiter for RedisRateLimiter {
+    async fn take(&self, buckets: &[(&str, Limit)]) -> Result<(), (usize, Duration)> {
+        match self.try_take(buckets).await {
+            Ok((empty, retry_after_ms)) => {
+                if self.degraded.swap(false, Ordering::Relaxed) {
+                    tracing::info!("Rate limiting with Redis again");
+                }
+
+                match empty {
+                    0 => Ok(()),
+                    index => Err((index - 1, Duration::from_millis(retry_after_ms))),
+                }
+            }
+            Err(error) => {
+                if !self.degraded.swap(true, Ordering::Relaxed) {
+                    tracing::warn!(%error, "Redis is unavailable, draws are not rate limited");
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
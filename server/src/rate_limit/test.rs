@@ -0,0 +1,316 @@
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, SocketAddr};
+    use std::time::{Duration, Instant};
+
+    use crate::{
+        cache::CacheBackend,
+        config::Config,
+        idempotency::{IDEMPOTENCY_KEY_HEADER, IDEMPOTENT_REPLAYED_HEADER},
+        rate_limit::{client_ip, Bucket, Limit, RateLimitBackend},
+        testing::{post_json, TestContext},
+    };
+
+    use axum::{
+        body::Body,
+        extract::ConnectInfo,
+        http::{self, HeaderMap, Method, Request, StatusCode},
+        response::Response,
+        Router,
+    };
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    async fn draw(app: &Router, user_id: i32, peer: Option<SocketAddr>) -> Response {
+        let mut request = Request::builder()
+            .uri("/draw")
+            .method(Method::POST)
+            .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref());
+
+        if let Some(peer) = peer {
+            request = request.extension(ConnectInfo(peer));
+        }
+
+        app.clone()
+            .oneshot(
+                request
+                    .body(Body::from(
+                        serde_json::to_string(&json!({
+                            "campaign_id": 0,
+                            "user_id": user_id
+                        }))
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+    }
+
+    fn limit_draws(config: &mut Config) {
+        config.cache.backend = CacheBackend::Memory;
+        config.rate_limit.backend = RateLimitBackend::Memory;
+        config.rate_limit.user_burst = 2;
+        config.rate_limit.user_per_minute = 1;
+        config.rate_limit.ip_burst = 3;
+        config.rate_limit.ip_per_minute = 1;
+    }
+
+    #[test]
+    fn bucket_refills_at_the_configured_rate() {
+        let limit = Limit {
+            burst: 2,
+            per_minute: 60,
+        };
+        let start = Instant::now();
+        let mut bucket = Bucket::full(limit, start);
+
+        assert_eq!(bucket.take(limit, start), Ok(()));
+        assert_eq!(bucket.take(limit, start), Ok(()));
+        assert_eq!(bucket.take(limit, start), Err(Duration::from_secs(1)));
+
+        // Half a token later
+
+        let retry_after = bucket
+            .take(limit, start + Duration::from_millis(500))
+            .unwrap_err();
+
+        assert!(retry_after <= Duration::from_millis(500), "{retry_after:?}");
+        assert_eq!(bucket.take(limit, start + Duration::from_secs(1)), Ok(()));
+
+        // Never more than the burst, however long the bucket was left alone
+
+        let later = start + Duration::from_secs(3600);
+
+        assert_eq!(bucket.take(limit, later), Ok(()));
+        assert_eq!(bucket.take(limit, later), Ok(()));
+        assert!(bucket.take(limit, later).is_err());
+    }
+
+    #[test]
+    fn client_ip_trusts_forwarded_for_only_when_told_to() {
+        let peer: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let mut headers = HeaderMap::new();
+
+        headers.insert("x-forwarded-for", "1.1.1.1, 2.2.2.2".parse().unwrap());
+
+        assert_eq!(
+            client_ip(Some(peer), &headers, false),
+            Some("10.0.0.1".parse::<IpAddr>().unwrap())
+        );
+
+        // The last entry is the one added by the proxy, the others may be made up by the client
+        assert_eq!(
+            client_ip(Some(peer), &headers, true),
+            Some("2.2.2.2".parse::<IpAddr>().unwrap())
+        );
+        assert_eq!(
+            client_ip(Some(peer), &HeaderMap::new(), true),
+            Some("10.0.0.1".parse::<IpAddr>().unwrap())
+        );
+        assert_eq!(client_ip(None, &HeaderMap::new(), false), None);
+    }
+
+    #[tokio::test]
+    async fn draws_are_rate_limited_per_user() {
+        let ctx = TestContext::with_config(limit_draws).await;
+
+        // Even draws that fail further down take a token
+
+        for _ in 0..2 {
+            let draw_response = draw(&ctx.app, 1, None).await;
+
+            assert_eq!(draw_response.status(), StatusCode::NOT_FOUND);
+        }
+
+        let draw_response = draw(&ctx.app, 1, None).await;
+
+        assert_eq!(draw_response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let retry_after: u64 = draw_response.headers()[http::header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+
+        assert!((1..=60).contains(&retry_after), "{retry_after}");
+
+        let body = hyper::body::to_bytes(draw_response.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(body["code"], "rate_limited");
+        assert_eq!(body["details"]["retry_after_secs"], retry_after);
+
+        // Other users have buckets of their own
+
+        let draw_response = draw(&ctx.app, 2, None).await;
+
+        assert_eq!(draw_response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn draws_are_rate_limited_per_client_ip() {
+        let ctx = TestContext::with_config(limit_draws).await;
+        let peer: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+
+        for user_id in 1..=3 {
+            let draw_response = draw(&ctx.app, user_id, Some(peer)).await;
+
+            assert_eq!(draw_response.status(), StatusCode::NOT_FOUND);
+        }
+
+        let draw_response = draw(&ctx.app, 4, Some(peer)).await;
+
+        assert_eq!(draw_response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let draw_response = draw(&ctx.app, 4, Some("10.0.0.2:4000".parse().unwrap())).await;
+
+        assert_eq!(draw_response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn malformed_draws_are_rate_limited_per_client_ip() {
+        let ctx = TestContext::with_config(limit_draws).await;
+        let peer: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+
+        let malformed_draw = || {
+            ctx.app.clone().oneshot(
+                Request::builder()
+                    .uri("/draw")
+                    .method(Method::POST)
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .extension(ConnectInfo(peer))
+                    .body(Body::from("{ \"user_id\": \"everyone\" }"))
+                    .unwrap(),
+            )
+        };
+
+        for _ in 0..3 {
+            let draw_response = malformed_draw().await.unwrap();
+
+            assert!(draw_response.status().is_client_error());
+            assert_ne!(draw_response.status(), StatusCode::TOO_MANY_REQUESTS);
+        }
+
+        let draw_response = malformed_draw().await.unwrap();
+
+        assert_eq!(draw_response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // Well-formed draws from the IP are turned away as well
+
+        let draw_response = draw(&ctx.app, 1, Some(peer)).await;
+
+        assert_eq!(draw_response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn idempotent_retries_are_rate_limited() {
+        let ctx = TestContext::with_config(limit_draws).await;
+        let payload = json!({ "campaign_id": 0, "user_id": 1 });
+
+        let (status, _, _) = post_json(
+            &ctx.app,
+            "/draw",
+            &[(IDEMPOTENCY_KEY_HEADER, "retry")],
+            payload.clone(),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, headers, _) = post_json(
+            &ctx.app,
+            "/draw",
+            &[(IDEMPOTENCY_KEY_HEADER, "retry")],
+            payload.clone(),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(headers[IDEMPOTENT_REPLAYED_HEADER], "true");
+
+        // Replays take a token like any other draw
+
+        let (status, _, body) = post_json(
+            &ctx.app,
+            "/draw",
+            &[(IDEMPOTENCY_KEY_HEADER, "retry")],
+            payload,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(body["code"], "rate_limited");
+    }
+
+    /// Turns away the third draw of a user, while a draw from another user of the same IP, which
+    /// has one token left then, still goes through
+    async fn assert_rejected_draws_take_no_tokens(app: &Router) {
+        let peer: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+
+        for _ in 0..2 {
+            let draw_response = draw(app, 1, Some(peer)).await;
+
+            assert_eq!(draw_response.status(), StatusCode::NOT_FOUND);
+        }
+
+        for _ in 0..3 {
+            let draw_response = draw(app, 1, Some(peer)).await;
+
+            assert_eq!(draw_response.status(), StatusCode::TOO_MANY_REQUESTS);
+        }
+
+        let draw_response = draw(app, 2, Some(peer)).await;
+
+        assert_eq!(draw_response.status(), StatusCode::NOT_FOUND);
+
+        let draw_response = draw(app, 3, Some(peer)).await;
+
+        assert_eq!(draw_response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn rate_limited_draws_take_no_tokens() {
+        let ctx = TestContext::with_config(limit_draws).await;
+
+        assert_rejected_draws_take_no_tokens(&ctx.app).await;
+    }
+
+    #[tokio::test]
+    async fn draws_are_let_through_when_redis_is_unreachable() {
+        let ctx = TestContext::with_config(|config| {
+            limit_draws(config);
+            config.rate_limit.backend = RateLimitBackend::Redis;
+            // Nothing listens on port 1, so every connection is refused
+            config.redis.url = Some("redis://127.0.0.1:1/".to_string());
+        })
+        .await;
+
+        for _ in 0..3 {
+            let draw_response = draw(&ctx.app, 1, None).await;
+
+            assert_eq!(draw_response.status(), StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[tokio::test]
+    async fn draws_are_rate_limited_with_redis() {
+        // The rate limiter doesn't follow the backend of the cache
+        let ctx = TestContext::with_config(|config| {
+            limit_draws(config);
+            config.rate_limit.backend = RateLimitBackend::Redis;
+        })
+        .await;
+
+        assert_rejected_draws_take_no_tokens(&ctx.app).await;
+
+        let draw_response = draw(&ctx.app, 1, None).await;
+
+        assert_eq!(draw_response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(draw_response
+            .headers()
+            .contains_key(http::header::RETRY_AFTER));
+    }
+}
@@ -1,18 +1,17 @@
-use std::sync::Arc;
-
-use axum::{extract::State, response::IntoResponse, Json};
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Extension,
+};
 use hyper::StatusCode;
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
+use crate::api_key::AuthorizedApiKey;
+use crate::error::{AppError, ErrorCode};
+use crate::extract::Json;
+use crate::repository::{CouponRepository, PgRepository};
 use crate::store::Store;
-use crate::types::CampaignCoupon;
-
-#[derive(Serialize, Deserialize, ToSchema)]
-pub(super) enum RedeemError {
-    #[schema(example = "User or coupon not found")]
-    Conflict(String),
-}
 
 #[derive(Serialize, Deserialize, ToSchema)]
 pub(super) struct RedeemPayload {
@@ -24,38 +23,40 @@ pub(super) struct RedeemPayload {
     post,
     path = "/redeem",
     request_body = RedeemPayload,
+    params(
+        ("Idempotency-Key" = Option<String>, Header, description = "Retries with the same key get the original response, for 24 hours by default")
+    ),
     responses(
         (status = 200, description = "Coupon redeemed successfully", body = CampaignCoupon),
-        (status = 409, description = "Coupon not found, or coupon has already been redeemed", body = RedeemError),
+        (status = 401, description = "API key is missing, invalid or revoked", body = ErrorBody),
+        (status = 409, description = "Coupon not found, coupon has already been redeemed, or coupon is outside of the API key's campaigns, or a request with the same Idempotency-Key is in progress", body = ErrorBody),
+        (status = 422, description = "Idempotency-Key was already used for a different request", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+        (status = 503, description = "Database temporarily unavailable", body = ErrorBody)
+    ),
+    security(
+        ("api_key" = [])
     )
 )]
 pub(super) async fn redeem_coupon(
-    State(store): State<Arc<Store>>,
+    State(store): State<Store>,
+    Extension(api_key): Extension<AuthorizedApiKey>,
     Json(payload): Json<RedeemPayload>,
-) -> impl IntoResponse {
-    let db_pool = store.lock().await.db_pool.clone();
+) -> Result<Response, AppError> {
+    let repository = PgRepository::new(store.db_pool.clone(), store.metrics.clone());
 
-    let query = sqlx::query_as!(
-        CampaignCoupon,
-        "--sql
-            update campaign_coupons
-            set redeemed = true
-            where id = $1 and redeemed = false
-            returning *;
-        ",
-        payload.coupon_id
-    )
-    .fetch_one(&db_pool)
-    .await;
+    let query = repository.redeem(payload.coupon_id, api_key.id).await?;
 
     match query {
-        Err(_) => (
-            StatusCode::CONFLICT,
-            Json(RedeemError::Conflict(
-                "Coupon not found, or it has already been redeemed".to_string(),
-            )),
-        )
-            .into_response(),
-        Ok(coupon) => (StatusCode::OK, Json(coupon)).into_response(),
+        None => Err(AppError::new(ErrorCode::CouponNotRedeemable, "Coupon not found, it has already been redeemed, or it doesn't belong to the API key's campaigns")),
+        Some(coupon) => {
+            store
+                .metrics
+                .coupons_redeemed
+                .with_label_values(&[&coupon.campaign_coupon_type_id.to_string()])
+                .inc();
+
+            Ok((StatusCode::OK, Json(coupon)).into_response())
+        }
     }
 }
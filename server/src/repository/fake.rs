@@ -0,0 +1,485 @@
+//! In-memory repository for testing the services without a database. It follows the semantics of
+//! [`PgRepository`](super::PgRepository), errors included
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use super::*;
+use crate::eligibility::normalize_phone;
+use crate::error::ErrorCode;
+use crate::quota::test::take_quota;
+
+/// `(user id, campaign id, date, coupon)`
+pub type FakeDraw = (i32, i32, chrono::NaiveDate, Option<CampaignCoupon>);
+
+#[derive(Default)]
+pub struct FakeRepository {
+    pub users: Mutex<Vec<User>>,
+    /// Campaign id to its eligibility rules
+    pub campaigns: Mutex<HashMap<i32, Option<EligibilityRules>>>,
+    /// Campaign id to its prize rules, if not the default
+    pub prize_rules: Mutex<HashMap<i32, PrizeRules>>,
+    /// Coupon types along with the id of their campaign
+    pub coupon_types: Mutex<Vec<(i32, CampaignCouponType)>>,
+    pub draws: Mutex<Vec<FakeDraw>>,
+}
+
+/// A user created just now, without any details
+fn fake_user(id: i32) -> User {
+    User {
+        id,
+        phone: None,
+        name: None,
+        email: None,
+        locale: None,
+        membership_tier: None,
+        marketing_opt_in: false,
+        marketing_opt_in_at: None,
+        phone_verified_at: None,
+        created_at: chrono::Utc::now(),
+        deleted_at: None,
+        anonymized_at: None,
+    }
+}
+
+impl FakeRepository {
+    pub fn add_user(&self, id: i32) -> User {
+        let user = User {
+            phone: Some(format!("+852 {id:08}")),
+            ..fake_user(id)
+        };
+
+        self.users.lock().unwrap().push(user.clone());
+
+        user
+    }
+
+    pub fn add_campaign(&self, id: i32, eligibility: Option<EligibilityRules>) {
+        self.campaigns.lock().unwrap().insert(id, eligibility);
+    }
+
+    pub fn set_prize_rules(&self, campaign_id: i32, prize_rules: PrizeRules) {
+        self.prize_rules
+            .lock()
+            .unwrap()
+            .insert(campaign_id, prize_rules);
+    }
+
+    pub fn add_coupon_type(
+        &self,
+        id: i32,
+        campaign_id: i32,
+        probability: f32,
+        total_quota: Option<i32>,
+        daily_quota: Option<i32>,
+    ) {
+        self.coupon_types.lock().unwrap().push((
+            campaign_id,
+            CampaignCouponType {
+                id,
+                description: format!("Coupon type {id}"),
+                probability,
+                total_quota,
+                daily_quota,
+                current_quota: total_quota,
+                current_daily_quota: daily_quota,
+                last_drawn_date: None,
+                fallback: false,
+                first_draw: false,
+            },
+        ));
+    }
+}
+
+fn phone_taken(phone: &str) -> AppError {
+    AppError::new(
+        ErrorCode::PhoneTaken,
+        format!("Phone number {phone} is registered by another user"),
+    )
+}
+
+impl FakeRepository {
+    fn matches(&self, user: &User, filter: &UserFilter) -> bool {
+        let created_date = user.created_at.naive_utc().date();
+
+        user.deleted_at.is_none()
+            && filter.phone.as_deref().is_none_or(|phone| {
+                user.phone.as_deref().map(normalize_phone) == Some(normalize_phone(phone))
+            })
+            && filter.created_from.is_none_or(|from| created_date >= from)
+            && filter.created_to.is_none_or(|to| created_date <= to)
+            && filter
+                .verified
+                .is_none_or(|verified| user.phone_verified_at.is_some() == verified)
+            && filter.drawn_in_campaign.is_none_or(|campaign_id| {
+                self.draws
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .any(|&(u, c, _, _)| (u, c) == (user.id, campaign_id))
+            })
+    }
+
+    fn check_phone_free(&self, users: &[User], id: i32, phone: &str) -> Result<(), AppError> {
+        if users.iter().any(|u| {
+            u.id != id
+                && u.deleted_at.is_none()
+                && u.phone.as_deref().map(normalize_phone) == Some(normalize_phone(phone))
+        }) {
+            return Err(phone_taken(phone));
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl UserRepository for FakeRepository {
+    async fn list(
+        &self,
+        filter: &UserFilter,
+        cursor: Option<i32>,
+        limit: i64,
+    ) -> Result<Vec<User>, AppError> {
+        let mut users: Vec<User> = self
+            .users
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|u| cursor.is_none_or(|cursor| u.id < cursor))
+            .cloned()
+            .collect();
+
+        users.retain(|u| self.matches(u, filter));
+        users.sort_by_key(|u| std::cmp::Reverse(u.id));
+        users.truncate(limit.max(0) as usize);
+
+        Ok(users)
+    }
+
+    async fn count(&self, filter: &UserFilter) -> Result<i64, AppError> {
+        let users = self.users.lock().unwrap().clone();
+
+        Ok(users.iter().filter(|u| self.matches(u, filter)).count() as i64)
+    }
+
+    async fn create(&self, phone: &str) -> Result<User, AppError> {
+        let mut users = self.users.lock().unwrap();
+        let id = users.iter().map(|u| u.id).max().unwrap_or(0) + 1;
+
+        self.check_phone_free(&users, id, phone)?;
+
+        let user = User {
+            phone: Some(phone.to_string()),
+            ..fake_user(id)
+        };
+
+        users.push(user.clone());
+
+        Ok(user)
+    }
+
+    async fn find(&self, id: i32) -> Result<Option<User>, AppError> {
+        Ok(self
+            .users
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|u| u.id == id)
+            .cloned())
+    }
+
+    async fn find_active(&self, id: i32) -> Result<Option<User>, AppError> {
+        Ok(self.find(id).await?.filter(|u| u.deleted_at.is_none()))
+    }
+
+    async fn update(&self, id: i32, update: &UserUpdate) -> Result<Option<User>, AppError> {
+        let mut users = self.users.lock().unwrap();
+
+        if let Some(phone) = &update.phone {
+            self.check_phone_free(&users, id, phone)?;
+        }
+
+        let Some(user) = users
+            .iter_mut()
+            .find(|u| u.id == id && u.deleted_at.is_none())
+        else {
+            return Ok(None);
+        };
+
+        let now = chrono::Utc::now();
+
+        // Changing the phone number invalidates its verification
+        if let Some(phone) = &update.phone {
+            if user.phone.as_ref() != Some(phone) {
+                user.phone_verified_at = None;
+            }
+
+            user.phone = Some(phone.clone());
+        }
+
+        for (field, value) in [
+            (&mut user.name, &update.name),
+            (&mut user.email, &update.email),
+            (&mut user.locale, &update.locale),
+            (&mut user.membership_tier, &update.membership_tier),
+        ] {
+            if let Some(value) = value {
+                *field = value.clone();
+            }
+        }
+
+        match update.marketing_opt_in {
+            Some(true) if !user.marketing_opt_in => user.marketing_opt_in_at = Some(now),
+            Some(false) => user.marketing_opt_in_at = None,
+            _ => {}
+        }
+
+        user.marketing_opt_in = update.marketing_opt_in.unwrap_or(user.marketing_opt_in);
+
+        match update.phone_verified {
+            Some(true) => user.phone_verified_at = user.phone_verified_at.or(Some(now)),
+            Some(false) => user.phone_verified_at = None,
+            None => {}
+        }
+
+        Ok(Some(user.clone()))
+    }
+
+    async fn delete(&self, id: i32, anonymize: bool) -> Result<bool, AppError> {
+        let mut users = self.users.lock().unwrap();
+
+        // An already deleted user can still be anonymized
+        let Some(user) = users.iter_mut().find(|u| {
+            u.id == id && (u.deleted_at.is_none() || (anonymize && u.anonymized_at.is_none()))
+        }) else {
+            return Ok(false);
+        };
+
+        let now = chrono::Utc::now();
+
+        user.deleted_at = user.deleted_at.or(Some(now));
+
+        if anonymize {
+            *user = User {
+                id: user.id,
+                created_at: user.created_at,
+                deleted_at: user.deleted_at,
+                anonymized_at: Some(now),
+                phone: None,
+                ..fake_user(user.id)
+            };
+        }
+
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl CampaignRepository for FakeRepository {
+    async fn exists(&self, id: i32) -> Result<bool, AppError> {
+        Ok(self.campaigns.lock().unwrap().contains_key(&id))
+    }
+
+    async fn create(
+        &self,
+        coupon_types: &[NewCouponType],
+        eligibility: Option<&EligibilityRules>,
+        prize_rules: Option<&PrizeRules>,
+    ) -> Result<Campaign, AppError> {
+        let id = {
+            let mut campaigns = self.campaigns.lock().unwrap();
+            let id = campaigns.keys().max().unwrap_or(&0) + 1;
+
+            campaigns.insert(id, eligibility.cloned());
+
+            id
+        };
+
+        let first_coupon_type_id = {
+            let mut existing = self.coupon_types.lock().unwrap();
+            let first_id = existing.iter().map(|(_, t)| t.id).max().unwrap_or(0) + 1;
+
+            existing.extend(
+                coupon_types
+                    .iter()
+                    .zip(first_id..)
+                    .map(|(t, coupon_type_id)| {
+                        (
+                            id,
+                            CampaignCouponType {
+                                id: coupon_type_id,
+                                description: t.description.clone(),
+                                probability: t.probability,
+                                total_quota: t.total_quota,
+                                daily_quota: t.daily_quota,
+                                current_quota: t.total_quota,
+                                current_daily_quota: t.daily_quota,
+                                last_drawn_date: None,
+                                fallback: t.fallback,
+                                first_draw: t.first_draw,
+                            },
+                        )
+                    }),
+            );
+
+            first_id
+        };
+
+        if let Some(prize_rules) = prize_rules {
+            let flagged = |flag: fn(&NewCouponType) -> bool| {
+                (first_coupon_type_id..)
+                    .zip(coupon_types)
+                    .find_map(|(id, t)| flag(t).then_some(id))
+            };
+
+            self.set_prize_rules(
+                id,
+                PrizeRules {
+                    fallback_coupon_type_id: flagged(|t| t.fallback),
+                    first_draw_coupon_type_id: flagged(|t| t.first_draw),
+                    ..prize_rules.clone()
+                },
+            );
+        }
+
+        Ok(Campaign { id })
+    }
+
+    async fn eligibility_rules(
+        &self,
+        campaign_id: i32,
+    ) -> Result<Option<EligibilityRules>, AppError> {
+        Ok(self
+            .campaigns
+            .lock()
+            .unwrap()
+            .get(&campaign_id)
+            .cloned()
+            .flatten())
+    }
+
+    async fn prize_rules(&self, campaign_id: i32) -> Result<Option<PrizeRules>, AppError> {
+        Ok(self.prize_rules.lock().unwrap().get(&campaign_id).cloned())
+    }
+}
+
+#[async_trait]
+impl CouponTypeRepository for FakeRepository {
+    async fn list_by_campaign(
+        &self,
+        campaign_id: i32,
+    ) -> Result<Vec<CampaignCouponType>, AppError> {
+        Ok(self
+            .coupon_types
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(c, _)| *c == campaign_id)
+            .map(|(_, t)| t.clone())
+            .collect())
+    }
+}
+
+#[async_trait]
+impl DrawRepository for FakeRepository {
+    async fn has_drawn(
+        &self,
+        user_id: i32,
+        campaign_id: i32,
+        date: chrono::NaiveDate,
+    ) -> Result<bool, AppError> {
+        Ok(self
+            .draws
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|&(u, c, d, _)| (u, c, d) == (user_id, campaign_id, date)))
+    }
+
+    async fn record(
+        &self,
+        user_id: i32,
+        campaign_id: i32,
+        coupon_type_ids: &[i32],
+        date: chrono::NaiveDate,
+    ) -> Result<Option<CampaignCoupon>, AppError> {
+        if self.has_drawn(user_id, campaign_id, date).await? {
+            return Err(AppError::new(
+                crate::error::ErrorCode::AlreadyDrawn,
+                "User has already drawn from this campaign. Come again tommorow",
+            ));
+        }
+
+        let mut draws = self.draws.lock().unwrap();
+        let mut coupon_types = self.coupon_types.lock().unwrap();
+
+        let coupon = coupon_type_ids.iter().find_map(|&coupon_type_id| {
+            let (_, coupon_type) = coupon_types
+                .iter_mut()
+                .find(|(_, t)| t.id == coupon_type_id)?;
+
+            if !take_quota(coupon_type, date) {
+                return None;
+            }
+
+            Some(CampaignCoupon {
+                id: draws.len() as i32 + 1,
+                redeem_code: format!("FAKE-{}", draws.len() + 1),
+                campaign_coupon_type_id: coupon_type_id,
+                redeemed: false,
+            })
+        });
+
+        draws.push((user_id, campaign_id, date, coupon.clone()));
+
+        Ok(coupon)
+    }
+
+    async fn history(&self, user_id: i32, campaign_id: i32) -> Result<DrawHistory, AppError> {
+        let draws = self.draws.lock().unwrap();
+        let coupons: Vec<_> = draws
+            .iter()
+            .filter(|&&(u, c, _, _)| (u, c) == (user_id, campaign_id))
+            .map(|(_, _, _, coupon)| coupon.is_some())
+            .collect();
+
+        Ok(DrawHistory {
+            draws: coupons.len() as i64,
+            losing_streak: coupons.iter().rev().take_while(|&&won| !won).count() as i64,
+        })
+    }
+
+    async fn list_by_user(&self, user_id: i32) -> Result<Vec<UserDataExportDraw>, AppError> {
+        let coupon_types = self.coupon_types.lock().unwrap();
+
+        let mut draws: Vec<_> = self
+            .draws
+            .lock()
+            .unwrap()
+            .iter()
+            .zip(1..)
+            .filter(|((u, _, _, _), _)| *u == user_id)
+            .map(|((_, campaign_id, date, coupon), id)| UserDataExportDraw {
+                id,
+                campaign_id: *campaign_id,
+                date: *date,
+                coupon_id: coupon.as_ref().map(|c| c.id),
+                coupon_description: coupon.as_ref().and_then(|c| {
+                    coupon_types
+                        .iter()
+                        .find(|(_, t)| t.id == c.campaign_coupon_type_id)
+                        .map(|(_, t)| t.description.clone())
+                }),
+                redeem_code: coupon.as_ref().map(|c| c.redeem_code.clone()),
+                redeemed: coupon.as_ref().map(|c| c.redeemed),
+            })
+            .collect();
+
+        draws.sort_by_key(|d| (d.date, d.id));
+
+        Ok(draws)
+    }
+}
@@ -0,0 +1,230 @@
+//! Data access behind traits, so that the service logic can be tested against fakes instead of a
+//! live Postgres. [`PgRepository`] implements every trait
+
+use async_trait::async_trait;
+
+use crate::eligibility::EligibilityRules;
+use crate::error::AppError;
+use crate::prize_rules::PrizeRules;
+use crate::types::{
+    ApiKey, Campaign, CampaignCoupon, CampaignCouponType, User, UserDataExportDraw,
+};
+
+#[cfg(test)]
+pub mod fake;
+mod pg;
+mod test;
+
+pub use pg::PgRepository;
+
+/// Filters of [`UserRepository::list`] and [`UserRepository::count`]. Unset filters match every
+/// user
+#[derive(Clone, Debug, Default)]
+pub struct UserFilter {
+    /// Spaces, dashes and brackets are ignored
+    pub phone: Option<String>,
+    pub created_from: Option<chrono::NaiveDate>,
+    pub created_to: Option<chrono::NaiveDate>,
+    pub verified: Option<bool>,
+    pub drawn_in_campaign: Option<i32>,
+}
+
+/// Changes of [`UserRepository::update`]. `None` leaves a field unchanged, and `Some(None)`
+/// clears an optional one
+#[derive(Clone, Debug, Default)]
+pub struct UserUpdate {
+    pub phone: Option<String>,
+    pub name: Option<Option<String>>,
+    pub email: Option<Option<String>>,
+    pub locale: Option<Option<String>>,
+    pub membership_tier: Option<Option<String>>,
+    pub marketing_opt_in: Option<bool>,
+    pub phone_verified: Option<bool>,
+}
+
+/// The response stored under an idempotency key
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+/// What [`IdempotencyRepository::claim`] found under the key
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IdempotencyClaim {
+    /// The key was free, or had expired, and is now held by the caller
+    Claimed,
+    /// A request with the key is still in progress
+    InProgress { fingerprint: String },
+    Completed {
+        fingerprint: String,
+        response: StoredResponse,
+    },
+}
+
+/// What [`DrawRepository::history`] found of a user's draws from a campaign
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DrawHistory {
+    pub draws: i64,
+    /// Draws without a coupon since the user last won one, or since their first draw
+    pub losing_streak: i64,
+}
+
+#[derive(Clone, Debug)]
+pub struct NewCouponType {
+    pub description: String,
+    pub probability: f32,
+    pub total_quota: Option<i32>,
+    pub daily_quota: Option<i32>,
+    pub fallback: bool,
+    pub first_draw: bool,
+}
+
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    /// Users that haven't been deleted, newest first, starting after the `cursor` user
+    async fn list(
+        &self,
+        filter: &UserFilter,
+        cursor: Option<i32>,
+        limit: i64,
+    ) -> Result<Vec<User>, AppError>;
+
+    async fn count(&self, filter: &UserFilter) -> Result<i64, AppError>;
+
+    /// Fails with `phone_taken` if another active user has the phone number
+    async fn create(&self, phone: &str) -> Result<User, AppError>;
+
+    /// Deleted users are included
+    async fn find(&self, id: i32) -> Result<Option<User>, AppError>;
+
+    async fn find_active(&self, id: i32) -> Result<Option<User>, AppError>;
+
+    /// Fails with `phone_taken` if another active user has the new phone number. `None` if the
+    /// user doesn't exist or has been deleted
+    async fn update(&self, id: i32, update: &UserUpdate) -> Result<Option<User>, AppError>;
+
+    /// Soft-deletes the user, and also scrubs their personal details if `anonymize`. `false` if
+    /// there was nothing to do
+    async fn delete(&self, id: i32, anonymize: bool) -> Result<bool, AppError>;
+}
+
+#[async_trait]
+pub trait CampaignRepository: Send + Sync {
+    async fn exists(&self, id: i32) -> Result<bool, AppError>;
+
+    /// Creates the campaign along with its coupon types, eligibility rules and prize rules
+    async fn create(
+        &self,
+        coupon_types: &[NewCouponType],
+        eligibility: Option<&EligibilityRules>,
+        prize_rules: Option<&PrizeRules>,
+    ) -> Result<Campaign, AppError>;
+
+    /// `None` if everyone can draw from the campaign
+    async fn eligibility_rules(
+        &self,
+        campaign_id: i32,
+    ) -> Result<Option<EligibilityRules>, AppError>;
+
+    /// `None` if the campaign has the default prize rules
+    async fn prize_rules(&self, campaign_id: i32) -> Result<Option<PrizeRules>, AppError>;
+}
+
+#[async_trait]
+pub trait CouponTypeRepository: Send + Sync {
+    async fn list_by_campaign(&self, campaign_id: i32)
+        -> Result<Vec<CampaignCouponType>, AppError>;
+}
+
+#[async_trait]
+pub trait CouponRepository: Send + Sync {
+    /// `None` if the coupon doesn't exist, has already been redeemed, or doesn't belong to one of
+    /// the API key's campaigns
+    async fn redeem(
+        &self,
+        coupon_id: i32,
+        api_key_id: i32,
+    ) -> Result<Option<CampaignCoupon>, AppError>;
+}
+
+#[derive(Clone, Debug)]
+pub struct NewApiKey {
+    pub merchant: String,
+    pub key_prefix: String,
+    pub key_hash: String,
+    /// Sorted and deduplicated
+    pub campaign_ids: Vec<i32>,
+}
+
+#[async_trait]
+pub trait ApiKeyRepository: Send + Sync {
+    /// The ID of the unrevoked key with the hash, whose usage counter is bumped
+    async fn authorize(&self, key_hash: &str) -> Result<Option<i32>, AppError>;
+
+    /// Every key, revoked ones included, or only those of the merchant
+    async fn list(&self, merchant: Option<&str>) -> Result<Vec<ApiKey>, AppError>;
+
+    /// Fails with `campaign_not_found` if one of the campaigns doesn't exist
+    async fn create(&self, new_key: &NewApiKey) -> Result<ApiKey, AppError>;
+
+    /// `false` if the key doesn't exist or has already been revoked
+    async fn revoke(&self, id: i32) -> Result<bool, AppError>;
+}
+
+#[async_trait]
+pub trait IdempotencyRepository: Send + Sync {
+    /// Claims the key within `scope` for a request with the fingerprint until `ttl` has passed,
+    /// unless it is held by another request. A claim of a request that never completed, e.g.
+    /// because the node was killed, is released after `abandon_after`
+    async fn claim(
+        &self,
+        scope: &str,
+        key: &str,
+        fingerprint: &str,
+        ttl: std::time::Duration,
+        abandon_after: std::time::Duration,
+    ) -> Result<IdempotencyClaim, AppError>;
+
+    async fn complete(
+        &self,
+        scope: &str,
+        key: &str,
+        response: &StoredResponse,
+    ) -> Result<(), AppError>;
+
+    /// Frees the key, so that the request can be retried
+    async fn release(&self, scope: &str, key: &str) -> Result<(), AppError>;
+
+    /// Deletes the expired keys and returns how many there were
+    async fn delete_expired(&self) -> Result<u64, AppError>;
+}
+
+#[async_trait]
+pub trait DrawRepository: Send + Sync {
+    async fn has_drawn(
+        &self,
+        user_id: i32,
+        campaign_id: i32,
+        date: chrono::NaiveDate,
+    ) -> Result<bool, AppError>;
+
+    /// Records the user's draw on `date`, today's (UTC) date. A coupon is issued of the first of
+    /// `coupon_type_ids` whose quota hasn't run out, and its quota is deducted, resetting the
+    /// daily quota on the first draw of `date`. If they all have run out, or none was drawn, the
+    /// draw yields no coupon. Fails with `already_drawn` if the user has already drawn from the
+    /// campaign on `date`
+    async fn record(
+        &self,
+        user_id: i32,
+        campaign_id: i32,
+        coupon_type_ids: &[i32],
+        date: chrono::NaiveDate,
+    ) -> Result<Option<CampaignCoupon>, AppError>;
+
+    async fn history(&self, user_id: i32, campaign_id: i32) -> Result<DrawHistory, AppError>;
+
+    /// Every draw of the user along with the coupon they got, oldest first
+    async fn list_by_user(&self, user_id: i32) -> Result<Vec<UserDataExportDraw>, AppError>;
+}
@@ -0,0 +1,948 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{
+    ApiKeyRepository, CampaignRepository, CouponRepository, CouponTypeRepository, DrawHistory,
+    DrawRepository, IdempotencyClaim, IdempotencyRepository, NewApiKey, NewCouponType,
+    StoredResponse, UserFilter, UserRepository, UserUpdate,
+};
+use crate::eligibility::{normalize_phone, EligibilityRules};
+use crate::error::{AppError, ErrorCode};
+use crate::metrics::Metrics;
+use crate::prize_rules::PrizeRules;
+use crate::types::{
+    ApiKey, Campaign, CampaignCoupon, CampaignCouponType, User, UserDataExportDraw,
+};
+
+/// Cloning is cheap, the pool and the metrics are reference counted. Every query's latency is
+/// observed by [`Metrics::db_query_duration`]
+#[derive(Clone)]
+pub struct PgRepository {
+    db_pool: PgPool,
+    metrics: Arc<Metrics>,
+}
+
+impl PgRepository {
+    pub fn new(db_pool: PgPool, metrics: Arc<Metrics>) -> Self {
+        Self { db_pool, metrics }
+    }
+}
+
+/// Phone numbers only have to be unique among active users, once normalized
+fn phone_taken_on_conflict(e: sqlx::Error, phone: Option<&str>) -> AppError {
+    match e {
+        sqlx::Error::Database(e) if e.is_unique_violation() => AppError::new(
+            ErrorCode::PhoneTaken,
+            format!(
+                "Phone number {} is registered by another user",
+                phone.unwrap_or_default()
+            ),
+        ),
+        e => e.into(),
+    }
+}
+
+/// The unique index on `draws` is what ultimately enforces one draw per user, campaign and day
+fn already_drawn_on_conflict(e: sqlx::Error) -> AppError {
+    match e {
+        sqlx::Error::Database(e) if e.is_unique_violation() => AppError::new(
+            ErrorCode::AlreadyDrawn,
+            "User has already drawn from this campaign. Come again tommorow",
+        ),
+        e => e.into(),
+    }
+}
+
+/// The dates of the filter as the (UTC) start of the first day and end of the last, so that
+/// `created_at` is compared as is and its index can be used
+fn created_between(
+    filter: &UserFilter,
+) -> (
+    Option<chrono::DateTime<chrono::Utc>>,
+    Option<chrono::DateTime<chrono::Utc>>,
+) {
+    let start_of_day = |date: chrono::NaiveDate| date.and_time(chrono::NaiveTime::MIN).and_utc();
+
+    (
+        filter.created_from.map(start_of_day),
+        filter
+            .created_to
+            .and_then(|date| date.succ_opt())
+            .map(start_of_day),
+    )
+}
+
+#[async_trait]
+impl UserRepository for PgRepository {
+    async fn list(
+        &self,
+        filter: &UserFilter,
+        cursor: Option<i32>,
+        limit: i64,
+    ) -> Result<Vec<User>, AppError> {
+        let _timer = self.metrics.db_timer("users.list");
+        let (created_from, created_to) = created_between(filter);
+
+        let users = sqlx::query_as!(
+            User,
+            r#"--sql
+                select id, phone, name, email, locale, membership_tier, marketing_opt_in, marketing_opt_in_at,
+                    phone_verified_at, created_at, deleted_at, anonymized_at
+                from users
+                where deleted_at is null
+                and ($1::text is null or phone_normalized = $1)
+                and ($2::timestamptz is null or created_at >= $2)
+                and ($3::timestamptz is null or created_at < $3)
+                and ($4::bool is null or (phone_verified_at is not null) = $4)
+                and ($5::int is null or exists(
+                    select *
+                    from draws
+                    where draws.user_id = users.id and draws.campaign_id = $5
+                ))
+                and ($6::int is null or id < $6)
+                order by id desc
+                limit $7;
+            "#,
+            filter.phone.as_deref().map(normalize_phone),
+            created_from,
+            created_to,
+            filter.verified,
+            filter.drawn_in_campaign,
+            cursor,
+            limit
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        Ok(users)
+    }
+
+    async fn count(&self, filter: &UserFilter) -> Result<i64, AppError> {
+        let _timer = self.metrics.db_timer("users.count");
+        let (created_from, created_to) = created_between(filter);
+
+        let count = sqlx::query_scalar!(
+            r#"--sql
+                select count(*)
+                from users
+                where deleted_at is null
+                and ($1::text is null or phone_normalized = $1)
+                and ($2::timestamptz is null or created_at >= $2)
+                and ($3::timestamptz is null or created_at < $3)
+                and ($4::bool is null or (phone_verified_at is not null) = $4)
+                and ($5::int is null or exists(
+                    select *
+                    from draws
+                    where draws.user_id = users.id and draws.campaign_id = $5
+                ));
+            "#,
+            filter.phone.as_deref().map(normalize_phone),
+            created_from,
+            created_to,
+            filter.verified,
+            filter.drawn_in_campaign
+        )
+        .fetch_one(&self.db_pool)
+        .await?
+        .unwrap_or(0);
+
+        Ok(count)
+    }
+
+    async fn create(&self, phone: &str) -> Result<User, AppError> {
+        let _timer = self.metrics.db_timer("users.create");
+
+        sqlx::query_as!(
+            User,
+            "--sql
+                insert into users (phone, phone_normalized)
+                values ($1, $2)
+                returning id, phone, name, email, locale, membership_tier, marketing_opt_in, marketing_opt_in_at,
+                    phone_verified_at, created_at, deleted_at, anonymized_at;
+            ",
+            phone,
+            normalize_phone(phone)
+        )
+        .fetch_one(&self.db_pool)
+        .await
+        .map_err(|e| phone_taken_on_conflict(e, Some(phone)))
+    }
+
+    async fn find(&self, id: i32) -> Result<Option<User>, AppError> {
+        let _timer = self.metrics.db_timer("users.find");
+
+        let user = sqlx::query_as!(
+            User,
+            "--sql
+                select id, phone, name, email, locale, membership_tier, marketing_opt_in, marketing_opt_in_at,
+                    phone_verified_at, created_at, deleted_at, anonymized_at
+                from users
+                where id = $1;
+            ",
+            id
+        )
+        .fetch_optional(&self.db_pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    async fn find_active(&self, id: i32) -> Result<Option<User>, AppError> {
+        let _timer = self.metrics.db_timer("users.find_active");
+
+        let user = sqlx::query_as!(
+            User,
+            "--sql
+                select id, phone, name, email, locale, membership_tier, marketing_opt_in, marketing_opt_in_at,
+                    phone_verified_at, created_at, deleted_at, anonymized_at
+                from users
+                where id = $1 and deleted_at is null;
+            ",
+            id
+        )
+        .fetch_optional(&self.db_pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    async fn update(&self, id: i32, update: &UserUpdate) -> Result<Option<User>, AppError> {
+        let _timer = self.metrics.db_timer("users.update");
+
+        // The opt-in and verification timestamps are only touched when they actually change, and
+        // changing the phone number invalidates its verification
+
+        sqlx::query_as!(
+            User,
+            "--sql
+                update users
+                set phone = coalesce($2, phone),
+                phone_normalized = coalesce($13, phone_normalized),
+                name = case when $3 then $4 else name end,
+                email = case when $5 then $6 else email end,
+                locale = case when $7 then $8 else locale end,
+                membership_tier = case when $11 then $12 else membership_tier end,
+                marketing_opt_in_at = case
+                    when $9::bool is null then marketing_opt_in_at
+                    when $9 and marketing_opt_in then marketing_opt_in_at
+                    when $9 then now()
+                    else null
+                end,
+                marketing_opt_in = coalesce($9, marketing_opt_in),
+                phone_verified_at = case
+                    when $10::bool = false or ($2 is not null and $2 != phone) then
+                        case when $10 then now() else null end
+                    when $10 then coalesce(phone_verified_at, now())
+                    else phone_verified_at
+                end
+                where id = $1 and deleted_at is null
+                returning id, phone, name, email, locale, membership_tier, marketing_opt_in, marketing_opt_in_at,
+                    phone_verified_at, created_at, deleted_at, anonymized_at;
+            ",
+            id,
+            update.phone,
+            update.name.is_some(),
+            update.name.clone().flatten(),
+            update.email.is_some(),
+            update.email.clone().flatten(),
+            update.locale.is_some(),
+            update.locale.clone().flatten(),
+            update.marketing_opt_in,
+            update.phone_verified,
+            update.membership_tier.is_some(),
+            update.membership_tier.clone().flatten(),
+            update.phone.as_deref().map(normalize_phone)
+        )
+        .fetch_optional(&self.db_pool)
+        .await
+        .map_err(|e| phone_taken_on_conflict(e, update.phone.as_deref()))
+    }
+
+    async fn delete(&self, id: i32, anonymize: bool) -> Result<bool, AppError> {
+        let _timer = self.metrics.db_timer("users.delete");
+
+        // Users are only soft-deleted because their draws reference them. An already deleted user
+        // can still be anonymized
+
+        let q_result = sqlx::query!(
+            "--sql
+                update users
+                set deleted_at = coalesce(deleted_at, now()),
+                anonymized_at = case when $2 then now() else anonymized_at end,
+                phone = case when $2 then null else phone end,
+                phone_normalized = case when $2 then null else phone_normalized end,
+                name = case when $2 then null else name end,
+                email = case when $2 then null else email end,
+                locale = case when $2 then null else locale end,
+                membership_tier = case when $2 then null else membership_tier end,
+                marketing_opt_in = case when $2 then false else marketing_opt_in end,
+                marketing_opt_in_at = case when $2 then null else marketing_opt_in_at end,
+                phone_verified_at = case when $2 then null else phone_verified_at end
+                where id = $1 and (deleted_at is null or ($2 and anonymized_at is null));
+            ",
+            id,
+            anonymize
+        )
+        .execute(&self.db_pool)
+        .await?;
+
+        Ok(q_result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+impl CampaignRepository for PgRepository {
+    async fn exists(&self, id: i32) -> Result<bool, AppError> {
+        let _timer = self.metrics.db_timer("campaigns.exists");
+
+        let exists = sqlx::query_scalar!(
+            "--sql
+                select exists(
+                    select *
+                    from campaigns
+                    where id = $1
+                );
+            ",
+            id
+        )
+        .fetch_one(&self.db_pool)
+        .await?
+        .unwrap_or(false);
+
+        Ok(exists)
+    }
+
+    async fn create(
+        &self,
+        coupon_types: &[NewCouponType],
+        eligibility: Option<&EligibilityRules>,
+        prize_rules: Option<&PrizeRules>,
+    ) -> Result<Campaign, AppError> {
+        let _timer = self.metrics.db_timer("campaigns.create");
+
+        let mut tx = self.db_pool.begin().await?;
+
+        let new_compaign = sqlx::query_as!(
+            Campaign,
+            "--sql
+                insert into campaigns (id)
+                values (default)
+                returning *;
+            "
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let campaign_id = new_compaign.id;
+
+        let campaign_ids: Vec<_> = vec![campaign_id; coupon_types.len()];
+        let descriptions: Vec<_> = coupon_types.iter().map(|t| t.description.clone()).collect();
+        let probabilities: Vec<_> = coupon_types.iter().map(|t| t.probability).collect();
+        let total_quotas: Vec<Option<i32>> = coupon_types.iter().map(|t| t.total_quota).collect();
+        let daily_quotas: Vec<Option<i32>> = coupon_types.iter().map(|t| t.daily_quota).collect();
+        let fallbacks: Vec<_> = coupon_types.iter().map(|t| t.fallback).collect();
+        let first_draws: Vec<_> = coupon_types.iter().map(|t| t.first_draw).collect();
+
+        // https://github.com/launchbadge/sqlx/blob/main/FAQ.md#how-can-i-bind-an-array-to-a-values-clause-how-can-i-do-bulk-inserts
+        // https://github.com/launchbadge/sqlx/issues/1893
+        #[allow(deprecated)]
+        sqlx::query!(
+            "--sql
+                insert into campaign_coupon_types (campaign_id, description, probability, total_quota, daily_quota, current_quota, current_daily_quota, fallback, first_draw)
+                select * from unnest($1::int[], $2::text[], $3::float4[], $4::int[], $5::int[], $4::int[], $5::int[], $6::bool[], $7::bool[]);
+            ",
+            &campaign_ids[..],
+            &descriptions[..],
+            &probabilities[..],
+            &total_quotas[..]: Vec<Option<i32>>,
+            &daily_quotas[..]: Vec<Option<i32>>,
+            &fallbacks[..],
+            &first_draws[..]
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if let Some(eligibility) = eligibility {
+            sqlx::query!(
+                "--sql
+                    insert into campaign_eligibility_rules (campaign_id, min_account_age_days, max_account_age_days,
+                        phone_prefixes, membership_tiers, require_verified_phone, allowed_user_ids, denied_user_ids)
+                    values ($1, $2, $3, $4, $5, $6, $7, $8);
+                ",
+                campaign_id,
+                eligibility.min_account_age_days,
+                eligibility.max_account_age_days,
+                eligibility.phone_prefixes.as_deref(),
+                eligibility.membership_tiers.as_deref(),
+                eligibility.require_verified_phone,
+                eligibility.allowed_user_ids.as_deref(),
+                eligibility.denied_user_ids.as_deref()
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        if let Some(prize_rules) = prize_rules {
+            sqlx::query!(
+                "--sql
+                    insert into campaign_prize_rules (campaign_id, when_exhausted, guaranteed_win_after, pacing)
+                    values ($1, $2, $3, $4);
+                ",
+                campaign_id,
+                prize_rules.when_exhausted.as_str(),
+                prize_rules.guaranteed_win_after,
+                prize_rules.pacing
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(new_compaign)
+    }
+
+    async fn eligibility_rules(
+        &self,
+        campaign_id: i32,
+    ) -> Result<Option<EligibilityRules>, AppError> {
+        let _timer = self.metrics.db_timer("campaigns.eligibility_rules");
+
+        let eligibility = sqlx::query_as!(
+            EligibilityRules,
+            "--sql
+                select min_account_age_days, max_account_age_days, phone_prefixes, membership_tiers,
+                    require_verified_phone, allowed_user_ids, denied_user_ids
+                from campaign_eligibility_rules
+                where campaign_id = $1;
+            ",
+            campaign_id
+        )
+        .fetch_optional(&self.db_pool)
+        .await?;
+
+        Ok(eligibility)
+    }
+
+    async fn prize_rules(&self, campaign_id: i32) -> Result<Option<PrizeRules>, AppError> {
+        let _timer = self.metrics.db_timer("campaigns.prize_rules");
+
+        let row = sqlx::query!(
+            "--sql
+                select r.when_exhausted, r.guaranteed_win_after, r.pacing, (
+                    select t.id
+                    from campaign_coupon_types t
+                    where t.campaign_id = r.campaign_id and t.fallback
+                ) as fallback_coupon_type_id, (
+                    select t.id
+                    from campaign_coupon_types t
+                    where t.campaign_id = r.campaign_id and t.first_draw
+                ) as first_draw_coupon_type_id
+                from campaign_prize_rules r
+                where r.campaign_id = $1;
+            ",
+            campaign_id
+        )
+        .fetch_optional(&self.db_pool)
+        .await?;
+
+        row.map(|row| {
+            Ok(PrizeRules {
+                when_exhausted: row.when_exhausted.parse().map_err(AppError::Internal)?,
+                guaranteed_win_after: row.guaranteed_win_after,
+                pacing: row.pacing,
+                fallback_coupon_type_id: row.fallback_coupon_type_id,
+                first_draw_coupon_type_id: row.first_draw_coupon_type_id,
+            })
+        })
+        .transpose()
+    }
+}
+
+#[async_trait]
+impl CouponTypeRepository for PgRepository {
+    async fn list_by_campaign(
+        &self,
+        campaign_id: i32,
+    ) -> Result<Vec<CampaignCouponType>, AppError> {
+        let _timer = self.metrics.db_timer("coupon_types.list_by_campaign");
+
+        let coupon_types = sqlx::query_as!(
+            CampaignCouponType,
+            "--sql
+                select id, description, probability, total_quota, daily_quota, current_quota,
+                    current_daily_quota, last_drawn_date, fallback, first_draw
+                from campaign_coupon_types
+                where campaign_id = $1
+                order by id;
+            ",
+            campaign_id
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        Ok(coupon_types)
+    }
+}
+
+#[async_trait]
+impl CouponRepository for PgRepository {
+    async fn redeem(
+        &self,
+        coupon_id: i32,
+        api_key_id: i32,
+    ) -> Result<Option<CampaignCoupon>, AppError> {
+        let _timer = self.metrics.db_timer("coupons.redeem");
+
+        let coupon = sqlx::query_as!(
+            CampaignCoupon,
+            "--sql
+                update campaign_coupons
+                set redeemed = true
+                where id = $1 and redeemed = false and campaign_coupon_type_id in (
+                    select t.id
+                    from campaign_coupon_types t
+                    join api_key_campaigns k on k.campaign_id = t.campaign_id
+                    where k.api_key_id = $2
+                )
+                returning *;
+            ",
+            coupon_id,
+            api_key_id
+        )
+        .fetch_optional(&self.db_pool)
+        .await?;
+
+        Ok(coupon)
+    }
+}
+
+#[async_trait]
+impl ApiKeyRepository for PgRepository {
+    async fn authorize(&self, key_hash: &str) -> Result<Option<i32>, AppError> {
+        let _timer = self.metrics.db_timer("api_keys.authorize");
+
+        // Look up the key and bump its usage counter in one go
+
+        let id = sqlx::query_scalar!(
+            "--sql
+                update api_keys
+                set usage_count = usage_count + 1,
+                last_used_at = now()
+                where key_hash = $1 and revoked_at is null
+                returning id;
+            ",
+            key_hash
+        )
+        .fetch_optional(&self.db_pool)
+        .await?;
+
+        Ok(id)
+    }
+
+    async fn list(&self, merchant: Option<&str>) -> Result<Vec<ApiKey>, AppError> {
+        let _timer = self.metrics.db_timer("api_keys.list");
+
+        let api_keys = sqlx::query_as!(
+            ApiKey,
+            r#"--sql
+                select k.id, k.merchant, k.key_prefix, k.usage_count, k.last_used_at, k.created_at, k.revoked_at,
+                    array_agg(c.campaign_id order by c.campaign_id) as "campaign_ids!"
+                from api_keys k
+                join api_key_campaigns c on c.api_key_id = k.id
+                where $1::text is null or k.merchant = $1
+                group by k.id
+                order by k.id;
+            "#,
+            merchant
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        Ok(api_keys)
+    }
+
+    async fn create(&self, new_key: &NewApiKey) -> Result<ApiKey, AppError> {
+        let _timer = self.metrics.db_timer("api_keys.create");
+
+        let mut tx = self.db_pool.begin().await?;
+
+        let existing_campaigns = sqlx::query_scalar!(
+            "--sql
+                select count(*)
+                from campaigns
+                where id = any($1);
+            ",
+            &new_key.campaign_ids[..]
+        )
+        .fetch_one(&mut *tx)
+        .await?
+        .unwrap_or(0);
+
+        if existing_campaigns != new_key.campaign_ids.len() as i64 {
+            tx.rollback().await?;
+
+            return Err(AppError::new(
+                ErrorCode::CampaignNotFound,
+                format!(
+                    "One or more of the campaigns {:?} don't exist",
+                    new_key.campaign_ids
+                ),
+            ));
+        }
+
+        let created = sqlx::query!(
+            "--sql
+                insert into api_keys (merchant, key_prefix, key_hash)
+                values ($1, $2, $3)
+                returning id, created_at;
+            ",
+            new_key.merchant,
+            new_key.key_prefix,
+            new_key.key_hash
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "--sql
+                insert into api_key_campaigns (api_key_id, campaign_id)
+                select $1, * from unnest($2::int[]);
+            ",
+            created.id,
+            &new_key.campaign_ids[..]
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(ApiKey {
+            id: created.id,
+            merchant: new_key.merchant.clone(),
+            key_prefix: new_key.key_prefix.clone(),
+            campaign_ids: new_key.campaign_ids.clone(),
+            usage_count: 0,
+            last_used_at: None,
+            created_at: created.created_at,
+            revoked_at: None,
+        })
+    }
+
+    async fn revoke(&self, id: i32) -> Result<bool, AppError> {
+        let _timer = self.metrics.db_timer("api_keys.revoke");
+
+        let result = sqlx::query!(
+            "--sql
+                update api_keys
+                set revoked_at = now()
+                where id = $1 and revoked_at is null;
+            ",
+            id
+        )
+        .execute(&self.db_pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+impl IdempotencyRepository for PgRepository {
+    async fn claim(
+        &self,
+        scope: &str,
+        key: &str,
+        fingerprint: &str,
+        ttl: std::time::Duration,
+        abandon_after: std::time::Duration,
+    ) -> Result<IdempotencyClaim, AppError> {
+        let _timer = self.metrics.db_timer("idempotency_keys.claim");
+
+        // Takes over the key if it has expired or was abandoned, and otherwise returns what is
+        // stored under it. The update locks the row, so only one of several racing requests wins
+        let existing = sqlx::query!(
+            r#"--sql
+                with claimed as (
+                    insert into idempotency_keys (scope, key, fingerprint, expires_at)
+                    values ($1, $2, $3, now() + make_interval(secs => $4))
+                    on conflict (scope, key) do update
+                    set fingerprint = excluded.fingerprint,
+                    response_status = null,
+                    response_content_type = null,
+                    response_body = null,
+                    created_at = now(),
+                    expires_at = excluded.expires_at
+                    where idempotency_keys.expires_at < now() or (
+                        idempotency_keys.response_status is null
+                        and idempotency_keys.created_at < now() - make_interval(secs => $5)
+                    )
+                    returning key
+                )
+                select
+                    exists (select 1 from claimed) as "claimed!",
+                    k.fingerprint as "fingerprint?",
+                    k.response_status as "response_status?",
+                    k.response_content_type as "response_content_type?",
+                    k.response_body as "response_body?"
+                from (select 1) as one
+                left join idempotency_keys k
+                    on k.scope = $1 and k.key = $2 and not exists (select 1 from claimed);
+            "#,
+            scope,
+            key,
+            fingerprint,
+            ttl.as_secs_f64(),
+            abandon_after.as_secs_f64()
+        )
+        .fetch_one(&self.db_pool)
+        .await?;
+
+        if existing.claimed {
+            return Ok(IdempotencyClaim::Claimed);
+        }
+
+        let Some(fingerprint) = existing.fingerprint else {
+            // Deleted by the cleanup in between, which won't happen to a live key
+            return Err(AppError::Internal(format!(
+                "Idempotency key {key} vanished while being claimed"
+            )));
+        };
+
+        Ok(match existing.response_status {
+            None => IdempotencyClaim::InProgress { fingerprint },
+            Some(status) => IdempotencyClaim::Completed {
+                fingerprint,
+                response: StoredResponse {
+                    status: status as u16,
+                    content_type: existing.response_content_type,
+                    body: existing.response_body.unwrap_or_default(),
+                },
+            },
+        })
+    }
+
+    async fn complete(
+        &self,
+        scope: &str,
+        key: &str,
+        response: &StoredResponse,
+    ) -> Result<(), AppError> {
+        let _timer = self.metrics.db_timer("idempotency_keys.complete");
+
+        sqlx::query!(
+            "--sql
+                update idempotency_keys
+                set response_status = $3,
+                response_content_type = $4,
+                response_body = $5
+                where scope = $1 and key = $2;
+            ",
+            scope,
+            key,
+            response.status as i16,
+            response.content_type,
+            response.body
+        )
+        .execute(&self.db_pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn release(&self, scope: &str, key: &str) -> Result<(), AppError> {
+        let _timer = self.metrics.db_timer("idempotency_keys.release");
+
+        sqlx::query!(
+            "--sql
+                delete from idempotency_keys
+                where scope = $1 and key = $2 and response_status is null;
+            ",
+            scope,
+            key
+        )
+        .execute(&self.db_pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete_expired(&self) -> Result<u64, AppError> {
+        let _timer = self.metrics.db_timer("idempotency_keys.delete_expired");
+
+        let result = sqlx::query!(
+            "--sql
+                delete from idempotency_keys
+                where expires_at < now();
+            "
+        )
+        .execute(&self.db_pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl DrawRepository for PgRepository {
+    async fn has_drawn(
+        &self,
+        user_id: i32,
+        campaign_id: i32,
+        date: chrono::NaiveDate,
+    ) -> Result<bool, AppError> {
+        let _timer = self.metrics.db_timer("draws.has_drawn");
+
+        let drawn = sqlx::query_scalar!(
+            "--sql
+                select exists(
+                    select *
+                    from draws
+                    where user_id = $1 and campaign_id = $2 and date = $3
+                );
+            ",
+            user_id,
+            campaign_id,
+            date
+        )
+        .fetch_one(&self.db_pool)
+        .await?
+        .unwrap_or(false);
+
+        Ok(drawn)
+    }
+
+    async fn record(
+        &self,
+        user_id: i32,
+        campaign_id: i32,
+        coupon_type_ids: &[i32],
+        date: chrono::NaiveDate,
+    ) -> Result<Option<CampaignCoupon>, AppError> {
+        let _timer = self.metrics.db_timer("draws.record");
+
+        let mut tx = self.db_pool.begin().await?;
+
+        let mut coupon = None;
+
+        for &coupon_type_id in coupon_type_ids {
+            // Deduct the coupon type's quotas, unless either has run out. The daily quota is reset
+            // on the first draw of the day, by the date of the caller's clock rather than the DB's
+            // so that it agrees with pacing. The date never moves backwards, so that nodes whose
+            // clocks disagree around midnight can't reset the quota over and over. The conditions
+            // are re-checked on the locked row, so concurrent draws can't take the quotas below 0.
+            // Follows `quota::test::take_quota`
+            let deducted = sqlx::query_scalar!(
+                "--sql
+                    update campaign_coupon_types
+                    set current_quota = current_quota - 1,
+                        current_daily_quota = case
+                            when last_drawn_date >= $2 then current_daily_quota
+                            else daily_quota
+                        end - 1,
+                        last_drawn_date = greatest(last_drawn_date, $2)
+                    where id = $1
+                        and (current_quota is null or current_quota > 0)
+                        and (daily_quota is null or case
+                            when last_drawn_date >= $2 then current_daily_quota
+                            else daily_quota
+                        end > 0)
+                    returning id;
+                ",
+                coupon_type_id,
+                date
+            )
+            .fetch_optional(&mut *tx)
+            .await?;
+
+            if deducted.is_some() {
+                coupon = Some(
+                    sqlx::query_as!(
+                        CampaignCoupon,
+                        "--sql
+                            insert into campaign_coupons (redeem_code, campaign_coupon_type_id)
+                            values ($1, $2)
+                            returning *;
+                        ",
+                        String::from(Uuid::new_v4()),
+                        coupon_type_id
+                    )
+                    .fetch_one(&mut *tx)
+                    .await?,
+                );
+
+                break;
+            }
+        }
+
+        sqlx::query!(
+            "--sql
+                insert into draws (user_id, campaign_id, campaign_coupon_id, date)
+                values ($1, $2, $3, $4);
+            ",
+            user_id,
+            campaign_id,
+            coupon.as_ref().map(|c| c.id),
+            date
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(already_drawn_on_conflict)?;
+
+        tx.commit().await?;
+
+        Ok(coupon)
+    }
+
+    async fn history(&self, user_id: i32, campaign_id: i32) -> Result<DrawHistory, AppError> {
+        let _timer = self.metrics.db_timer("draws.history");
+
+        let history = sqlx::query_as!(
+            DrawHistory,
+            r#"--sql
+                select count(*) as "draws!",
+                    count(*) filter (where id > coalesce((
+                        select max(id)
+                        from draws
+                        where user_id = $1 and campaign_id = $2 and campaign_coupon_id is not null
+                    ), 0)) as "losing_streak!"
+                from draws
+                where user_id = $1 and campaign_id = $2;
+            "#,
+            user_id,
+            campaign_id
+        )
+        .fetch_one(&self.db_pool)
+        .await?;
+
+        Ok(history)
+    }
+
+    async fn list_by_user(&self, user_id: i32) -> Result<Vec<UserDataExportDraw>, AppError> {
+        let _timer = self.metrics.db_timer("draws.list_by_user");
+
+        let draws = sqlx::query_as!(
+            UserDataExportDraw,
+            r#"--sql
+                select d.id, d.campaign_id, d.date,
+                    c.id as "coupon_id?",
+                    t.description as "coupon_description?",
+                    c.redeem_code as "redeem_code?",
+                    c.redeemed as "redeemed?"
+                from draws d
+                left join campaign_coupons c on c.id = d.campaign_coupon_id
+                left join campaign_coupon_types t on t.id = c.campaign_coupon_type_id
+                where d.user_id = $1
+                order by d.date, d.id;
+            "#,
+            user_id
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        Ok(draws)
+    }
+}
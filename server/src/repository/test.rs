@@ -0,0 +1,232 @@
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::{AppError, ErrorCode},
+        prize_rules::{PrizeRules, WhenExhausted},
+        repository::{
+            fake::FakeRepository, CampaignRepository, CouponTypeRepository, DrawRepository,
+            NewCouponType, PgRepository, UserFilter, UserRepository, UserUpdate,
+        },
+        testing::TestContext,
+    };
+
+    use chrono::Utc;
+
+    /// The code of the request error, if it is one
+    fn code<T>(result: Result<T, AppError>) -> Option<ErrorCode> {
+        match result {
+            Err(AppError::Request { code, .. }) => Some(code),
+            _ => None,
+        }
+    }
+
+    /// The same steps against either repository, so that the fake can't drift from Postgres
+    async fn users_and_campaigns<R>(repository: &R)
+    where
+        R: UserRepository + CampaignRepository + CouponTypeRepository + DrawRepository,
+    {
+        let user = UserRepository::create(repository, "+852 1234 5678")
+            .await
+            .unwrap();
+        let other = UserRepository::create(repository, "+852 8765 4321")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            code(UserRepository::create(repository, "+852 1234 5678").await),
+            Some(ErrorCode::PhoneTaken)
+        );
+
+        // Another format of the same number is the same phone
+        assert_eq!(
+            code(UserRepository::create(repository, "+85212345678").await),
+            Some(ErrorCode::PhoneTaken)
+        );
+
+        let filter = UserFilter {
+            phone: Some("+852-1234-5678".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(repository.count(&filter).await.unwrap(), 1);
+        assert_eq!(
+            repository.list(&filter, None, 10).await.unwrap()[0].id,
+            user.id
+        );
+
+        let ids: Vec<_> = repository
+            .list(&UserFilter::default(), None, 10)
+            .await
+            .unwrap()
+            .iter()
+            .map(|u| u.id)
+            .collect();
+
+        assert_eq!(ids, [other.id, user.id]);
+        assert!(repository
+            .list(&UserFilter::default(), Some(user.id), 10)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let verified = repository
+            .update(
+                user.id,
+                &UserUpdate {
+                    name: Some(Some("Chan Tai Man".to_string())),
+                    marketing_opt_in: Some(true),
+                    phone_verified: Some(true),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(verified.name.as_deref(), Some("Chan Tai Man"));
+        assert!(verified.marketing_opt_in && verified.marketing_opt_in_at.is_some());
+        assert!(verified.phone_verified_at.is_some());
+
+        // A new phone number isn't verified
+        let moved = repository
+            .update(
+                user.id,
+                &UserUpdate {
+                    phone: Some("+852 1111 2222".to_string()),
+                    name: Some(None),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(moved.name, None);
+        assert_eq!(moved.phone_verified_at, None);
+        assert_eq!(moved.marketing_opt_in_at, verified.marketing_opt_in_at);
+
+        assert_eq!(
+            code(
+                repository
+                    .update(
+                        other.id,
+                        &UserUpdate {
+                            phone: Some("+852-1111-2222".to_string()),
+                            ..Default::default()
+                        }
+                    )
+                    .await
+            ),
+            Some(ErrorCode::PhoneTaken)
+        );
+
+        let campaign = CampaignRepository::create(
+            repository,
+            &[
+                NewCouponType {
+                    description: "Grand prize".to_string(),
+                    probability: 1.0,
+                    total_quota: Some(1),
+                    daily_quota: None,
+                    fallback: false,
+                    first_draw: false,
+                },
+                NewCouponType {
+                    description: "Consolation".to_string(),
+                    probability: 0.0,
+                    total_quota: None,
+                    daily_quota: None,
+                    fallback: true,
+                    first_draw: false,
+                },
+            ],
+            None,
+            Some(&PrizeRules {
+                when_exhausted: WhenExhausted::Fallback,
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+
+        let coupon_types = repository.list_by_campaign(campaign.id).await.unwrap();
+        let prize_rules = repository.prize_rules(campaign.id).await.unwrap().unwrap();
+
+        assert_eq!(coupon_types.len(), 2);
+        assert_eq!(prize_rules.when_exhausted, WhenExhausted::Fallback);
+        assert_eq!(
+            prize_rules.fallback_coupon_type_id,
+            Some(coupon_types[1].id)
+        );
+        assert_eq!(prize_rules.first_draw_coupon_type_id, None);
+
+        let coupon = repository
+            .record(
+                user.id,
+                campaign.id,
+                &[coupon_types[0].id],
+                Utc::now().date_naive(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+
+        let draws = repository.list_by_user(user.id).await.unwrap();
+
+        assert_eq!(draws.len(), 1);
+        assert_eq!(draws[0].campaign_id, campaign.id);
+        assert_eq!(draws[0].coupon_id, Some(coupon.id));
+        assert_eq!(draws[0].coupon_description.as_deref(), Some("Grand prize"));
+        assert_eq!(draws[0].redeemed, Some(false));
+
+        let drawn = UserFilter {
+            drawn_in_campaign: Some(campaign.id),
+            ..Default::default()
+        };
+
+        assert_eq!(repository.count(&drawn).await.unwrap(), 1);
+
+        // Deleted users can still be anonymized, once
+        assert!(repository.delete(user.id, false).await.unwrap());
+        assert!(!repository.delete(user.id, false).await.unwrap());
+        assert!(repository.delete(user.id, true).await.unwrap());
+        assert!(!repository.delete(user.id, true).await.unwrap());
+
+        let anonymized = repository.find(user.id).await.unwrap().unwrap();
+
+        assert_eq!(anonymized.phone, None);
+        assert!(!anonymized.marketing_opt_in);
+        assert!(anonymized.anonymized_at.is_some());
+        assert_eq!(
+            repository.find_active(user.id).await.unwrap().map(|u| u.id),
+            None
+        );
+        assert_eq!(repository.count(&drawn).await.unwrap(), 0);
+        assert_eq!(
+            repository
+                .update(user.id, &UserUpdate::default())
+                .await
+                .unwrap()
+                .map(|u| u.id),
+            None
+        );
+
+        // Its phone number is free again
+        UserRepository::create(repository, "+852 1234 5678")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn fake_repository_behaves_like_postgres() {
+        let ctx = TestContext::new().await;
+
+        users_and_campaigns(&PgRepository::new(
+            ctx.store.db_pool.clone(),
+            ctx.store.metrics.clone(),
+        ))
+        .await;
+
+        users_and_campaigns(&FakeRepository::default()).await;
+    }
+}
@@ -0,0 +1,78 @@
+use axum::{extract::State, Json};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::store::Store;
+
+mod test;
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub(super) struct DbPoolStats {
+    pub max_size: u32,
+    /// Number of open connections, idle or in use
+    pub size: u32,
+    pub idle: usize,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub(super) struct RedisPoolStats {
+    pub max_size: usize,
+    /// Number of open connections, available or in use
+    pub size: usize,
+    pub available: usize,
+    /// Number of handlers waiting for a connection
+    pub waiting: usize,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub(super) struct CacheStats {
+    /// Whether the last cache operation failed, i.e. draws are currently checked against the DB
+    /// only
+    pub degraded: bool,
+    /// Number of cache operations that failed
+    pub errors: u64,
+    /// Number of cache operations skipped because the cache failed shortly before
+    pub bypassed: u64,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub(super) struct PoolStats {
+    pub db: DbPoolStats,
+    /// Absent unless the cache is backed by Redis
+    pub redis: Option<RedisPoolStats>,
+    pub cache: CacheStats,
+}
+
+#[utoipa::path(
+    get,
+    path = "/stats/pools",
+    responses(
+        (status = 200, description = "Get DB and Redis connection pool and cache health stats successfully", body = PoolStats)
+    )
+)]
+pub(super) async fn get_pool_stats(State(store): State<Store>) -> Json<PoolStats> {
+    let cache_health = store.cache.health();
+
+    Json(PoolStats {
+        db: DbPoolStats {
+            max_size: store.db_pool.options().get_max_connections(),
+            size: store.db_pool.size(),
+            idle: store.db_pool.num_idle(),
+        },
+        redis: store.redis_pool.as_ref().map(|redis_pool| {
+            let redis_status = redis_pool.status();
+
+            RedisPoolStats {
+                max_size: redis_status.max_size,
+                size: redis_status.size,
+                available: redis_status.available,
+                waiting: redis_status.waiting,
+            }
+        }),
+        cache: CacheStats {
+            degraded: cache_health.degraded,
+            errors: cache_health.errors,
+            bypassed: cache_health.bypassed,
+        },
+    })
+}
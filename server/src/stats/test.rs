@@ -0,0 +1,118 @@
+#[cfg(test)]
+mod tests {
+    use crate::{cache::CacheBackend, testing::TestContext};
+
+    use axum::{
+        body::Body,
+        http::{self, Method, Request, StatusCode},
+    };
+    use serde_json::json;
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn redis_connections_are_reused_across_draws() {
+        let ctx = TestContext::with_config(|config| {
+            config.cache.backend = CacheBackend::Redis;
+            config.features.pool_stats = true;
+        })
+        .await;
+        let app = ctx.app.clone();
+
+        let create_campaign_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/campaign")
+                    .method(Method::POST)
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(
+                        serde_json::to_string(&json!({
+                            "coupon_types": [{
+                                "description": "0%",
+                                "probability": 0.0,
+                                "total_quota": null,
+                                "daily_quota": null
+                            }]
+                        }))
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let body = hyper::body::to_bytes(create_campaign_response.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        let campaign_id = body["id"].as_i64().unwrap();
+
+        let store = &ctx.store;
+
+        let random_phones: Vec<String> = (0..3)
+            .map(|_| Uuid::new_v4().to_string()[..20].to_owned())
+            .collect();
+
+        let user_ids: Vec<i32> = sqlx::query_scalar!(
+            "--sql
+                insert into users (phone)
+                select * from unnest($1::text[])
+                returning id;
+            ",
+            &random_phones[..],
+        )
+        .fetch_all(&store.db_pool)
+        .await
+        .unwrap();
+
+        for user_id in user_ids {
+            let draw_response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .uri("/draw")
+                        .method(Method::POST)
+                        .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                        .body(Body::from(
+                            serde_json::to_string(&json!({
+                                "campaign_id": campaign_id,
+                                "user_id": user_id
+                            }))
+                            .unwrap(),
+                        ))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(draw_response.status(), StatusCode::OK);
+        }
+
+        // Sequential draws share a single pooled Redis connection
+
+        let pool_stats_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/stats/pools")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(pool_stats_response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(pool_stats_response.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(body["redis"]["size"], 1);
+        assert_eq!(body["redis"]["available"], 1);
+        assert_eq!(body["redis"]["waiting"], 0);
+        assert!(body["db"]["size"].as_u64().unwrap() >= 1);
+    }
+}
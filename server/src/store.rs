@@ -1,10 +1,25 @@
+use std::sync::Arc;
+
+use crate::cache::Cache;
+use crate::config::Config;
+use crate::metrics::Metrics;
+use crate::rate_limit::RateLimiter;
+
 use sqlx::pool::Pool;
 use sqlx::postgres::Postgres;
-use tokio::sync::Mutex;
 
-pub struct StoreInternal {
+/// Shared by every handler. Cloning is cheap and needs no locking: the pools and the cache are
+/// reference counted
+#[derive(Clone)]
+pub struct Store {
     pub db_pool: Pool<Postgres>,
-    pub redis: redis::Client,
+    /// Only set when the cache is backed by Redis. Connections are health-checked with a `PING`
+    /// when they are taken out of the pool, so broken ones are replaced by new connections once
+    /// Redis is reachable again
+    pub redis_pool: Option<deadpool_redis::Pool>,
+    pub cache: Arc<dyn Cache>,
+    /// `None` if rate limiting is disabled
+    pub rate_limiter: Option<Arc<dyn RateLimiter>>,
+    pub config: Arc<Config>,
+    pub metrics: Arc<Metrics>,
 }
-
-pub type Store = Mutex<StoreInternal>;
@@ -0,0 +1,139 @@
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use crate::{serve, testing::TestContext, REQUEST_ID_HEADER};
+
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+        routing, Router,
+    };
+    use tokio::sync::{oneshot, Notify};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn responses_carry_the_request_id() {
+        let ctx = TestContext::new().await;
+
+        // Generated if the client didn't send one
+
+        let response = ctx
+            .app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/user/0")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let request_id = response.headers()[&REQUEST_ID_HEADER].to_str().unwrap();
+
+        assert!(uuid::Uuid::parse_str(request_id).is_ok());
+
+        // Echoed otherwise, even on errors
+
+        let response = ctx
+            .app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/user/not-a-number")
+                    .header(&REQUEST_ID_HEADER, "req-1234")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.headers()[&REQUEST_ID_HEADER], "req-1234");
+    }
+
+    /// An app with a `/slow` route taking `duration`, which notifies `started` when called
+    fn slow_app(duration: Duration, started: Arc<Notify>) -> Router {
+        Router::new().route(
+            "/slow",
+            routing::get(move || async move {
+                started.notify_one();
+                tokio::time::sleep(duration).await;
+                "done"
+            }),
+        )
+    }
+
+    #[tokio::test]
+    async fn shutdown_drains_in_flight_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let started = Arc::new(Notify::new());
+        let (shutdown, shutting_down) = oneshot::channel();
+
+        let server = tokio::spawn(serve(
+            listener,
+            slow_app(Duration::from_millis(300), started.clone()),
+            async {
+                shutting_down.await.ok();
+            },
+            Duration::from_secs(10),
+        ));
+
+        let request = tokio::spawn(
+            hyper::Client::new().get(format!("http://{address}/slow").parse().unwrap()),
+        );
+
+        started.notified().await;
+        shutdown.send(()).unwrap();
+
+        // The request started before the shutdown still completes
+
+        let response = request.await.unwrap().unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+        assert_eq!(&body[..], b"done");
+        assert!(server.await.unwrap().unwrap());
+
+        // No new connections are accepted
+
+        let result = hyper::Client::new()
+            .get(format!("http://{address}/slow").parse().unwrap())
+            .await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn shutdown_gives_up_on_requests_after_the_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let started = Arc::new(Notify::new());
+        let (shutdown, shutting_down) = oneshot::channel();
+
+        let server = tokio::spawn(serve(
+            listener,
+            slow_app(Duration::from_secs(60), started.clone()),
+            async {
+                shutting_down.await.ok();
+            },
+            Duration::from_millis(200),
+        ));
+
+        tokio::spawn(hyper::Client::new().get(format!("http://{address}/slow").parse().unwrap()));
+
+        started.notified().await;
+
+        let start = Instant::now();
+
+        shutdown.send(()).unwrap();
+
+        assert!(!server.await.unwrap().unwrap());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
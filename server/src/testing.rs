@@ -0,0 +1,170 @@
+//! Hermetic test setup. Every [`TestContext`] gets a database of its own on the Postgres of
+//! `DATABASE_URL`, migrated from scratch and dropped once the test is done, so tests can run in
+//! parallel and leave nothing behind
+
+use axum::{
+    body::Body,
+    http::{self, HeaderMap, Method, Request, StatusCode},
+    Router,
+};
+use sqlx::{Connection, PgConnection};
+use tower::ServiceExt;
+use uuid::Uuid;
+
+use crate::cache::CacheBackend;
+use crate::config::Config;
+use crate::rate_limit::RateLimitBackend;
+use crate::store::Store;
+use crate::{connect_store, create_router};
+
+/// Configured as the admin token of every [`TestContext`]
+pub const ADMIN_TOKEN: &str = "test-admin-token-0123456789abcdef";
+
+pub struct TestContext {
+    pub store: Store,
+    pub app: Router,
+    /// Name of the test's database, also used to prefix its Redis keys
+    pub db_name: String,
+    admin_url: String,
+}
+
+impl TestContext {
+    /// With the in-memory cache and rate limiter, so that no Redis is needed
+    pub async fn new() -> Self {
+        Self::with_config(|config| {
+            config.cache.backend = CacheBackend::Memory;
+            config.rate_limit.backend = RateLimitBackend::Memory;
+        })
+        .await
+    }
+
+    /// With the Redis of `REDIS_URL` for the cache and rate limiter. Keys are prefixed with the
+    /// name of the test's database
+    pub async fn with_redis() -> Self {
+        Self::with_config(|config| {
+            config.cache.backend = CacheBackend::Redis;
+            config.rate_limit.backend = RateLimitBackend::Redis;
+        })
+        .await
+    }
+
+    /// With the configuration of the environment, adjusted by `configure`
+    pub async fn with_config(configure: impl FnOnce(&mut Config)) -> Self {
+        dotenv::dotenv().ok();
+
+        let mut config = Config::read().expect("Failed to read configuration");
+        let admin_url = config.database.url.clone();
+        let db_name = format!("test_{}", Uuid::new_v4().simple());
+
+        let mut admin = PgConnection::connect(&admin_url)
+            .await
+            .expect("Failed to connect to DB");
+
+        sqlx::query(&format!(r#"create database "{db_name}";"#))
+            .execute(&mut admin)
+            .await
+            .expect("Failed to create test DB");
+
+        admin.close().await.ok();
+
+        config.database.url = database_url(&admin_url, &db_name);
+        config.redis.key_prefix = format!("{db_name}:");
+        config.admin.token = Some(ADMIN_TOKEN.to_string());
+        configure(&mut config);
+        config.validate().expect("Invalid configuration");
+
+        // Migrations are run on connecting
+        let store = connect_store(config).await;
+        let app = create_router(store.clone());
+
+        Self {
+            store,
+            app,
+            db_name,
+            admin_url,
+        }
+    }
+}
+
+impl Drop for TestContext {
+    fn drop(&mut self) {
+        let admin_url = self.admin_url.clone();
+        let db_name = self.db_name.clone();
+
+        // The test's runtime can't be blocked on from here, so the database is dropped on a runtime
+        // of its own. Errors are only reported, as panicking while a failed test unwinds aborts
+        let result = std::thread::spawn(move || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?
+                .block_on(async {
+                    let mut admin = PgConnection::connect(&admin_url).await?;
+
+                    // Forcefully, as the store's pool still holds connections
+                    sqlx::query(&format!(
+                        r#"drop database if exists "{db_name}" with (force);"#
+                    ))
+                    .execute(&mut admin)
+                    .await?;
+
+                    admin.close().await?;
+
+                    Ok::<_, Box<dyn std::error::Error + Send + Sync>>(())
+                })
+        })
+        .join();
+
+        if let Ok(Err(e)) = result {
+            eprintln!("Failed to drop test DB {}: {e}", self.db_name);
+        }
+    }
+}
+
+/// The same sequence of random numbers on every run
+pub fn seeded_rng() -> rand::rngs::StdRng {
+    rand::SeedableRng::seed_from_u64(42)
+}
+
+/// Posts `body` to `uri` with the extra `headers`, and returns the status, headers and JSON body of
+/// the response. An empty or non-JSON body comes back as null
+pub async fn post_json(
+    app: &Router,
+    uri: &str,
+    headers: &[(&str, &str)],
+    body: serde_json::Value,
+) -> (StatusCode, HeaderMap, serde_json::Value) {
+    let mut request = Request::builder()
+        .uri(uri)
+        .method(Method::POST)
+        .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref());
+
+    for &(name, value) in headers {
+        request = request.header(name, value);
+    }
+
+    let response = app
+        .clone()
+        .oneshot(request.body(Body::from(body.to_string())).unwrap())
+        .await
+        .unwrap();
+
+    let status = response.status();
+    let headers = response.headers().clone();
+
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body = serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null);
+
+    (status, headers, body)
+}
+
+/// `url` with its database replaced by `db_name`
+fn database_url(url: &str, db_name: &str) -> String {
+    let (url, params) = url.split_once('?').unwrap_or((url, ""));
+    let (server, _) = url.rsplit_once('/').expect("DATABASE_URL has no database");
+
+    if params.is_empty() {
+        format!("{server}/{db_name}")
+    } else {
+        format!("{server}/{db_name}?{params}")
+    }
+}
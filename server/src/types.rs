@@ -5,8 +5,25 @@ use utoipa::ToSchema;
 #[derive(Serialize, Deserialize, ToSchema, Clone, FromRow)]
 pub struct User {
     pub id: i32,
+    /// Scrubbed (null) once the user has been anonymized, along with the other personal details
     #[schema(example = r"+852 1234 5678")]
-    pub phone: String,
+    pub phone: Option<String>,
+    #[schema(example = "Chan Tai Man")]
+    pub name: Option<String>,
+    #[schema(example = "taiman@example.com")]
+    pub email: Option<String>,
+    #[schema(example = "zh-HK")]
+    pub locale: Option<String>,
+    #[schema(example = "gold")]
+    pub membership_tier: Option<String>,
+    pub marketing_opt_in: bool,
+    /// When the user last opted in to marketing. Cleared when they opt out
+    pub marketing_opt_in_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// When the phone number was verified. Cleared when the phone number changes
+    pub phone_verified_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub anonymized_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 #[derive(Serialize, Deserialize, ToSchema, Clone, FromRow)]
@@ -17,7 +34,6 @@ pub struct Campaign {
 #[derive(ToSchema, Clone, FromRow)]
 pub struct CampaignCouponType {
     pub id: i32,
-    pub campaign_id: i32,
     pub description: String,
     #[schema(example = "0.1")]
     pub probability: f32,
@@ -26,6 +42,8 @@ pub struct CampaignCouponType {
     pub current_quota: Option<i32>,
     pub current_daily_quota: Option<i32>,
     pub last_drawn_date: Option<chrono::NaiveDate>,
+    pub fallback: bool,
+    pub first_draw: bool,
 }
 
 #[derive(Serialize, Deserialize, ToSchema, Clone, FromRow)]
@@ -37,11 +55,30 @@ pub struct CampaignCoupon {
     pub redeemed: bool,
 }
 
-#[derive(ToSchema, Clone, FromRow)]
-pub struct Draw {
+#[derive(Serialize, Deserialize, ToSchema, Clone, FromRow)]
+pub struct ApiKey {
+    pub id: i32,
+    #[schema(example = "merchant-1")]
+    pub merchant: String,
+    #[schema(example = "ldk_Xq3f")]
+    pub key_prefix: String,
+    pub campaign_ids: Vec<i32>,
+    pub usage_count: i64,
+    pub last_used_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub revoked_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// A draw of a user along with the coupon they got, if any
+#[derive(Serialize, Deserialize, ToSchema, Clone, FromRow)]
+pub struct UserDataExportDraw {
     pub id: i32,
-    pub user_id: i32,
     pub campaign_id: i32,
-    pub campaign_coupon_id: Option<i32>,
     pub date: chrono::NaiveDate,
+    pub coupon_id: Option<i32>,
+    #[schema(example = "10% off")]
+    pub coupon_description: Option<String>,
+    #[schema(example = "BK81-DNFJ")]
+    pub redeem_code: Option<String>,
+    pub redeemed: Option<bool>,
 }
@@ -1,49 +1,100 @@
-use std::sync::Arc;
-
 use axum::{
-    extract::{Path, State},
-    response::IntoResponse,
-    Json,
+    extract::State,
+    http::HeaderMap,
+    response::{IntoResponse, Response},
 };
 use hyper::StatusCode;
-use serde::{Deserialize, Serialize};
-use utoipa::ToSchema;
+use serde::{Deserialize, Deserializer, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
+use crate::admin;
+use crate::eligibility::normalize_phone;
+use crate::error::{AppError, ErrorCode};
+use crate::extract::{Json, Path, Query};
+use crate::repository::{DrawRepository, PgRepository, UserFilter, UserRepository, UserUpdate};
 use crate::store::Store;
-use crate::types::User;
+use crate::types::{User, UserDataExportDraw};
 
 mod test;
 
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(Deserialize, IntoParams)]
+pub(super) struct ListUsersQuery {
+    /// Look up users by phone number. Spaces, dashes and brackets are ignored
+    #[param(example = "+85212345678")]
+    pub phone: Option<String>,
+    /// Only list users created on or after this date (UTC)
+    pub created_from: Option<chrono::NaiveDate>,
+    /// Only list users created on or before this date (UTC)
+    pub created_to: Option<chrono::NaiveDate>,
+    /// Only list users whose phone number is (or isn't) verified
+    pub verified: Option<bool>,
+    /// Only list users who have drawn in this campaign
+    pub drawn_in_campaign: Option<i32>,
+    /// The `next_cursor` of the previous page
+    pub cursor: Option<i32>,
+    /// Number of users per page, at most 500
+    #[param(example = 50)]
+    pub limit: Option<i64>,
+}
+
 #[derive(Serialize, Deserialize, ToSchema)]
-pub(super) enum UserError {
-    #[schema(example = "User ID already exists")]
-    Conflict(String),
-    #[schema(example = "User ID doesn't exist")]
-    NotFound(String),
+pub(super) struct ListUsersResult {
+    pub users: Vec<User>,
+    /// Number of users matching the filters across all pages
+    pub total_count: i64,
+    /// Pass as `cursor` to get the next page. Null on the last page
+    pub next_cursor: Option<i32>,
 }
 
 #[utoipa::path(
     get,
     path = "/user",
+    params(ListUsersQuery),
     responses(
-        (status = 200, description = "List all users successfully", body = [User])
+        (status = 200, description = "List users that haven't been deleted successfully, newest first", body = ListUsersResult),
+        (status = 500, description = "Database error", body = ErrorBody),
+        (status = 503, description = "Database temporarily unavailable", body = ErrorBody)
     )
 )]
-pub(super) async fn list_users(State(store): State<Arc<Store>>) -> Json<Vec<User>> {
-    let db_pool = store.lock().await.db_pool.clone();
-
-    let users = sqlx::query_as!(
-        User,
-        "--sql
-            select *
-            from users;
-        "
-    )
-    .fetch_all(&db_pool)
-    .await
-    .unwrap();
+pub(super) async fn list_users(
+    State(store): State<Store>,
+    Query(query): Query<ListUsersQuery>,
+) -> Result<Json<ListUsersResult>, AppError> {
+    let repository = PgRepository::new(store.db_pool.clone(), store.metrics.clone());
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
 
-    Json(users)
+    let filter = UserFilter {
+        phone: query.phone,
+        created_from: query.created_from,
+        created_to: query.created_to,
+        verified: query.verified,
+        drawn_in_campaign: query.drawn_in_campaign,
+    };
+
+    // Fetch one extra row to find out whether there is a next page
+
+    let mut users = repository.list(&filter, query.cursor, limit + 1).await?;
+    let total_count = repository.count(&filter).await?;
+
+    let next_cursor = if users.len() as i64 > limit {
+        users.truncate(limit as usize);
+        users.last().map(|u| u.id)
+    } else {
+        None
+    };
+
+    Ok(Json(ListUsersResult {
+        users,
+        total_count,
+        next_cursor,
+    }))
 }
 
 #[derive(Serialize, Deserialize, ToSchema)]
@@ -58,76 +109,267 @@ pub(super) struct CreateUserPayload {
     request_body = CreateUserPayload,
     responses(
         (status = 201, description = "User created successfully", body = User),
-        (status = 409, description = "Phone number is registered by another user", body = UserError)
+        (status = 400, description = "Phone number is invalid", body = ErrorBody),
+        (status = 409, description = "Phone number is registered by another user", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+        (status = 503, description = "Database temporarily unavailable", body = ErrorBody)
     )
 )]
 pub(super) async fn create_user(
-    State(store): State<Arc<Store>>,
+    State(store): State<Store>,
     Json(payload): Json<CreateUserPayload>,
-) -> impl IntoResponse {
-    let db_pool = store.lock().await.db_pool.clone();
-
-    let request = sqlx::query_as!(
-        User,
-        "--sql
-            insert into users (phone)
-            values ($1)
-            returning *;
-        ",
-        payload.phone,
+) -> Result<Response, AppError> {
+    validate_phone(&payload.phone)?;
+
+    let repository = PgRepository::new(store.db_pool.clone(), store.metrics.clone());
+
+    let new_user = UserRepository::create(&repository, &payload.phone).await?;
+
+    Ok((StatusCode::CREATED, Json(new_user)).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/user/{id}",
+    responses(
+        (status = 200, description = "Get user successfully", body = User),
+        (status = 404, description = "User not found", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+        (status = 503, description = "Database temporarily unavailable", body = ErrorBody)
+    ),
+    params(
+        ("id" = i32, Path, description = "User id")
     )
-    .fetch_one(&db_pool)
-    .await;
-
-    match request {
-        Ok(new_user) => (StatusCode::CREATED, Json(new_user)).into_response(),
-        Err(_) => (
-            StatusCode::CONFLICT,
-            Json(UserError::Conflict(format!(
-                "Phone number {} is registered by another user",
-                payload.phone
-            ))),
-        )
-            .into_response(),
+)]
+pub(super) async fn get_user(
+    Path(id): Path<i32>,
+    State(store): State<Store>,
+) -> Result<Response, AppError> {
+    let repository = PgRepository::new(store.db_pool.clone(), store.metrics.clone());
+
+    match repository.find_active(id).await? {
+        Some(user) => Ok((StatusCode::OK, Json(user)).into_response()),
+        None => Err(AppError::new(
+            ErrorCode::UserNotFound,
+            format!("User with ID {id} doesn't exist"),
+        )),
     }
 }
 
+/// The length of the `users.phone` column
+const MAX_PHONE_LENGTH: usize = 20;
+
+/// Rejects phone numbers the database can't store, or that consist only of formatting
+fn validate_phone(phone: &str) -> Result<(), AppError> {
+    if normalize_phone(phone).is_empty() || phone.chars().count() > MAX_PHONE_LENGTH {
+        return Err(AppError::new(
+            ErrorCode::InvalidPhone,
+            format!("Phone number {phone} is invalid"),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Distinguishes a field explicitly set to `null` (`Some(None)`) from an absent one (`None`)
+fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// Absent fields are left unchanged, and `null` clears an optional field
+#[derive(Serialize, Deserialize, ToSchema)]
+pub(super) struct UpdateUserPayload {
+    #[schema(example = r"+852 1234 5678")]
+    pub phone: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    #[schema(value_type = Option<String>, example = "Chan Tai Man")]
+    pub name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    #[schema(value_type = Option<String>, example = "taiman@example.com")]
+    pub email: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    #[schema(value_type = Option<String>, example = "zh-HK")]
+    pub locale: Option<Option<String>>,
+    /// Requires the admin token, as eligibility rules can restrict campaigns to tiers
+    #[serde(default, deserialize_with = "deserialize_some")]
+    #[schema(value_type = Option<String>, example = "gold")]
+    pub membership_tier: Option<Option<String>>,
+    pub marketing_opt_in: Option<bool>,
+    /// Set once the phone number has been verified, e.g. by SMS. Requires the admin token, as
+    /// eligibility rules can restrict campaigns to verified users
+    pub phone_verified: Option<bool>,
+}
+
+#[utoipa::path(
+    patch,
+    path = "/user/{id}",
+    request_body = UpdateUserPayload,
+    responses(
+        (status = 200, description = "Update user successfully", body = User),
+        (status = 400, description = "Phone number, email address or locale is invalid", body = ErrorBody),
+        (status = 401, description = "Admin token is missing or invalid, when setting the membership tier or verifying the phone number", body = ErrorBody),
+        (status = 404, description = "User not found", body = ErrorBody),
+        (status = 409, description = "Phone number is registered by another user", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+        (status = 503, description = "Database temporarily unavailable", body = ErrorBody)
+    ),
+    params(
+        ("id" = i32, Path, description = "User id")
+    ),
+    security(
+        (),
+        ("admin_token" = [])
+    )
+)]
+pub(super) async fn update_user(
+    Path(id): Path<i32>,
+    State(store): State<Store>,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateUserPayload>,
+) -> Result<Response, AppError> {
+    // Users could otherwise make themselves eligible for campaigns restricted by these
+    if payload.membership_tier.is_some() || payload.phone_verified.is_some() {
+        admin::authorize(&store, &headers)?;
+    }
+
+    let repository = PgRepository::new(store.db_pool.clone(), store.metrics.clone());
+
+    if let Some(phone) = &payload.phone {
+        validate_phone(phone)?;
+    }
+
+    if let Some(Some(email)) = &payload.email {
+        if !email.contains('@') {
+            return Err(AppError::new(
+                ErrorCode::InvalidEmail,
+                format!("Email address {email} is invalid"),
+            ));
+        }
+    }
+
+    if let Some(Some(locale)) = &payload.locale {
+        if locale.is_empty()
+            || !locale
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return Err(AppError::new(
+                ErrorCode::InvalidLocale,
+                format!("Locale {locale} is invalid"),
+            ));
+        }
+    }
+
+    let update = UserUpdate {
+        phone: payload.phone,
+        name: payload.name,
+        email: payload.email,
+        locale: payload.locale,
+        membership_tier: payload.membership_tier,
+        marketing_opt_in: payload.marketing_opt_in,
+        phone_verified: payload.phone_verified,
+    };
+
+    match repository.update(id, &update).await? {
+        Some(user) => Ok((StatusCode::OK, Json(user)).into_response()),
+        None => Err(AppError::new(
+            ErrorCode::UserNotFound,
+            format!("User with ID {id} doesn't exist"),
+        )),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+pub(super) struct DeleteUserQuery {
+    /// Also scrub the user's personal details. Their draws and coupons are kept for statistics.
+    /// Requires the admin token
+    #[serde(default)]
+    pub anonymize: bool,
+}
+
 #[utoipa::path(
     delete,
     path = "/user/{id}",
     responses(
         (status = 200, description = "Delete user successfully"),
-        (status = 404, description = "User not found", body = UserError, example = json!(UserError::NotFound(String::from("User with ID 1 doesn't exist"))))
+        (status = 401, description = "Admin token is missing or invalid, when anonymizing", body = ErrorBody),
+        (status = 404, description = "User not found, or user has already been deleted", body = ErrorBody, example = json!({ "type": "not_found", "code": "user_not_found", "message": "User with ID 1 doesn't exist or has already been deleted" })),
+        (status = 500, description = "Database error", body = ErrorBody),
+        (status = 503, description = "Database temporarily unavailable", body = ErrorBody)
     ),
     params(
-        ("id" = i32, Path, description = "User id")
+        ("id" = i32, Path, description = "User id"),
+        DeleteUserQuery
+    ),
+    security(
+        (),
+        ("admin_token" = [])
     )
 )]
 pub(super) async fn delete_user(
     Path(id): Path<i32>,
-    State(store): State<Arc<Store>>,
-) -> impl IntoResponse {
-    let db_pool = store.lock().await.db_pool.clone();
-
-    let q_result = sqlx::query!(
-        "--sql
-            delete from users
-            where id = $1;
-        ",
-        id
-    )
-    .execute(&db_pool)
-    .await
-    .unwrap();
-
-    match q_result.rows_affected() {
-        0 => (
-            StatusCode::NOT_FOUND,
-            Json(UserError::NotFound(format!(
-                "User with ID {id} doesn't exist"
-            ))),
-        )
-            .into_response(),
-        _ => StatusCode::OK.into_response(),
+    Query(query): Query<DeleteUserQuery>,
+    State(store): State<Store>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    // Anonymizing erases personal data for good, so it is as privileged as the export
+    if query.anonymize {
+        admin::authorize(&store, &headers)?;
     }
+
+    let repository = PgRepository::new(store.db_pool.clone(), store.metrics.clone());
+
+    if repository.delete(id, query.anonymize).await? {
+        Ok(StatusCode::OK.into_response())
+    } else {
+        Err(AppError::new(
+            ErrorCode::UserNotFound,
+            format!("User with ID {id} doesn't exist or has already been deleted"),
+        ))
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub(super) struct UserDataExport {
+    pub user: User,
+    pub draws: Vec<UserDataExportDraw>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/user/{id}/export",
+    responses(
+        (status = 200, description = "Export everything held about the user successfully", body = UserDataExport),
+        (status = 401, description = "Admin token is missing or invalid", body = ErrorBody),
+        (status = 404, description = "User not found", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+        (status = 503, description = "Database temporarily unavailable", body = ErrorBody)
+    ),
+    params(
+        ("id" = i32, Path, description = "User id")
+    ),
+    security(
+        ("admin_token" = [])
+    )
+)]
+pub(super) async fn export_user(
+    Path(id): Path<i32>,
+    State(store): State<Store>,
+) -> Result<Response, AppError> {
+    let repository = PgRepository::new(store.db_pool.clone(), store.metrics.clone());
+
+    let Some(user) = repository.find(id).await? else {
+        return Err(AppError::new(
+            ErrorCode::UserNotFound,
+            format!("User with ID {id} doesn't exist"),
+        ));
+    };
+
+    let draws = repository.list_by_user(id).await?;
+
+    Ok((StatusCode::OK, Json(UserDataExport { user, draws })).into_response())
 }
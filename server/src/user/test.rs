@@ -1,17 +1,24 @@
 #[cfg(test)]
 mod tests {
-    use crate::{create_app, user::CreateUserPayload};
+    use crate::{
+        admin::ADMIN_TOKEN_HEADER,
+        repository::{PgRepository, UserFilter, UserRepository},
+        testing::{post_json, TestContext, ADMIN_TOKEN},
+        user::CreateUserPayload,
+    };
 
     use axum::{
         body::Body,
         http::{self, Method, Request, StatusCode},
     };
+    use serde_json::json;
     use tower::ServiceExt;
     use uuid::Uuid;
 
     #[tokio::test]
     async fn create_list_delete_user() {
-        let app = create_app().await;
+        let ctx = TestContext::new().await;
+        let app = ctx.app.clone();
 
         let phone = &Uuid::new_v4().to_string()[..20];
 
@@ -55,11 +62,146 @@ mod tests {
             .unwrap();
         let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
 
-        assert!(body
+        assert!(body["users"]
             .as_array()
             .unwrap()
             .iter()
-            .any(|user| { user["phone"] == phone.to_string() }));
+            .any(|user| { user["phone"] == phone }));
+
+        let delete_user_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/user/{}", user_id))
+                    .method(Method::DELETE)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(delete_user_response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn soft_delete_anonymize_and_export_user() {
+        let ctx = TestContext::new().await;
+        let app = ctx.app.clone();
+
+        let phone = &Uuid::new_v4().to_string()[..20];
+
+        let create_user_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/user")
+                    .method(Method::POST)
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(
+                        serde_json::to_string(&CreateUserPayload {
+                            phone: phone.to_string(),
+                        })
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(create_user_response.status(), StatusCode::CREATED);
+
+        let body = hyper::body::to_bytes(create_user_response.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        let user_id = body["id"].as_i64().unwrap();
+
+        let update_user_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/user/{}", user_id))
+                    .method(Method::PATCH)
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .header(ADMIN_TOKEN_HEADER, ADMIN_TOKEN)
+                    .body(Body::from(
+                        serde_json::to_string(&json!({
+                            "locale": "zh-HK",
+                            "membership_tier": "gold"
+                        }))
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(update_user_response.status(), StatusCode::OK);
+
+        // Draw a coupon so that the user has some history. The second campaign is for checking
+        // that the user can't draw after being deleted
+
+        let mut campaign_ids = vec![];
+
+        for _ in 0..2 {
+            let create_campaign_response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .uri("/campaign")
+                        .method(Method::POST)
+                        .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                        .body(Body::from(
+                            serde_json::to_string(&json!({
+                                "coupon_types": [{
+                                    "description": "100%",
+                                    "probability": 1.0,
+                                    "total_quota": null,
+                                    "daily_quota": null
+                                }]
+                            }))
+                            .unwrap(),
+                        ))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            let body = hyper::body::to_bytes(create_campaign_response.into_body())
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+            campaign_ids.push(body["id"].as_i64().unwrap());
+        }
+
+        let campaign_id = campaign_ids[0];
+
+        let draw_request = |campaign_id: i64| {
+            Request::builder()
+                .uri("/draw")
+                .method(Method::POST)
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(Body::from(
+                    serde_json::to_string(&json!({
+                        "campaign_id": campaign_id,
+                        "user_id": user_id
+                    }))
+                    .unwrap(),
+                ))
+                .unwrap()
+        };
+
+        let draw_response = app
+            .clone()
+            .oneshot(draw_request(campaign_id))
+            .await
+            .unwrap();
+
+        assert_eq!(draw_response.status(), StatusCode::OK);
+
+        // Soft-deleted users are hidden from the listing and can't draw
 
         let delete_user_response = app
             .clone()
@@ -74,5 +216,763 @@ mod tests {
             .unwrap();
 
         assert_eq!(delete_user_response.status(), StatusCode::OK);
+
+        let list_users_response = app
+            .clone()
+            .oneshot(Request::builder().uri("/user").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let body = hyper::body::to_bytes(list_users_response.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert!(!body["users"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|user| { user["id"] == user_id }));
+
+        let draw_response = app
+            .clone()
+            .oneshot(draw_request(campaign_ids[1]))
+            .await
+            .unwrap();
+
+        assert_eq!(draw_response.status(), StatusCode::NOT_FOUND);
+
+        // Deleting twice fails
+
+        let delete_user_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/user/{}", user_id))
+                    .method(Method::DELETE)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(delete_user_response.status(), StatusCode::NOT_FOUND);
+
+        // The phone number can be registered again
+
+        let create_user_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/user")
+                    .method(Method::POST)
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(
+                        serde_json::to_string(&CreateUserPayload {
+                            phone: phone.to_string(),
+                        })
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(create_user_response.status(), StatusCode::CREATED);
+
+        // Anonymizing scrubs the personal details but keeps the draw history
+
+        let anonymize_user_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/user/{}?anonymize=true", user_id))
+                    .method(Method::DELETE)
+                    .header(ADMIN_TOKEN_HEADER, ADMIN_TOKEN)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(anonymize_user_response.status(), StatusCode::OK);
+
+        let export_user_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/user/{}/export", user_id))
+                    .header(ADMIN_TOKEN_HEADER, ADMIN_TOKEN)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(export_user_response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(export_user_response.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(body["user"]["phone"], serde_json::Value::Null);
+        assert_eq!(body["user"]["locale"], serde_json::Value::Null);
+        assert_eq!(body["user"]["membership_tier"], serde_json::Value::Null);
+        assert!(body["user"]["deleted_at"].is_string());
+        assert!(body["user"]["anonymized_at"].is_string());
+
+        let draws = body["draws"].as_array().unwrap();
+        assert_eq!(draws.len(), 1);
+        assert_eq!(draws[0]["campaign_id"], campaign_id);
+        assert_eq!(draws[0]["coupon_description"], "100%");
+        assert!(draws[0]["redeem_code"].is_string());
+
+        let anonymize_user_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/user/{}?anonymize=true", user_id))
+                    .method(Method::DELETE)
+                    .header(ADMIN_TOKEN_HEADER, ADMIN_TOKEN)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(anonymize_user_response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn export_and_anonymize_require_the_admin_token() {
+        let ctx = TestContext::new().await;
+        let app = ctx.app.clone();
+
+        let (status, _, user) = post_json(
+            &app,
+            "/user",
+            &[],
+            json!({ "phone": &Uuid::new_v4().to_string()[..20] }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let user_id = user["id"].as_i64().unwrap();
+
+        let export_uri = format!("/user/{user_id}/export");
+        let anonymize_uri = format!("/user/{user_id}?anonymize=true");
+
+        for (method, uri) in [(Method::GET, &export_uri), (Method::DELETE, &anonymize_uri)] {
+            for token in [None, Some("wrong")] {
+                let mut request = Request::builder().uri(uri).method(method.clone());
+
+                if let Some(token) = token {
+                    request = request.header(ADMIN_TOKEN_HEADER, token);
+                }
+
+                let response = app
+                    .clone()
+                    .oneshot(request.body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+
+                assert_eq!(
+                    response.status(),
+                    StatusCode::UNAUTHORIZED,
+                    "{method} {uri}"
+                );
+            }
+        }
+
+        // The user was left as is
+
+        let user = PgRepository::new(ctx.store.db_pool.clone(), ctx.store.metrics.clone())
+            .find(user_id as i32)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(user.phone.is_some());
+        assert_eq!(user.deleted_at, None);
+    }
+
+    #[tokio::test]
+    async fn only_admins_can_set_fields_that_eligibility_rules_check() {
+        let ctx = TestContext::new().await;
+        let app = ctx.app.clone();
+
+        let (status, _, user) = post_json(
+            &app,
+            "/user",
+            &[],
+            json!({ "phone": &Uuid::new_v4().to_string()[..20] }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let user_id = user["id"].as_i64().unwrap();
+
+        let update_user = |body: serde_json::Value, token: Option<&str>| {
+            let mut request = Request::builder()
+                .uri(format!("/user/{user_id}"))
+                .method(Method::PATCH)
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref());
+
+            if let Some(token) = token {
+                request = request.header(ADMIN_TOKEN_HEADER, token);
+            }
+
+            app.clone()
+                .oneshot(request.body(Body::from(body.to_string())).unwrap())
+        };
+
+        for body in [
+            json!({ "membership_tier": "gold", "phone_verified": true }),
+            json!({ "membership_tier": "gold" }),
+            json!({ "membership_tier": null }),
+            json!({ "phone_verified": true }),
+        ] {
+            for token in [None, Some("wrong")] {
+                let response = update_user(body.clone(), token).await.unwrap();
+
+                assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{body}");
+            }
+        }
+
+        let user = PgRepository::new(ctx.store.db_pool.clone(), ctx.store.metrics.clone())
+            .find(user_id as i32)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(user.membership_tier, None);
+        assert_eq!(user.phone_verified_at, None);
+
+        // Users can still update the rest of their profile, and admins everything
+
+        let response = update_user(json!({ "name": "Chan Tai Man" }), None)
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = update_user(
+            json!({ "membership_tier": "gold", "phone_verified": true }),
+            Some(ADMIN_TOKEN),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(body["membership_tier"], "gold");
+        assert!(body["phone_verified_at"].is_string());
+    }
+
+    #[tokio::test]
+    async fn update_get_and_look_up_user_by_phone() {
+        let ctx = TestContext::new().await;
+        let app = ctx.app.clone();
+
+        let digits: u32 = rand::random::<u32>() % 100_000_000;
+        let phone = format!("+852 {:04} {:04}", digits / 10_000, digits % 10_000);
+
+        let create_user_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/user")
+                    .method(Method::POST)
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(
+                        serde_json::to_string(&CreateUserPayload {
+                            phone: phone.clone(),
+                        })
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(create_user_response.status(), StatusCode::CREATED);
+
+        let body = hyper::body::to_bytes(create_user_response.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        let user_id = body["id"].as_i64().unwrap();
+        assert_eq!(body["marketing_opt_in"], false);
+
+        // The number can't be registered again in another format
+
+        let (status, _, body) = post_json(
+            &app,
+            "/user",
+            &[],
+            json!({ "phone": phone.replace(' ', "") }),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["code"], "phone_taken");
+
+        let update_user_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/user/{}", user_id))
+                    .method(Method::PATCH)
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(
+                        serde_json::to_string(&json!({
+                            "name": "Chan Tai Man",
+                            "email": "taiman@example.com",
+                            "locale": "zh-HK",
+                            "marketing_opt_in": true
+                        }))
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(update_user_response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(update_user_response.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(body["name"], "Chan Tai Man");
+        assert_eq!(body["marketing_opt_in"], true);
+        assert!(body["marketing_opt_in_at"].is_string());
+
+        // Absent fields are kept, null clears a field, and opting out clears the timestamp
+
+        let update_user_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/user/{}", user_id))
+                    .method(Method::PATCH)
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(
+                        serde_json::to_string(&json!({
+                            "email": null,
+                            "marketing_opt_in": false
+                        }))
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(update_user_response.status(), StatusCode::OK);
+
+        let get_user_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/user/{}", user_id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(get_user_response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(get_user_response.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(body["name"], "Chan Tai Man");
+        assert_eq!(body["email"], serde_json::Value::Null);
+        assert_eq!(body["locale"], "zh-HK");
+        assert_eq!(body["marketing_opt_in"], false);
+        assert_eq!(body["marketing_opt_in_at"], serde_json::Value::Null);
+
+        let update_user_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/user/{}", user_id))
+                    .method(Method::PATCH)
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(
+                        serde_json::to_string(&json!({ "email": "not-an-email" })).unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(update_user_response.status(), StatusCode::BAD_REQUEST);
+
+        // Customer service can find the user without typing the spaces
+
+        let look_up_user_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!(
+                        "/user?phone={}",
+                        phone.replace(' ', "").replace('+', "%2B")
+                    ))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(look_up_user_response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(look_up_user_response.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        let users = body["users"].as_array().unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0]["id"], user_id);
+
+        let get_user_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/user/999999")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(get_user_response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn reject_blank_or_too_long_phone_numbers() {
+        let ctx = TestContext::new().await;
+        let app = ctx.app.clone();
+
+        for phone in ["", " - ", "+852 1234 5678 9012 3456"] {
+            let (status, _, body) = post_json(&app, "/user", &[], json!({ "phone": phone })).await;
+
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{phone:?}");
+            assert_eq!(body["code"], "invalid_phone");
+        }
+
+        let (status, _, body) = post_json(
+            &app,
+            "/user",
+            &[],
+            json!({ "phone": &Uuid::new_v4().to_string()[..20] }),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::CREATED);
+
+        let user_id = body["id"].as_i64().unwrap();
+
+        let update_user_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/user/{user_id}"))
+                    .method(Method::PATCH)
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(
+                        serde_json::to_string(&json!({ "phone": "" })).unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(update_user_response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn paginate_and_filter_users() {
+        let ctx = TestContext::new().await;
+        let app = ctx.app.clone();
+
+        let create_campaign_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/campaign")
+                    .method(Method::POST)
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(
+                        serde_json::to_string(&json!({
+                            "coupon_types": [{
+                                "description": "0%",
+                                "probability": 0.0,
+                                "total_quota": null,
+                                "daily_quota": null
+                            }]
+                        }))
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let body = hyper::body::to_bytes(create_campaign_response.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        let campaign_id = body["id"].as_i64().unwrap();
+
+        // Create 3 users who draw in the campaign, so that they can be told apart from the users
+        // created by other tests
+
+        let mut user_ids = vec![];
+
+        for _ in 0..3 {
+            let create_user_response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .uri("/user")
+                        .method(Method::POST)
+                        .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                        .body(Body::from(
+                            serde_json::to_string(&CreateUserPayload {
+                                phone: Uuid::new_v4().to_string()[..20].to_string(),
+                            })
+                            .unwrap(),
+                        ))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            let body = hyper::body::to_bytes(create_user_response.into_body())
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+            let user_id = body["id"].as_i64().unwrap();
+            user_ids.push(user_id);
+
+            let draw_response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .uri("/draw")
+                        .method(Method::POST)
+                        .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                        .body(Body::from(
+                            serde_json::to_string(&json!({
+                                "campaign_id": campaign_id,
+                                "user_id": user_id
+                            }))
+                            .unwrap(),
+                        ))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(draw_response.status(), StatusCode::OK);
+        }
+
+        let update_user_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/user/{}", user_ids[0]))
+                    .method(Method::PATCH)
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .header(ADMIN_TOKEN_HEADER, ADMIN_TOKEN)
+                    .body(Body::from(
+                        serde_json::to_string(&json!({ "phone_verified": true })).unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(update_user_response.status(), StatusCode::OK);
+
+        let list_users = |query: String| {
+            let app = app.clone();
+
+            async move {
+                let list_users_response = app
+                    .oneshot(
+                        Request::builder()
+                            .uri(format!("/user?drawn_in_campaign={}&{}", campaign_id, query))
+                            .body(Body::empty())
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+
+                assert_eq!(list_users_response.status(), StatusCode::OK);
+
+                let body = hyper::body::to_bytes(list_users_response.into_body())
+                    .await
+                    .unwrap();
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+                body
+            }
+        };
+
+        // Walk through the pages, newest user first
+
+        let page = list_users("limit=2".to_string()).await;
+
+        assert_eq!(page["total_count"], 3);
+        let users = page["users"].as_array().unwrap();
+        assert_eq!(users.len(), 2);
+        assert_eq!(users[0]["id"], user_ids[2]);
+        assert_eq!(users[1]["id"], user_ids[1]);
+        assert_eq!(page["next_cursor"], user_ids[1]);
+
+        let page = list_users(format!("limit=2&cursor={}", user_ids[1])).await;
+
+        assert_eq!(page["total_count"], 3);
+        let users = page["users"].as_array().unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0]["id"], user_ids[0]);
+        assert_eq!(page["next_cursor"], serde_json::Value::Null);
+
+        // Filter by verification and creation date
+
+        let page = list_users("verified=true".to_string()).await;
+
+        assert_eq!(page["total_count"], 1);
+        assert_eq!(page["users"][0]["id"], user_ids[0]);
+        assert!(page["users"][0]["phone_verified_at"].is_string());
+
+        let page = list_users("verified=false".to_string()).await;
+
+        assert_eq!(page["total_count"], 2);
+
+        let today_date = chrono::Utc::now().naive_utc().date();
+
+        let page = list_users(format!("created_from={}", today_date)).await;
+
+        assert_eq!(page["total_count"], 3);
+
+        let page = list_users(format!("created_to={}", today_date.pred_opt().unwrap())).await;
+
+        assert_eq!(page["total_count"], 0);
+        assert_eq!(page["users"].as_array().unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn look_up_by_phone_uses_an_index() {
+        let ctx = TestContext::new().await;
+        let mut conn = ctx.store.db_pool.acquire().await.unwrap();
+
+        // With sequential scans priced out, the plan only avoids one if an index can serve the
+        // lookup
+        sqlx::query("set enable_seqscan = off;")
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+
+        let plan: Vec<String> = sqlx::query_scalar(
+            r"
+                explain
+                select id
+                from users
+                where deleted_at is null
+                and phone_normalized = $1;
+            ",
+        )
+        .bind("+85212345678")
+        .fetch_all(&mut *conn)
+        .await
+        .unwrap();
+
+        assert!(
+            plan.iter()
+                .any(|line| line.contains("users_phone_normalized_key")),
+            "{plan:#?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn filter_by_creation_date_uses_an_index() {
+        let ctx = TestContext::new().await;
+        let repository = PgRepository::new(ctx.store.db_pool.clone(), ctx.store.metrics.clone());
+
+        // Users created on the last and first second of a day count towards that day only
+
+        sqlx::query!(
+            "--sql
+                insert into users (phone, created_at)
+                values ('+852 0000 0001', '2023-11-01T00:00:00Z'), ('+852 0000 0002', '2023-11-01T23:59:59Z');
+            "
+        )
+        .execute(&ctx.store.db_pool)
+        .await
+        .unwrap();
+
+        let date = |month, day| chrono::NaiveDate::from_ymd_opt(2023, month, day);
+
+        for (created_from, created_to, expected) in [
+            (date(11, 1), date(11, 1), 2),
+            (date(11, 2), None, 0),
+            (None, date(10, 31), 0),
+            (None, date(11, 1), 2),
+        ] {
+            let filter = UserFilter {
+                created_from,
+                created_to,
+                ..Default::default()
+            };
+
+            assert_eq!(
+                repository.count(&filter).await.unwrap(),
+                expected,
+                "{filter:?}"
+            );
+        }
+
+        let mut conn = ctx.store.db_pool.acquire().await.unwrap();
+
+        sqlx::query("set enable_seqscan = off;")
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+
+        let plan: Vec<String> = sqlx::query_scalar(
+            r"
+                explain
+                select id
+                from users
+                where ($1::timestamptz is null or created_at >= $1)
+                and ($2::timestamptz is null or created_at < $2);
+            ",
+        )
+        .bind(chrono::Utc::now())
+        .bind(chrono::Utc::now())
+        .fetch_all(&mut *conn)
+        .await
+        .unwrap();
+
+        assert!(
+            plan.iter()
+                .any(|line| line.contains("users_created_at_idx")),
+            "{plan:#?}"
+        );
     }
 }